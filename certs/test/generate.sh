@@ -0,0 +1,36 @@
+#!/bin/sh
+# Regenerates the CA and the server certificate used by the TLS tests.
+# The certificate is issued to sql.fake-adventure-works.test, not to 127.0.0.1,
+# so the tests can check the host name override.
+set -e
+cd "$(dirname "$0")"
+work=$(mktemp -d)
+
+openssl req -x509 -newkey rsa:2048 -nodes -days 36500 \
+    -subj "/CN=Tiberius Test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" \
+    -keyout "$work/ca-key.pem" -out ca.pem
+
+openssl req -newkey rsa:2048 -nodes \
+    -subj "/CN=sql.fake-adventure-works.test" \
+    -keyout "$work/server-key.pem" -out "$work/server.csr"
+
+printf '%s\n' \
+    "basicConstraints=critical,CA:FALSE" \
+    "keyUsage=critical,digitalSignature,keyEncipherment" \
+    "extendedKeyUsage=serverAuth" \
+    "subjectAltName=DNS:sql.fake-adventure-works.test" > "$work/server.ext"
+
+openssl x509 -req -days 36500 -in "$work/server.csr" \
+    -CA ca.pem -CAkey "$work/ca-key.pem" -CAserial "$work/ca.srl" -CAcreateserial \
+    -extfile "$work/server.ext" -out server.pem
+
+# The identity of the test server, its password is "tiberius"
+openssl pkcs12 -export -passout pass:tiberius \
+    -keypbe PBE-SHA1-3DES -certpbe PBE-SHA1-3DES -macalg sha1 \
+    -inkey "$work/server-key.pem" -in server.pem -certfile ca.pem -out server.p12
+
+openssl x509 -in server.pem -noout -fingerprint -sha256
+
+rm -rf "$work"
@@ -0,0 +1,36 @@
+-- Reports over the sales orders
+-- Run with the sqlcmd variable Schema, for instance: sqlcmd -v Schema=dbo
+
+:setvar ReportTitle "Sales order report"
+
+create or alter view [$(Schema)].[vSalesOrderStatus]
+as
+select SalesOrderID,
+       OrderDate,
+       Status,
+       [$(Schema)].ufnGetSalesOrderStatusText(Status) as StatusDescription,
+       TotalDue
+from [$(Schema)].SalesOrderHeader
+GO
+
+/*
+   The procedure below must be first in its batch,
+   this GO is inside a comment so it does not end the batch:
+GO
+*/
+create or alter procedure [$(Schema)].[uspGetSalesOrderReport]
+as
+begin
+    select N'$(ReportTitle)' as Title,
+           N'Rows separated by GO
+GO
+are not batches inside strings' as Note
+
+    select Status,
+           StatusDescription,
+           count(*)      as Orders,
+           sum(TotalDue) as TotalDue
+    from [$(Schema)].[vSalesOrderStatus]
+    group by Status, StatusDescription
+end
+GO
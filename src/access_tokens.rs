@@ -0,0 +1,504 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures_util::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tiberius::AuthMethod;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use url::{Position, Url};
+
+/// The scope of Azure SQL Database in the client credentials flow
+const AZURE_SQL_SCOPE: &str = "https://database.windows.net/.default";
+/// The resource of Azure SQL Database for managed identities
+const AZURE_SQL_RESOURCE: &str = "https://database.windows.net/";
+/// The instance metadata service of Azure virtual machines
+const IMDS_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A Microsoft Entra ID (Azure AD) access token.
+///
+/// `Debug` masks the token.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AccessToken {
+    pub token: String,
+    /// `None` when the token endpoint did not tell
+    pub expires_at: Option<Instant>,
+}
+
+impl AccessToken {
+    pub fn auth_method(&self) -> AuthMethod {
+        AuthMethod::aad_token(&self.token)
+    }
+
+    /// Whether it expires in less than `margin`
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at.saturating_duration_since(Instant::now()) <= margin)
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("token", &"********")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum TokenError {
+    /// The endpoint can't be reached
+    Request {
+        url: String,
+        message: String,
+    },
+    /// The endpoint answered with an error status
+    Status {
+        url: String,
+        status: u16,
+        message: String,
+    },
+    InvalidResponse {
+        url: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Request { url, message } => {
+                write!(f, "Cannot request a token from {}: {}", url, message)
+            }
+            TokenError::Status {
+                url,
+                status,
+                message,
+            } => write!(f, "{} answered {}: {}", url, status, message),
+            TokenError::InvalidResponse { url, message } => {
+                write!(f, "{} did not answer a token: {}", url, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+pub type TokenFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<AccessToken, TokenError>> + Send + 'a>>;
+
+/// Where access tokens come from, to log in with Microsoft Entra ID.
+pub trait TokenProvider: Send + Sync {
+    fn token(&self) -> TokenFuture<'_>;
+
+    /// Forgets a cached token, called when the server rejects it.
+    fn invalidate(&self) {}
+}
+
+/// A token obtained elsewhere, `az account get-access-token` for instance.
+#[derive(Debug, Clone)]
+pub struct StaticToken {
+    token: AccessToken,
+}
+
+impl StaticToken {
+    pub fn new(token: &str) -> Self {
+        StaticToken {
+            token: AccessToken {
+                token: token.to_owned(),
+                expires_at: None,
+            },
+        }
+    }
+}
+
+impl TokenProvider for StaticToken {
+    fn token(&self) -> TokenFuture<'_> {
+        Box::pin(async move { Ok(self.token.clone()) })
+    }
+}
+
+/// The OAuth 2.0 client credentials flow of a service principal (an app registration).
+#[derive(Clone)]
+pub struct ClientCredentialsToken {
+    endpoint: String,
+    client_id: String,
+    client_secret: String,
+    scope: String,
+}
+
+impl ClientCredentialsToken {
+    pub fn new(tenant_id: &str, client_id: &str, client_secret: &str) -> Self {
+        ClientCredentialsToken {
+            endpoint: format!(
+                "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                tenant_id
+            ),
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            scope: AZURE_SQL_SCOPE.to_owned(),
+        }
+    }
+
+    /// Another token endpoint, a sovereign cloud or a stand-in for tests
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.to_owned();
+        self
+    }
+
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scope = scope.to_owned();
+        self
+    }
+}
+
+impl fmt::Debug for ClientCredentialsToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientCredentialsToken")
+            .field("endpoint", &self.endpoint)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"********")
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+impl TokenProvider for ClientCredentialsToken {
+    fn token(&self) -> TokenFuture<'_> {
+        Box::pin(async move {
+            let form = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("grant_type", "client_credentials")
+                .append_pair("client_id", &self.client_id)
+                .append_pair("client_secret", &self.client_secret)
+                .append_pair("scope", &self.scope)
+                .finish();
+
+            let body = http_request("POST", &self.endpoint, &[], Some(&form)).await?;
+
+            parse_token(&self.endpoint, &body)
+        })
+    }
+}
+
+/// The managed identity of the Azure resource the program runs on.
+#[derive(Clone)]
+pub struct ManagedIdentityToken {
+    endpoint: String,
+    /// `X-IDENTITY-HEADER` of App Service, the metadata service of virtual machines has none
+    identity_header: Option<String>,
+    client_id: Option<String>,
+    resource: String,
+}
+
+impl ManagedIdentityToken {
+    /// The metadata service of Azure virtual machines
+    pub fn new() -> Self {
+        ManagedIdentityToken {
+            endpoint: IMDS_ENDPOINT.to_owned(),
+            identity_header: None,
+            client_id: None,
+            resource: AZURE_SQL_RESOURCE.to_owned(),
+        }
+    }
+
+    /// The endpoint App Service and Azure Functions give in `IDENTITY_ENDPOINT`
+    /// and `IDENTITY_HEADER`, or the metadata service of virtual machines.
+    pub fn from_environment() -> Self {
+        match (
+            std::env::var("IDENTITY_ENDPOINT"),
+            std::env::var("IDENTITY_HEADER"),
+        ) {
+            (Ok(endpoint), Ok(header)) => ManagedIdentityToken {
+                endpoint,
+                identity_header: Some(header),
+                ..ManagedIdentityToken::new()
+            },
+            _ => ManagedIdentityToken::new(),
+        }
+    }
+
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.to_owned();
+        self
+    }
+
+    /// A user-assigned identity, the system-assigned one is used otherwise
+    pub fn client_id(mut self, client_id: &str) -> Self {
+        self.client_id = Some(client_id.to_owned());
+        self
+    }
+}
+
+impl Default for ManagedIdentityToken {
+    fn default() -> Self {
+        ManagedIdentityToken::new()
+    }
+}
+
+impl fmt::Debug for ManagedIdentityToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManagedIdentityToken")
+            .field("endpoint", &self.endpoint)
+            .field(
+                "identity_header",
+                &self.identity_header.as_ref().map(|_| "********"),
+            )
+            .field("client_id", &self.client_id)
+            .field("resource", &self.resource)
+            .finish()
+    }
+}
+
+impl TokenProvider for ManagedIdentityToken {
+    fn token(&self) -> TokenFuture<'_> {
+        Box::pin(async move {
+            let (api_version, header) = match &self.identity_header {
+                Some(value) => ("2019-08-01", ("X-IDENTITY-HEADER", value.as_str())),
+                None => ("2018-02-01", ("Metadata", "true")),
+            };
+
+            let mut url = Url::parse(&self.endpoint).map_err(|e| TokenError::Request {
+                url: self.endpoint.clone(),
+                message: e.to_string(),
+            })?;
+            url.query_pairs_mut()
+                .append_pair("api-version", api_version)
+                .append_pair("resource", &self.resource);
+            if let Some(client_id) = &self.client_id {
+                url.query_pairs_mut().append_pair("client_id", client_id);
+            }
+
+            let body = http_request("GET", url.as_str(), &[header], None).await?;
+
+            parse_token(&self.endpoint, &body)
+        })
+    }
+}
+
+/// Keeps the token of another provider and asks for a new one
+/// when it is about to expire, `refresh_before` its expiry.
+pub struct CachedToken<P> {
+    provider: P,
+    refresh_before: Duration,
+    cached: Mutex<Option<AccessToken>>,
+}
+
+impl<P: TokenProvider> CachedToken<P> {
+    pub fn new(provider: P, refresh_before: Duration) -> Self {
+        CachedToken {
+            provider,
+            refresh_before,
+            cached: Mutex::new(None),
+        }
+    }
+}
+
+impl<P: TokenProvider> TokenProvider for CachedToken<P> {
+    fn token(&self) -> TokenFuture<'_> {
+        Box::pin(async move {
+            // Held while the provider runs, concurrent callers wait for one token
+            let mut cached = self.cached.lock().await;
+            if let Some(token) = cached.as_ref() {
+                if !token.expires_within(self.refresh_before) {
+                    return Ok(token.clone());
+                }
+            }
+
+            let token = self.provider.token().await?;
+            *cached = Some(token.clone());
+
+            Ok(token)
+        })
+    }
+
+    fn invalidate(&self) {
+        if let Ok(mut cached) = self.cached.try_lock() {
+            *cached = None;
+        }
+        self.provider.invalidate();
+    }
+}
+
+// `access_token` with `expires_in` (seconds from now) or `expires_on` (seconds since 1970),
+// managed identities give them as strings
+fn parse_token(url: &str, body: &str) -> Result<AccessToken, TokenError> {
+    let invalid = |message: &str| TokenError::InvalidResponse {
+        url: url.to_owned(),
+        message: message.to_owned(),
+    };
+
+    let value: serde_json::Value = serde_json::from_str(body).map_err(|_| invalid("not JSON"))?;
+    let token = value
+        .get("access_token")
+        .and_then(|token| token.as_str())
+        .ok_or_else(|| invalid("no access_token"))?;
+    let seconds = |key: &str| {
+        value
+            .get(key)
+            .and_then(|v| v.as_u64().or_else(|| v.as_str()?.parse().ok()))
+    };
+
+    let expires_in = match (seconds("expires_in"), seconds("expires_on")) {
+        (Some(expires_in), _) => Some(expires_in),
+        (None, Some(expires_on)) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or(0);
+            Some(expires_on.saturating_sub(now))
+        }
+        (None, None) => None,
+    };
+
+    Ok(AccessToken {
+        token: token.to_owned(),
+        expires_at: expires_in.map(|seconds| Instant::now() + Duration::from_secs(seconds)),
+    })
+}
+
+// A single HTTP/1.1 request on its own connection, over TLS for https URLs.
+// It's enough for the token endpoints, not a general client: redirects are not followed,
+// they fail as any status other than 2xx, and the response is read until the server
+// closes the connection, as `Connection: close` asks, within REQUEST_TIMEOUT.
+async fn http_request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    form: Option<&str>,
+) -> Result<String, TokenError> {
+    let request_error = |message: String| TokenError::Request {
+        url: url.to_owned(),
+        message,
+    };
+
+    let parsed = Url::parse(url).map_err(|e| request_error(e.to_string()))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| request_error("no host".to_owned()))?;
+    let port = parsed
+        .port_or_known_default()
+        .ok_or_else(|| request_error("no port".to_owned()))?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nAccept: application/json\r\nConnection: close\r\n",
+        method,
+        &parsed[Position::BeforePath..Position::AfterQuery],
+        &parsed[Position::BeforeHost..Position::AfterPort]
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(form) = form {
+        request.push_str(&format!(
+            "Content-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+            form.len(),
+            form
+        ));
+    } else {
+        request.push_str("\r\n");
+    }
+
+    let exchange = async {
+        let tcp = TcpStream::connect((host, port)).await?.compat();
+
+        if parsed.scheme() == "https" {
+            let tls = async_native_tls::connect(host, tcp)
+                .await
+                .map_err(std::io::Error::other)?;
+            send(tls, &request).await
+        } else {
+            send(tcp, &request).await
+        }
+    };
+    let response = timeout(REQUEST_TIMEOUT, exchange)
+        .await
+        .map_err(|_| request_error("timed out".to_owned()))?
+        .map_err(|e| request_error(e.to_string()))?;
+
+    let (status, body) = parse_response(&response).ok_or_else(|| TokenError::InvalidResponse {
+        url: url.to_owned(),
+        message: "not an HTTP response".to_owned(),
+    })?;
+
+    if !(200..300).contains(&status) {
+        // Entra ID explains errors in error_description
+        let message = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|value| {
+                value
+                    .get("error_description")
+                    .or_else(|| value.get("error"))
+                    .and_then(|message| message.as_str().map(str::to_owned))
+            })
+            .unwrap_or_else(|| body.chars().take(200).collect());
+
+        return Err(TokenError::Status {
+            url: url.to_owned(),
+            status,
+            message,
+        });
+    }
+
+    Ok(body)
+}
+
+async fn send<S>(mut stream: S, request: &str) -> std::io::Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    Ok(response)
+}
+
+// The status and the body, the connection is closed after the response
+// so the body is the rest unless it is chunked. The chunks are decoded as bytes,
+// a character may be split between two of them.
+pub(crate) fn parse_response(response: &[u8]) -> Option<(u16, String)> {
+    let end_of_head = find(response, b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&response[..end_of_head]).ok()?;
+    let body = &response[end_of_head + 4..];
+    let mut lines = head.split("\r\n");
+
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+
+    if !chunked {
+        return Some((status, String::from_utf8_lossy(body).into_owned()));
+    }
+
+    let mut rest = body;
+    let mut decoded = Vec::new();
+    loop {
+        let end_of_size = find(rest, b"\r\n")?;
+        let size = std::str::from_utf8(&rest[..end_of_size]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        if size == 0 {
+            return Some((status, String::from_utf8_lossy(&decoded).into_owned()));
+        }
+        let after = &rest[end_of_size + 2..];
+        decoded.extend_from_slice(after.get(..size)?);
+        rest = after.get(size..)?.strip_prefix(b"\r\n")?;
+    }
+}
+
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+}
@@ -0,0 +1,88 @@
+//! Generates typed Rust wrappers of procedures and functions.
+//!
+//! Take a snapshot of the signatures (it needs a database):
+//!
+//! ```text
+//! cargo run --bin codegen -- snapshot "<ADO.NET connection string>" schema/sales_order_routines.json [routine...]
+//! ```
+//!
+//! Generate the wrappers from the snapshot (it does not need a database):
+//!
+//! ```text
+//! cargo run --bin codegen -- generate schema/sales_order_routines.json src/sales_order_routines.rs
+//! ```
+
+use std::path::Path;
+
+use tiberius::{Client, Config, SqlBrowser};
+use tiberius_tokio_sql_server::{
+    generate_from_snapshot_file, sales_order_routine_names, snapshot_routines, ObjectName,
+    SchemaSnapshot,
+};
+use tokio::net::TcpStream;
+use tokio_util::compat::TokioAsyncWriteCompatExt;
+
+const USAGE: &str = "Usage:
+    codegen snapshot <connection string> <snapshot.json> [routine...]
+    codegen generate <snapshot.json> <output.rs>";
+
+async fn snapshot(
+    connection_string: &str,
+    path: &str,
+    routines: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let names = if routines.is_empty() {
+        sales_order_routine_names()?
+    } else {
+        routines
+            .iter()
+            .map(|name| ObjectName::parse(name))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let config = Config::from_ado_string(connection_string)?;
+    let tcp = TcpStream::connect_named(&config).await?;
+    let mut client = Client::connect(config, tcp.compat_write()).await?;
+
+    let mut snapshot = snapshot_routines(&mut client, &names).await?;
+    client.close().await?;
+
+    // Result columns written by hand survive a new snapshot
+    if Path::new(path).exists() {
+        let previous = SchemaSnapshot::from_json(&std::fs::read_to_string(path)?)?;
+        snapshot.keep_result_columns(&previous);
+    }
+
+    for routine in &snapshot.routines {
+        if let Some(error) = &routine.result_error {
+            eprintln!(
+                "The result of {} is unknown, write its columns in {}: {}",
+                routine.name, path, error
+            );
+        }
+    }
+
+    std::fs::write(path, snapshot.to_json()?)?;
+    println!("Snapshot of {} routines written to {}", names.len(), path);
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("snapshot") if args.len() >= 3 => snapshot(&args[1], &args[2], &args[3..]).await?,
+        Some("generate") if args.len() == 3 => {
+            generate_from_snapshot_file(&args[1], &args[2])?;
+            println!("Wrappers written to {}", args[2]);
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    Ok(())
+}
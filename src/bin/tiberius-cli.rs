@@ -0,0 +1,622 @@
+//! Runs queries and scripts against SQL Server, like sqlcmd.
+//!
+//! ```text
+//! cargo run --bin tiberius-cli -- -S 127.0.0.1,22828 -d FakeAdventureWorks -U developer -P developer -C -Q "select @@version"
+//! cargo run --bin tiberius-cli -- --ado "Server=tcp:127.0.0.1\SQL2022D;..." -i sql/sales_order_reports.sql -v Schema=dbo --format csv
+//! ```
+//!
+//! Without `-Q` or `-i` it starts an interactive shell, `\?` lists its commands.
+//! Line editing, completion and Ctrl-C to cancel a batch need a Unix terminal,
+//! elsewhere lines are read as they are typed.
+//!
+//! Exit codes: 0 when everything ran, 1 when the server rejected a batch
+//! or the script is not valid, 2 for wrong arguments and 3 when it cannot connect.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{IsTerminal, Read, Write};
+use std::process::ExitCode;
+use std::time::Instant;
+
+use serde_json::Value;
+use tiberius::{AuthMethod, Client};
+use tiberius_tokio_sql_server::{
+    connect, describe_table, format_result_set, list_tables, prepare_batch, query_result_sets,
+    split_batches, table_description, Batch, Completer, ConnectionSpec, EditorEvent, History, Key,
+    KeyDecoder, LineEditor, MetaCommand, ObjectName, OutputFormat, ResultSet, ScriptError,
+    ServerConfig, ShellCommand, ShellInput, TlsSettings, SHELL_HELP,
+};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_util::compat::Compat;
+
+const USAGE: &str = "Usage:
+    tiberius-cli [connection] [-Q <query> | -i <script.sql>] [-v name=value]... [--format table|csv|json|expanded]
+
+Without -Q or -i it starts an interactive shell.
+
+Connection:
+    -S <server>          host, host,port or host\\instance (default 127.0.0.1)
+    -d <database>
+    -U <user> -P <password>
+                         SQL Server authentication, integrated security otherwise (Windows only)
+    -N <mode>            encryption: off, on, required (default) or DANGER_PLAINTEXT
+    -F <name>            name the server certificate is issued to, when it is not the host
+    --ca <file>          certificate of the CA that signs the server certificate (.pem, .crt or .der)
+    -C                   trust any server certificate, for a development server only
+    --ado <string>       ADO.NET connection string, instead of the options above
+    --jdbc <string>      JDBC connection string, instead of the options above
+    --url <string>       sqlserver:// URL, instead of the options above";
+
+#[derive(Debug, Default)]
+struct Arguments {
+    server: Option<String>,
+    database: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    tls: TlsSettings,
+    ado: Option<String>,
+    jdbc: Option<String>,
+    url: Option<String>,
+    query: Option<String>,
+    input: Option<String>,
+    variables: HashMap<String, String>,
+    format: Option<OutputFormat>,
+}
+
+fn parse_arguments(args: &[String]) -> Result<Arguments, String> {
+    let mut arguments = Arguments::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+
+        match arg.as_str() {
+            "-S" => arguments.server = Some(value()?),
+            "-d" => arguments.database = Some(value()?),
+            "-U" => arguments.user = Some(value()?),
+            "-P" => arguments.password = Some(value()?),
+            "-N" => arguments.tls.encryption = value()?.parse().map_err(|e| format!("{}", e))?,
+            "-F" => arguments.tls.server_name = Some(value()?),
+            "--ca" => arguments.tls.ca_bundle = Some(value()?.into()),
+            "-C" => arguments.tls.trust_server_certificate = true,
+            "--ado" => arguments.ado = Some(value()?),
+            "--jdbc" => arguments.jdbc = Some(value()?),
+            "--url" => arguments.url = Some(value()?),
+            "-Q" => arguments.query = Some(value()?),
+            "-i" => arguments.input = Some(value()?),
+            "-v" => {
+                let variable = value()?;
+                let (name, value) = variable
+                    .split_once('=')
+                    .ok_or_else(|| format!("-v {} is not name=value", variable))?;
+                arguments
+                    .variables
+                    .insert(name.to_owned(), value.to_owned());
+            }
+            "--format" => arguments.format = Some(value()?.parse()?),
+            other => return Err(format!("Unknown argument {}", other)),
+        }
+    }
+
+    if arguments.query.is_some() && arguments.input.is_some() {
+        return Err("Use either -Q or -i".to_owned());
+    }
+
+    Ok(arguments)
+}
+
+fn connection_spec(arguments: &Arguments) -> Result<ConnectionSpec, Box<dyn std::error::Error>> {
+    if let Some(ado) = &arguments.ado {
+        return Ok(ConnectionSpec::Ado(ado.clone()));
+    }
+    if let Some(jdbc) = &arguments.jdbc {
+        return Ok(ConnectionSpec::Jdbc(jdbc.clone()));
+    }
+    if let Some(url) = &arguments.url {
+        return Ok(ConnectionSpec::Url(url.clone()));
+    }
+
+    let authentication = match (&arguments.user, &arguments.password) {
+        (Some(user), Some(password)) => AuthMethod::sql_server(user, password),
+        (Some(_), None) => return Err("-U needs -P".into()),
+        #[cfg(windows)]
+        _ => AuthMethod::Integrated,
+        #[cfg(not(windows))]
+        _ => return Err("Integrated security needs Windows, use -U and -P".into()),
+    };
+
+    let server = arguments.server.as_deref().unwrap_or("127.0.0.1");
+    let server = server.strip_prefix("tcp:").unwrap_or(server);
+    let mut config = ServerConfig::new(server, authentication);
+
+    if let Some((host, instance)) = server.split_once('\\') {
+        // The port of SQL Server Browser, it tells the port of the instance
+        config.host = host.to_owned();
+        config.port = Some(1434);
+        config.instance_name = Some(instance.to_owned());
+    } else if let Some((host, port)) = server.split_once(',') {
+        config.host = host.to_owned();
+        config.port = Some(port.trim().parse()?);
+    }
+
+    config.database = arguments.database.clone();
+    config.tls = arguments.tls.clone();
+
+    Ok(config.into())
+}
+
+async fn run(
+    client: &mut Client<Compat<TcpStream>>,
+    script: &str,
+    variables: &HashMap<String, String>,
+    format: OutputFormat,
+) -> Result<(), ScriptError> {
+    let mut variables = variables.clone();
+    let mut first = true;
+
+    for (index, mut batch) in split_batches(script)?.into_iter().enumerate() {
+        let sql = match prepare_batch(&mut batch, &mut variables)? {
+            Some(sql) => sql,
+            None => continue,
+        };
+
+        for _ in 0..batch.repeat {
+            let result_sets = query_result_sets(client, &sql)
+                .await
+                .map_err(|e| ScriptError::batch_failed(index + 1, &batch, e))?;
+
+            print_result_sets(&result_sets, format, &mut first);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_result_sets(result_sets: &[ResultSet], format: OutputFormat, first: &mut bool) {
+    for result_set in result_sets {
+        if !*first {
+            println!();
+        }
+        print!("{}", format_result_set(result_set, format));
+        *first = false;
+    }
+}
+
+fn print_error(error: &ScriptError) {
+    match error {
+        // The same layout as sqlcmd, with the line in the script
+        ScriptError::Batch {
+            line,
+            source: tiberius::error::Error::Server(token),
+            ..
+        } => eprintln!(
+            "Msg {}, Level {}, State {}, Line {}\n{}",
+            token.code(),
+            token.class(),
+            token.state(),
+            line,
+            token.message()
+        ),
+        other => eprintln!("{}", other),
+    }
+}
+
+#[cfg(unix)]
+mod raw_mode {
+    /// Reads the keys one by one, without echo, until it's dropped.
+    pub struct RawMode {
+        original: libc::termios,
+    }
+
+    impl RawMode {
+        /// `None` when the input is not a terminal
+        pub fn enable() -> Option<Self> {
+            unsafe {
+                if libc::isatty(libc::STDIN_FILENO) != 1 {
+                    return None;
+                }
+
+                let mut original: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                    return None;
+                }
+
+                // Ctrl-C is read as a key instead of a signal. Output processing is kept,
+                // so `\n` still goes back to the start of the line
+                let mut raw = original;
+                raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+                raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+                raw.c_cc[libc::VMIN] = 1;
+                raw.c_cc[libc::VTIME] = 0;
+
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                    return None;
+                }
+
+                Some(RawMode { original })
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+            }
+        }
+    }
+}
+
+enum Input {
+    Line(String),
+    Interrupt,
+    EndOfFile,
+}
+
+// Reading stdin blocks, so a thread reads it and sends what it gets
+struct Terminal {
+    bytes: mpsc::UnboundedReceiver<Vec<u8>>,
+    decoder: KeyDecoder,
+    keys: VecDeque<Key>,
+    // What was read and is not a line yet, when the terminal is not in raw mode
+    text: Vec<u8>,
+    interactive: bool,
+    #[cfg(unix)]
+    raw_mode: Option<raw_mode::RawMode>,
+}
+
+impl Terminal {
+    fn new() -> Self {
+        let (sender, bytes) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buffer = [0u8; 256];
+            while let Ok(n) = stdin.read(&mut buffer) {
+                if n == 0 || sender.send(buffer[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Terminal {
+            bytes,
+            decoder: KeyDecoder::default(),
+            keys: VecDeque::new(),
+            text: Vec::new(),
+            interactive: std::io::stdin().is_terminal(),
+            #[cfg(unix)]
+            raw_mode: raw_mode::RawMode::enable(),
+        }
+    }
+
+    fn is_raw(&self) -> bool {
+        #[cfg(unix)]
+        return self.raw_mode.is_some();
+        #[cfg(not(unix))]
+        return false;
+    }
+
+    async fn next_key(&mut self) -> Option<Key> {
+        loop {
+            if let Some(key) = self.keys.pop_front() {
+                return Some(key);
+            }
+            let bytes = self.bytes.recv().await?;
+            self.keys.extend(self.decoder.decode(&bytes));
+        }
+    }
+
+    async fn read_line(&mut self, prompt: &str, history: &History, completer: &Completer) -> Input {
+        if !self.is_raw() {
+            return self.read_plain_line(prompt).await;
+        }
+
+        let mut editor = LineEditor::new();
+        draw(prompt, &editor);
+
+        loop {
+            let Some(key) = self.next_key().await else {
+                return Input::EndOfFile;
+            };
+
+            match editor.key(key, history, completer) {
+                EditorEvent::Redraw => draw(prompt, &editor),
+                EditorEvent::Line(line) => {
+                    println!();
+                    return Input::Line(line);
+                }
+                EditorEvent::Interrupt => {
+                    println!("^C");
+                    return Input::Interrupt;
+                }
+                EditorEvent::EndOfFile => {
+                    println!();
+                    return Input::EndOfFile;
+                }
+                EditorEvent::Candidates(names) => {
+                    println!("\n{}", names.join("  "));
+                    draw(prompt, &editor);
+                }
+            }
+        }
+    }
+
+    async fn read_plain_line(&mut self, prompt: &str) -> Input {
+        if self.interactive {
+            print!("{}", prompt);
+            let _ = std::io::stdout().flush();
+        }
+
+        loop {
+            if let Some(end) = self.text.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.text.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                return Input::Line(line.trim_end_matches(['\r', '\n']).to_owned());
+            }
+
+            match self.bytes.recv().await {
+                Some(bytes) => self.text.extend(bytes),
+                None if self.text.is_empty() => return Input::EndOfFile,
+                // The last line has no line break
+                None => self.text.push(b'\n'),
+            }
+        }
+    }
+
+    /// Waits for Ctrl-C, the other keys typed while a batch runs are dropped.
+    async fn interrupted(&mut self) {
+        if self.is_raw() {
+            while let Some(key) = self.next_key().await {
+                if key == Key::Interrupt {
+                    return;
+                }
+            }
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+fn draw(prompt: &str, editor: &LineEditor) {
+    let mut stdout = std::io::stdout();
+    let column = prompt.chars().count() + editor.cursor_column();
+
+    let _ = write!(stdout, "\r\x1b[K{}{}\r", prompt, editor.buffer());
+    if column > 0 {
+        let _ = write!(stdout, "\x1b[{}C", column);
+    }
+    let _ = stdout.flush();
+}
+
+const ROUTINES: &str = r#"
+select schema_name(schema_id) as [Schema], name as Name, type_desc as Type
+from sys.objects
+where type in ('P', 'FN', 'IF', 'TF')
+  and is_ms_shipped = 0
+order by [Schema], Name
+"#;
+
+async fn describe(
+    client: &mut Client<Compat<TcpStream>>,
+    command: MetaCommand,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let result_set = match command {
+        MetaCommand::Describe(Some(name)) => {
+            let name = ObjectName::parse(&name)?;
+            match describe_table(client, &name).await? {
+                Some(table) => table_description(&table),
+                None => return Err(format!("There is no table {}", name).into()),
+            }
+        }
+        MetaCommand::Describe(None) => ResultSet {
+            columns: vec!["Table".to_owned()],
+            rows: list_tables(client)
+                .await?
+                .iter()
+                .map(|table| vec![Value::from(table.to_string())])
+                .collect(),
+        },
+        MetaCommand::ListRoutines => query_result_sets(client, ROUTINES)
+            .await?
+            .pop()
+            .unwrap_or_default(),
+        _ => return Ok(()),
+    };
+
+    print!("{}", format_result_set(&result_set, format));
+
+    Ok(())
+}
+
+async fn execute(
+    client: &mut Client<Compat<TcpStream>>,
+    batch: &Batch,
+    format: OutputFormat,
+) -> tiberius::Result<()> {
+    let mut first = true;
+    for _ in 0..batch.repeat {
+        let result_sets = query_result_sets(client, &batch.sql).await?;
+        print_result_sets(&result_sets, format, &mut first);
+    }
+
+    Ok(())
+}
+
+async fn shell(
+    spec: ConnectionSpec,
+    mut client: Client<Compat<TcpStream>>,
+    format: OutputFormat,
+) -> Result<Client<Compat<TcpStream>>, Box<dyn std::error::Error>> {
+    let completer = Completer::from_catalog(&mut client)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Names will not be completed: {}", e);
+            Completer::default()
+        });
+    let mut history = match History::default_path().map(History::load) {
+        Some(Ok(history)) => history,
+        Some(Err(e)) => {
+            eprintln!("The history is not kept: {}", e);
+            History::default()
+        }
+        None => History::default(),
+    };
+
+    let mut terminal = Terminal::new();
+    let mut input = ShellInput::new();
+    let mut timing = false;
+    let mut expanded = false;
+
+    loop {
+        let prompt = format!("{}> ", input.lines() + 1);
+        let line = match terminal.read_line(&prompt, &history, &completer).await {
+            Input::Line(line) => line,
+            Input::Interrupt => {
+                input.clear();
+                continue;
+            }
+            Input::EndOfFile => break,
+        };
+
+        if let Err(e) = history.add(&line) {
+            eprintln!("The line is not kept in the history: {}", e);
+        }
+
+        let command = match input.push_line(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("{}", e);
+                input.clear();
+                continue;
+            }
+        };
+
+        let format = if expanded {
+            OutputFormat::Expanded
+        } else {
+            format
+        };
+        let on_off = |on: bool| if on { "on" } else { "off" };
+
+        match command {
+            ShellCommand::Meta(MetaCommand::Quit) => break,
+            ShellCommand::Meta(MetaCommand::Help) => println!("{}", SHELL_HELP),
+            ShellCommand::Meta(MetaCommand::Timing) => {
+                timing = !timing;
+                println!("Timing is {}", on_off(timing));
+            }
+            ShellCommand::Meta(MetaCommand::Expanded) => {
+                expanded = !expanded;
+                println!("Expanded display is {}", on_off(expanded));
+            }
+            ShellCommand::Meta(command) => {
+                if let Err(e) = describe(&mut client, command, format).await {
+                    eprintln!("{}", e);
+                }
+            }
+            ShellCommand::Execute { sql, repeat } => {
+                let batch = Batch {
+                    sql,
+                    start_line: 1,
+                    repeat,
+                };
+                let started = Instant::now();
+
+                let result = tokio::select! {
+                    result = execute(&mut client, &batch, format) => Some(result),
+                    _ = terminal.interrupted() => None,
+                };
+
+                match result {
+                    Some(Ok(())) => {}
+                    Some(Err(e)) => print_error(&ScriptError::batch_failed(1, &batch, e)),
+                    None => {
+                        // Tiberius can't send an attention to the server, so the connection
+                        // is closed and the server stops the batch when it finds out.
+                        // The session settings and temporary tables are lost with it
+                        println!("Cancelled, connecting again");
+                        client = connect(spec.clone()).await?;
+                    }
+                }
+
+                if timing {
+                    println!("Time: {:.3} ms", started.elapsed().as_secs_f64() * 1000.0);
+                }
+            }
+        }
+    }
+
+    Ok(client)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let arguments = match parse_arguments(&args) {
+        Ok(arguments) => arguments,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let script = match (&arguments.query, &arguments.input) {
+        (Some(query), _) => Some(query.clone()),
+        (_, Some(path)) => match std::fs::read_to_string(path) {
+            Ok(script) => Some(script),
+            Err(e) => {
+                eprintln!("Cannot read {}: {}", path, e);
+                return ExitCode::from(2);
+            }
+        },
+        _ => None,
+    };
+
+    let spec = match connection_spec(&arguments) {
+        Ok(spec) => spec,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut client = match connect(spec.clone()).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Cannot connect to SQL Server ({}): {}", spec, e);
+            return ExitCode::from(3);
+        }
+    };
+
+    let format = arguments.format.unwrap_or(OutputFormat::Table);
+
+    let Some(script) = script else {
+        return match shell(spec, client, format).await {
+            Ok(client) => {
+                let _ = client.close().await;
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("Cannot connect to SQL Server: {}", e);
+                ExitCode::from(3)
+            }
+        };
+    };
+
+    let result = run(&mut client, &script, &arguments.variables, format).await;
+    let _ = client.close().await;
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            print_error(&e);
+            ExitCode::from(1)
+        }
+    }
+}
@@ -0,0 +1,693 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tiberius::Client;
+use tokio::net::TcpStream;
+use tokio_util::compat::Compat;
+
+use crate::connections::connect_to_local_server;
+use crate::identifiers::quote;
+use crate::{
+    describe_routine, DataType, InvalidIdentifier, ObjectKind, ObjectName, Parameter,
+    ParameterDirection, ResultColumn, Signature,
+};
+
+// Type of the client taken by the generated functions
+const CLIENT_TYPE: &str = "tiberius::Client<tokio_util::compat::Compat<tokio::net::TcpStream>>";
+
+/// Signatures kept in a file, so the wrappers can be generated without a database.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaSnapshot {
+    pub routines: Vec<Signature>,
+}
+
+impl SchemaSnapshot {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        Ok(json)
+    }
+
+    /// Keeps the result columns written by hand in `previous`
+    /// for the routines the server can't describe.
+    pub fn keep_result_columns(&mut self, previous: &SchemaSnapshot) {
+        for routine in &mut self.routines {
+            if routine.result_error.is_none() {
+                continue;
+            }
+
+            let described = previous
+                .routines
+                .iter()
+                .find(|r| r.name == routine.name && r.result_error.is_none());
+            if let Some(described) = described {
+                routine.result_columns = described.result_columns.clone();
+                routine.result_error = None;
+            }
+        }
+    }
+}
+
+/// The procedures and functions of the examples that have generated wrappers
+pub fn sales_order_routine_names() -> Result<Vec<ObjectName>, InvalidIdentifier> {
+    [
+        "uspSaveOrderHeader",
+        "uspUpdateOrderStatus",
+        "uspGetSaleOrderByID",
+        "ufnGetSalesOrderStatusText",
+        "ufnGetSalesOrderWithTotalDueMoreThan",
+    ]
+    .iter()
+    .map(|name| ObjectName::new("dbo", name))
+    .collect()
+}
+
+/// Reads the signatures of `names` into a snapshot.
+pub async fn snapshot_routines(
+    client: &mut Client<Compat<TcpStream>>,
+    names: &[ObjectName],
+) -> Result<SchemaSnapshot, Box<dyn std::error::Error>> {
+    let mut snapshot = SchemaSnapshot::default();
+
+    for name in names {
+        let signature = describe_routine(client, name)
+            .await?
+            .ok_or_else(|| format!("{} is not a procedure or function", name))?;
+        snapshot.routines.push(signature);
+    }
+
+    Ok(snapshot)
+}
+
+// How a SQL Server type is written in the generated code
+struct RustType {
+    /// Type of fields and output values
+    owned: &'static str,
+    /// Type of the arguments
+    argument: &'static str,
+    /// Type read from the row
+    read: &'static str,
+    /// Turns the read value into the owned one
+    to_owned: Option<&'static str>,
+}
+
+impl RustType {
+    fn copied(name: &'static str) -> Self {
+        RustType {
+            owned: name,
+            argument: name,
+            read: name,
+            to_owned: None,
+        }
+    }
+}
+
+fn rust_type(data_type: &DataType) -> Result<RustType, String> {
+    Ok(match data_type.name.as_str() {
+        "bit" => RustType::copied("bool"),
+        "tinyint" => RustType::copied("u8"),
+        "smallint" => RustType::copied("i16"),
+        "int" => RustType::copied("i32"),
+        "bigint" => RustType::copied("i64"),
+        "real" => RustType::copied("f32"),
+        "float" | "money" | "smallmoney" => RustType::copied("f64"),
+        "decimal" | "numeric" => RustType::copied("tiberius::numeric::Numeric"),
+        "uniqueidentifier" => RustType::copied("uuid::Uuid"),
+        "date" => RustType::copied("chrono::NaiveDate"),
+        "time" => RustType::copied("chrono::NaiveTime"),
+        "datetime" | "datetime2" | "smalldatetime" => RustType::copied("chrono::NaiveDateTime"),
+        "datetimeoffset" => RustType::copied("chrono::DateTime<chrono::FixedOffset>"),
+        "char" | "varchar" | "nchar" | "nvarchar" | "text" | "ntext" | "xml" => RustType {
+            owned: "String",
+            argument: "&str",
+            read: "&str",
+            to_owned: Some(".map(str::to_owned)"),
+        },
+        "binary" | "varbinary" | "image" => RustType {
+            owned: "Vec<u8>",
+            argument: "&[u8]",
+            read: "&[u8]",
+            to_owned: Some(".map(<[u8]>::to_vec)"),
+        },
+        other => return Err(format!("The type {} has no Rust type", other)),
+    })
+}
+
+const RUST_KEYWORDS: [&str; 38] = [
+    "as", "async", "await", "box", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+    "mut", "pub", "ref", "return", "static", "struct", "super", "trait", "true", "type", "unsafe",
+    "use", "where", "while", "yield",
+];
+
+// Names of the locals of the generated functions, arguments can't use them
+const RESERVED_NAMES: [&str; 6] = ["client", "sql", "query", "results", "row", "rows"];
+
+// `uspGetSaleOrderByID` -> `usp_get_sale_order_by_id`
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.trim_start_matches('@').chars().collect();
+    let mut snake = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !snake.is_empty() && !snake.ends_with('_') {
+                snake.push('_');
+            }
+            continue;
+        }
+
+        if c.is_uppercase() {
+            let previous = i.checked_sub(1).map(|j| chars[j]);
+            let next = chars.get(i + 1);
+            // A new word starts after a lowercase letter or at the last capital of an acronym
+            let starts_word = match previous {
+                Some(p) if p.is_lowercase() || p.is_ascii_digit() => true,
+                Some(p) if p.is_uppercase() => next.is_some_and(|n| n.is_lowercase()),
+                _ => false,
+            };
+            if starts_word && !snake.ends_with('_') {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+
+    if snake.starts_with(|c: char| c.is_ascii_digit()) {
+        snake.insert(0, '_');
+    }
+    if RUST_KEYWORDS.contains(&snake.as_str()) {
+        snake.insert_str(0, "r#");
+    }
+
+    snake
+}
+
+// `usp_get_sale_order_by_id` -> `UspGetSaleOrderById`
+fn pascal_case(name: &str) -> String {
+    snake_case(name)
+        .trim_start_matches("r#")
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn argument_name(parameter: &Parameter) -> String {
+    let name = snake_case(&parameter.name);
+    if RESERVED_NAMES.contains(&name.as_str()) {
+        format!("{}_value", name)
+    } else {
+        name
+    }
+}
+
+fn column_name(name: &str) -> &str {
+    name.trim_start_matches('@')
+}
+
+// Reads a column of `row` as a field value
+fn read_column(row: &str, name: &str, rust: &RustType, nullable: bool) -> String {
+    let mut read = format!("{}.try_get::<{}, _>({:?})?", row, rust.read, name);
+    if let Some(to_owned) = rust.to_owned {
+        read.push_str(to_owned);
+    }
+    if !nullable {
+        let _ = write!(read, ".ok_or_else(|| null_column({:?}))?", name);
+    }
+    read
+}
+
+// A SQL statement where `{}` are arguments of `format!`
+struct Statement {
+    sql: String,
+    arguments: Vec<String>,
+}
+
+impl Statement {
+    fn new() -> Self {
+        Statement {
+            sql: String::new(),
+            arguments: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, sql: &str) {
+        self.sql
+            .push_str(&sql.replace('{', "{{").replace('}', "}}"));
+    }
+
+    fn push_argument(&mut self, argument: String) {
+        self.sql.push_str("{}");
+        self.arguments.push(argument);
+    }
+
+    // Binds the input parameters, `None` for a parameter with a default
+    // is replaced with `default`
+    fn push_parameters<'a>(
+        &mut self,
+        parameters: impl Iterator<Item = &'a Parameter>,
+        named: bool,
+    ) -> Vec<String> {
+        let mut bound = Vec::new();
+
+        for (i, parameter) in parameters.enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            if named {
+                self.push(&format!("{} = ", parameter.name));
+            }
+
+            let placeholder = format!("@P{}", bound.len() + 1);
+            let argument = argument_name(parameter);
+            if parameter.default_value.is_some() {
+                self.push_argument(format!(
+                    "if {}.is_some() {{ {:?} }} else {{ \"default\" }}",
+                    argument, placeholder
+                ));
+            } else {
+                self.push(&placeholder);
+            }
+            bound.push(argument);
+        }
+
+        bound
+    }
+
+    fn write(&self, code: &mut String) {
+        if self.arguments.is_empty() {
+            let _ = writeln!(
+                code,
+                "    let mut query = tiberius::Query::new({:?});",
+                self.sql.replace("{{", "{").replace("}}", "}")
+            );
+        } else {
+            let _ = writeln!(code, "    let sql = format!(");
+            let _ = writeln!(code, "        {:?},", self.sql);
+            for argument in &self.arguments {
+                let _ = writeln!(code, "        {},", argument);
+            }
+            let _ = writeln!(code, "    );");
+            let _ = writeln!(code, "    let mut query = tiberius::Query::new(sql);");
+        }
+    }
+}
+
+struct Generator<'a> {
+    routine: &'a Signature,
+    name: String,
+    type_name: String,
+}
+
+impl<'a> Generator<'a> {
+    fn new(routine: &'a Signature) -> Self {
+        Generator {
+            routine,
+            name: snake_case(routine.name.object().as_str()),
+            type_name: pascal_case(routine.name.object().as_str()),
+        }
+    }
+
+    fn inputs(&self) -> impl Iterator<Item = &'a Parameter> {
+        self.routine
+            .parameters
+            .iter()
+            .filter(|p| p.direction == ParameterDirection::Input)
+    }
+
+    fn outputs(&self) -> impl Iterator<Item = &'a Parameter> {
+        self.routine
+            .parameters
+            .iter()
+            .filter(|p| p.direction == ParameterDirection::Output)
+    }
+
+    fn row_type(&self) -> String {
+        format!("{}Row", self.type_name)
+    }
+
+    fn result_columns(&self) -> Result<&'a [ResultColumn], String> {
+        if let Some(error) = &self.routine.result_error {
+            return Err(format!(
+                "The result of {} is unknown ({}), describe its columns in the snapshot",
+                self.routine.name, error
+            ));
+        }
+        Ok(&self.routine.result_columns)
+    }
+
+    fn write_signature(&self, code: &mut String, returns: &str) -> Result<(), String> {
+        let _ = writeln!(code, "/// Calls `{}`.", self.routine.name);
+        if self.inputs().any(|p| p.default_value.is_some()) {
+            let _ = writeln!(
+                code,
+                "///\n/// Arguments set to `None` take the default of the parameter."
+            );
+        }
+        let _ = writeln!(code, "pub async fn {}(", self.name);
+        let _ = writeln!(code, "    client: &mut {},", CLIENT_TYPE);
+        for parameter in self.inputs() {
+            let rust = rust_type(&parameter.data_type)?;
+            let argument = if parameter.default_value.is_some() {
+                format!("Option<{}>", rust.argument)
+            } else {
+                rust.argument.to_owned()
+            };
+            let _ = writeln!(code, "    {}: {},", argument_name(parameter), argument);
+        }
+        let _ = writeln!(code, ") -> tiberius::Result<{}> {{", returns);
+        Ok(())
+    }
+
+    fn write_binds(&self, code: &mut String, bound: &[String]) {
+        for argument in bound {
+            let _ = writeln!(code, "    query.bind({});", argument);
+        }
+        let _ = writeln!(code);
+    }
+
+    fn write_row_type(&self, code: &mut String, columns: &[ResultColumn]) -> Result<(), String> {
+        let row_type = self.row_type();
+        let mut fields = Vec::with_capacity(columns.len());
+
+        for column in columns {
+            let name = column
+                .name
+                .as_deref()
+                .ok_or_else(|| format!("A column of {} has no name", self.routine.name))?;
+            fields.push((
+                name,
+                snake_case(name),
+                rust_type(&column.data_type)?,
+                column,
+            ));
+        }
+
+        let _ = writeln!(code, "/// A row returned by `{}`", self.routine.name);
+        let _ = writeln!(code, "#[derive(Debug, Clone, PartialEq)]");
+        let _ = writeln!(code, "pub struct {} {{", row_type);
+        for (_, field, rust, column) in &fields {
+            if column.is_nullable {
+                let _ = writeln!(code, "    pub {}: Option<{}>,", field, rust.owned);
+            } else {
+                let _ = writeln!(code, "    pub {}: {},", field, rust.owned);
+            }
+        }
+        let _ = writeln!(code, "}}\n");
+
+        let _ = writeln!(code, "impl {} {{", row_type);
+        let _ = writeln!(
+            code,
+            "    pub fn from_row(row: &tiberius::Row) -> tiberius::Result<Self> {{"
+        );
+        let _ = writeln!(code, "        Ok({} {{", row_type);
+        for (name, field, rust, column) in &fields {
+            let _ = writeln!(
+                code,
+                "            {}: {},",
+                field,
+                read_column("row", name, rust, column.is_nullable)
+            );
+        }
+        let _ = writeln!(code, "        }})");
+        let _ = writeln!(code, "    }}");
+        let _ = writeln!(code, "}}\n");
+
+        Ok(())
+    }
+
+    fn write_procedure(&self, code: &mut String) -> Result<(), String> {
+        let columns = self.result_columns()?;
+        let output_type = format!("{}Output", self.type_name);
+
+        if !columns.is_empty() {
+            self.write_row_type(code, columns)?;
+        }
+
+        let _ = writeln!(code, "/// Output of `{}`", self.routine.name);
+        let _ = writeln!(code, "#[derive(Debug, Clone, PartialEq)]");
+        let _ = writeln!(code, "pub struct {} {{", output_type);
+        let _ = writeln!(code, "    /// Value of the `return` statement");
+        let _ = writeln!(code, "    pub return_value: i32,");
+        for parameter in self.outputs() {
+            let rust = rust_type(&parameter.data_type)?;
+            let _ = writeln!(
+                code,
+                "    pub {}: Option<{}>,",
+                argument_name(parameter),
+                rust.owned
+            );
+        }
+        let _ = writeln!(code, "}}\n");
+
+        let returns = if columns.is_empty() {
+            output_type.clone()
+        } else {
+            format!("(Vec<{}>, {})", self.row_type(), output_type)
+        };
+        self.write_signature(code, &returns)?;
+
+        // The return value and the output parameters come in a last result set
+        let mut statement = Statement::new();
+        statement.push("declare @ReturnValue int;\n");
+        for parameter in self.outputs() {
+            statement.push(&format!(
+                "declare {} {};\n",
+                parameter.name, parameter.data_type
+            ));
+        }
+        statement.push(&format!("exec @ReturnValue = {}", self.routine.name));
+        if self.routine.parameters.is_empty() {
+            statement.push(";\n");
+        } else {
+            statement.push(" ");
+        }
+        let bound = statement.push_parameters(self.inputs(), true);
+        for (i, parameter) in self.outputs().enumerate() {
+            if i > 0 || !bound.is_empty() {
+                statement.push(", ");
+            }
+            statement.push(&format!("{name} = {name} output", name = parameter.name));
+        }
+        if !self.routine.parameters.is_empty() {
+            statement.push(";\n");
+        }
+        statement.push("select @ReturnValue as [ReturnValue]");
+        for parameter in self.outputs() {
+            statement.push(&format!(
+                ", {} as {}",
+                parameter.name,
+                quote(column_name(&parameter.name))
+            ));
+        }
+        statement.push(";");
+
+        statement.write(code);
+        self.write_binds(code, &bound);
+
+        let _ = writeln!(
+            code,
+            "    let results = query.query(client).await?.into_results().await?;"
+        );
+        let _ = writeln!(
+            code,
+            "    let row = results.last().and_then(|rows| rows.first()).ok_or_else(|| {{"
+        );
+        let _ = writeln!(
+            code,
+            "        tiberius::error::Error::Protocol(\"The procedure returned no output\".into())"
+        );
+        let _ = writeln!(code, "    }})?;");
+        let _ = writeln!(code, "    let output = {} {{", output_type);
+        let _ = writeln!(
+            code,
+            "        return_value: {},",
+            read_column("row", "ReturnValue", &RustType::copied("i32"), false)
+        );
+        for parameter in self.outputs() {
+            let rust = rust_type(&parameter.data_type)?;
+            let _ = writeln!(
+                code,
+                "        {}: {},",
+                argument_name(parameter),
+                read_column("row", column_name(&parameter.name), &rust, true)
+            );
+        }
+        let _ = writeln!(code, "    }};");
+
+        if columns.is_empty() {
+            let _ = writeln!(code, "\n    Ok(output)");
+        } else {
+            let _ = writeln!(code);
+            let _ = writeln!(code, "    let rows = match results.first() {{");
+            let _ = writeln!(
+                code,
+                "        Some(rows) if results.len() > 1 => rows\n            .iter()\n            .map({}::from_row)\n            .collect::<tiberius::Result<Vec<_>>>()?,",
+                self.row_type()
+            );
+            let _ = writeln!(code, "        _ => Vec::new(),");
+            let _ = writeln!(code, "    }};");
+            let _ = writeln!(code, "\n    Ok((rows, output))");
+        }
+        let _ = writeln!(code, "}}");
+
+        Ok(())
+    }
+
+    fn write_scalar_function(&self, code: &mut String) -> Result<(), String> {
+        let returns = self
+            .routine
+            .returns
+            .as_ref()
+            .ok_or_else(|| format!("The function {} has no return type", self.routine.name))?;
+        let rust = rust_type(returns)?;
+
+        self.write_signature(code, &format!("Option<{}>", rust.owned))?;
+
+        let mut statement = Statement::new();
+        statement.push(&format!("select {}(", self.routine.name));
+        let bound = statement.push_parameters(self.inputs(), false);
+        statement.push(") as [Value];");
+
+        statement.write(code);
+        self.write_binds(code, &bound);
+
+        let _ = writeln!(
+            code,
+            "    let row = query.query(client).await?.into_row().await?;"
+        );
+        let _ = writeln!(code, "    match row {{");
+        let _ = writeln!(
+            code,
+            "        Some(row) => Ok({}),",
+            read_column("row", "Value", &rust, true)
+        );
+        let _ = writeln!(code, "        None => Ok(None),");
+        let _ = writeln!(code, "    }}");
+        let _ = writeln!(code, "}}");
+
+        Ok(())
+    }
+
+    fn write_table_valued_function(&self, code: &mut String) -> Result<(), String> {
+        let columns = self.result_columns()?;
+        if columns.is_empty() {
+            return Err(format!("The function {} has no columns", self.routine.name));
+        }
+
+        self.write_row_type(code, columns)?;
+        self.write_signature(code, &format!("Vec<{}>", self.row_type()))?;
+
+        let names: Vec<String> = columns
+            .iter()
+            .filter_map(|c| c.name.as_deref())
+            .map(quote)
+            .collect();
+
+        let mut statement = Statement::new();
+        statement.push(&format!(
+            "select {} from {}(",
+            names.join(", "),
+            self.routine.name
+        ));
+        let bound = statement.push_parameters(self.inputs(), false);
+        statement.push(");");
+
+        statement.write(code);
+        self.write_binds(code, &bound);
+
+        let _ = writeln!(
+            code,
+            "    let rows = query.query(client).await?.into_first_result().await?;"
+        );
+        let _ = writeln!(
+            code,
+            "    rows.iter().map({}::from_row).collect()",
+            self.row_type()
+        );
+        let _ = writeln!(code, "}}");
+
+        Ok(())
+    }
+}
+
+/// Generates Rust async functions that call the procedures and functions of `snapshot`.
+///
+/// Procedures return their output parameters and return value in an `...Output` struct,
+/// and their first result set as `...Row` structs. Scalar functions return their value
+/// and table valued functions their rows.
+pub fn generate_rust(snapshot: &SchemaSnapshot) -> Result<String, Box<dyn std::error::Error>> {
+    let mut code = String::from(
+        "// Generated from a schema snapshot with `cargo run --bin codegen -- generate`,\n// do not edit. Take a new snapshot and generate it again when a signature changes.\n",
+    );
+
+    // Procedures always read their return value, a column that can't be NULL
+    let reads_required_columns = snapshot.routines.iter().any(|r| {
+        r.kind == ObjectKind::Procedure || r.result_columns.iter().any(|c| !c.is_nullable)
+    });
+    if reads_required_columns {
+        code.push_str(
+            r#"
+fn null_column(column: &str) -> tiberius::error::Error {
+    tiberius::error::Error::Conversion(format!("Column {} is NULL", column).into())
+}
+"#,
+        );
+    }
+
+    for routine in &snapshot.routines {
+        let generator = Generator::new(routine);
+        code.push('\n');
+
+        match routine.kind {
+            ObjectKind::Procedure => generator.write_procedure(&mut code)?,
+            ObjectKind::ScalarFunction => generator.write_scalar_function(&mut code)?,
+            ObjectKind::TableValuedFunction => generator.write_table_valued_function(&mut code)?,
+            ObjectKind::View => return Err(format!("{} is a view", routine.name).into()),
+        }
+    }
+
+    Ok(code)
+}
+
+/// Generates the wrappers of a snapshot file into `output`, to be called from a `build.rs`.
+///
+/// The file is only written when its content changes.
+pub fn generate_from_snapshot_file(
+    snapshot: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let snapshot = SchemaSnapshot::from_json(&std::fs::read_to_string(snapshot)?)?;
+    let code = generate_rust(&snapshot)?;
+
+    let output = output.as_ref();
+    if std::fs::read_to_string(output).ok().as_deref() != Some(code.as_str()) {
+        std::fs::write(output, code)?;
+    }
+
+    Ok(())
+}
+
+pub async fn snapshot_sales_order_routines() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let snapshot = snapshot_routines(&mut client, &sales_order_routine_names()?).await?;
+    println!("{}", snapshot.to_json()?);
+
+    client.close().await?;
+
+    Ok(())
+}
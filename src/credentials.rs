@@ -0,0 +1,297 @@
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use tiberius::AuthMethod;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// A user and a password for SQL Server authentication.
+///
+/// `Debug` masks the password.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(user: &str, password: &str) -> Self {
+        Credentials {
+            user: user.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    pub fn auth_method(&self) -> AuthMethod {
+        AuthMethod::sql_server(&self.user, &self.password)
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user", &self.user)
+            .field("password", &"********")
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum CredentialError {
+    MissingVariable(String),
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Command {
+        program: String,
+        message: String,
+    },
+    /// The command printed something that is not `{"user": "...", "password": "..."}`
+    InvalidOutput {
+        program: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialError::MissingVariable(name) => {
+                write!(f, "The environment variable {} is not set", name)
+            }
+            CredentialError::Io { path, source } => {
+                write!(f, "Cannot read {}: {}", path.display(), source)
+            }
+            CredentialError::Command { program, message } => {
+                write!(f, "{} failed: {}", program, message)
+            }
+            CredentialError::InvalidOutput { program, message } => {
+                write!(f, "{} did not print credentials: {}", program, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CredentialError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CredentialError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type CredentialFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Credentials, CredentialError>> + Send + 'a>>;
+
+/// Where the user and password come from, so they are not written in the code.
+pub trait CredentialProvider: Send + Sync {
+    fn credentials(&self) -> CredentialFuture<'_>;
+
+    /// Forgets cached credentials, called when the server rejects them
+    /// because the password may have been rotated.
+    fn invalidate(&self) {}
+}
+
+/// Reads the user and password from environment variables,
+/// `SQL_SERVER_USER` and `SQL_SERVER_PASSWORD` by default.
+#[derive(Debug, Clone)]
+pub struct EnvironmentCredentials {
+    user_variable: String,
+    password_variable: String,
+}
+
+impl EnvironmentCredentials {
+    pub fn new(user_variable: &str, password_variable: &str) -> Self {
+        EnvironmentCredentials {
+            user_variable: user_variable.to_owned(),
+            password_variable: password_variable.to_owned(),
+        }
+    }
+}
+
+impl Default for EnvironmentCredentials {
+    fn default() -> Self {
+        EnvironmentCredentials::new("SQL_SERVER_USER", "SQL_SERVER_PASSWORD")
+    }
+}
+
+impl CredentialProvider for EnvironmentCredentials {
+    fn credentials(&self) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            let variable = |name: &str| {
+                std::env::var(name).map_err(|_| CredentialError::MissingVariable(name.to_owned()))
+            };
+
+            Ok(Credentials {
+                user: variable(&self.user_variable)?,
+                password: variable(&self.password_variable)?,
+            })
+        })
+    }
+}
+
+/// Reads the user and the password from two files, as Docker and Kubernetes mount secrets.
+///
+/// The line break at the end of a file is not part of the value.
+#[derive(Debug, Clone)]
+pub struct FileCredentials {
+    user_path: PathBuf,
+    password_path: PathBuf,
+}
+
+impl FileCredentials {
+    pub fn new(user_path: impl Into<PathBuf>, password_path: impl Into<PathBuf>) -> Self {
+        FileCredentials {
+            user_path: user_path.into(),
+            password_path: password_path.into(),
+        }
+    }
+
+    /// The `username` and `password` files of a mounted secret,
+    /// `/run/secrets/sql_server` for instance.
+    pub fn directory(directory: impl AsRef<Path>) -> Self {
+        let directory = directory.as_ref();
+        FileCredentials::new(directory.join("username"), directory.join("password"))
+    }
+}
+
+async fn read_secret(path: &Path) -> Result<String, CredentialError> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|source| CredentialError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+
+    Ok(content.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+impl CredentialProvider for FileCredentials {
+    fn credentials(&self) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            Ok(Credentials {
+                user: read_secret(&self.user_path).await?,
+                password: read_secret(&self.password_path).await?,
+            })
+        })
+    }
+}
+
+/// Runs a command that prints `{"user": "...", "password": "..."}`,
+/// a secret manager CLI for instance.
+#[derive(Debug, Clone)]
+pub struct CommandCredentials {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandCredentials {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        CommandCredentials {
+            program: program.to_owned(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+}
+
+impl CredentialProvider for CommandCredentials {
+    fn credentials(&self) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            let output = Command::new(&self.program)
+                .args(&self.args)
+                .kill_on_drop(true)
+                .output()
+                .await
+                .map_err(|e| CredentialError::Command {
+                    program: self.program.clone(),
+                    message: e.to_string(),
+                })?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(CredentialError::Command {
+                    program: self.program.clone(),
+                    message: format!("{}, {}", output.status, stderr.trim()),
+                });
+            }
+
+            // The output is not echoed in errors, it may hold the password
+            let invalid = |message: &str| CredentialError::InvalidOutput {
+                program: self.program.clone(),
+                message: message.to_owned(),
+            };
+            let value: serde_json::Value =
+                serde_json::from_slice(&output.stdout).map_err(|_| invalid("not JSON"))?;
+            let field = |names: &[&str]| {
+                names
+                    .iter()
+                    .find_map(|name| value.get(*name).and_then(|v| v.as_str()))
+                    .map(str::to_owned)
+            };
+
+            Ok(Credentials {
+                user: field(&["user", "username"]).ok_or_else(|| invalid("no user"))?,
+                password: field(&["password"]).ok_or_else(|| invalid("no password"))?,
+            })
+        })
+    }
+}
+
+/// Keeps the credentials of another provider for a while,
+/// so a pool of connections does not run the command or read the files each time.
+pub struct CachedCredentials<P> {
+    provider: P,
+    time_to_live: Duration,
+    cached: Mutex<Option<(Credentials, Instant)>>,
+}
+
+impl<P: CredentialProvider> CachedCredentials<P> {
+    pub fn new(provider: P, time_to_live: Duration) -> Self {
+        CachedCredentials {
+            provider,
+            time_to_live,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Asks the provider again even if the cached credentials have not expired.
+    pub async fn refresh(&self) -> Result<Credentials, CredentialError> {
+        let mut cached = self.cached.lock().await;
+        let credentials = self.provider.credentials().await?;
+        *cached = Some((credentials.clone(), Instant::now()));
+
+        Ok(credentials)
+    }
+}
+
+impl<P: CredentialProvider> CredentialProvider for CachedCredentials<P> {
+    fn credentials(&self) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            // Held while the provider runs, concurrent callers wait for one answer
+            let mut cached = self.cached.lock().await;
+            if let Some((credentials, fetched)) = cached.as_ref() {
+                if fetched.elapsed() < self.time_to_live {
+                    return Ok(credentials.clone());
+                }
+            }
+
+            let credentials = self.provider.credentials().await?;
+            *cached = Some((credentials.clone(), Instant::now()));
+
+            Ok(credentials)
+        })
+    }
+
+    fn invalidate(&self) {
+        // A lock that is held is fetching new credentials already
+        if let Ok(mut cached) = self.cached.try_lock() {
+            *cached = None;
+        }
+        self.provider.invalidate();
+    }
+}
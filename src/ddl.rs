@@ -0,0 +1,422 @@
+use crate::identifiers::quote;
+use crate::{
+    CheckConstraint, Column, ComputedColumn, DataType, DefaultConstraint, ForeignKey, Identifier,
+    Identity, Index, IndexColumn, InvalidIdentifier, ObjectName, TableSchema,
+};
+
+/// The definition of a column as written in `create table` or `alter table ... add`,
+/// with its default constraint.
+pub fn column_definition(column: &Column) -> String {
+    let mut definition = quote(&column.name);
+
+    if let Some(computed) = &column.computed {
+        definition.push_str(" as ");
+        definition.push_str(&computed.definition);
+
+        // Only persisted computed columns can be declared not null
+        if computed.is_persisted {
+            definition.push_str(" persisted");
+            if !column.is_nullable {
+                definition.push_str(" not null");
+            }
+        }
+        return definition;
+    }
+
+    definition.push(' ');
+    definition.push_str(&column.data_type.to_string());
+
+    if let Some(collation) = &column.collation {
+        definition.push_str(" collate ");
+        definition.push_str(collation);
+    }
+    if let Some(identity) = &column.identity {
+        definition.push_str(&format!(
+            " identity({}, {})",
+            identity.seed, identity.increment
+        ));
+    }
+    definition.push_str(if column.is_nullable {
+        " null"
+    } else {
+        " not null"
+    });
+
+    if let Some(default) = &column.default {
+        if !default.is_system_named {
+            definition.push_str(" constraint ");
+            definition.push_str(&quote(&default.name));
+        }
+        definition.push_str(" default ");
+        definition.push_str(&default.definition);
+    }
+
+    definition
+}
+
+fn index_columns(index: &Index, included: bool) -> String {
+    index
+        .columns
+        .iter()
+        .filter(|c| c.is_included == included)
+        .map(|c| {
+            if included {
+                quote(&c.name)
+            } else if c.is_descending {
+                format!("{} desc", quote(&c.name))
+            } else {
+                format!("{} asc", quote(&c.name))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn clustering(index: &Index) -> &'static str {
+    if index.is_clustered() {
+        "clustered"
+    } else {
+        "nonclustered"
+    }
+}
+
+/// `true` for the indexes created by a primary key or unique constraint
+pub fn is_constraint_index(index: &Index) -> bool {
+    index.is_primary_key || index.is_unique_constraint
+}
+
+/// The primary key or unique constraint of an index, `constraint [PK_...] primary key ...`
+pub fn index_constraint(index: &Index) -> String {
+    format!(
+        "constraint {} {} {} ({})",
+        quote(&index.name),
+        if index.is_primary_key {
+            "primary key"
+        } else {
+            "unique"
+        },
+        clustering(index),
+        index_columns(index, false)
+    )
+}
+
+/// Creates an index that is not a constraint.
+pub fn create_index_statement(table: &ObjectName, index: &Index) -> String {
+    let mut statement = format!(
+        "create {}{} index {} on {} ({})",
+        if index.is_unique { "unique " } else { "" },
+        clustering(index),
+        quote(&index.name),
+        table,
+        index_columns(index, false)
+    );
+
+    let included = index_columns(index, true);
+    if !included.is_empty() {
+        statement.push_str(&format!(" include ({})", included));
+    }
+
+    statement
+}
+
+pub fn check_constraint(check: &CheckConstraint) -> String {
+    format!(
+        "constraint {} check {}",
+        quote(&check.name),
+        check.definition
+    )
+}
+
+pub fn foreign_key_constraint(foreign_key: &ForeignKey) -> String {
+    let columns = |referenced: bool| {
+        foreign_key
+            .columns
+            .iter()
+            .map(|c| {
+                quote(if referenced {
+                    &c.referenced_column
+                } else {
+                    &c.column
+                })
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    format!(
+        "constraint {} foreign key ({}) references {} ({}) on delete {} on update {}",
+        quote(&foreign_key.name),
+        columns(false),
+        foreign_key.referenced_table,
+        columns(true),
+        foreign_key.on_delete,
+        foreign_key.on_update
+    )
+}
+
+/// The statements that create a table: `create table` with its columns and constraints,
+/// followed by a `create index` for every index that is not a constraint.
+pub fn create_table_statements(table: &TableSchema) -> Vec<String> {
+    let mut lines: Vec<String> = table.columns.iter().map(column_definition).collect();
+
+    lines.extend(
+        table
+            .indexes
+            .iter()
+            .filter(|i| is_constraint_index(i))
+            .map(index_constraint),
+    );
+    lines.extend(table.check_constraints.iter().map(check_constraint));
+    lines.extend(table.foreign_keys.iter().map(foreign_key_constraint));
+
+    let mut statements = vec![format!(
+        "create table {}\n(\n    {}\n)",
+        table.name,
+        lines.join(",\n    ")
+    )];
+
+    statements.extend(
+        table
+            .indexes
+            .iter()
+            .filter(|i| !is_constraint_index(i))
+            .map(|i| create_index_statement(&table.name, i)),
+    );
+
+    statements
+}
+
+impl DataType {
+    fn sized(name: &str, max_length: i16, precision: u8, scale: u8) -> Self {
+        DataType {
+            name: name.to_owned(),
+            max_length,
+            precision,
+            scale,
+        }
+    }
+
+    pub fn bit() -> Self {
+        DataType::sized("bit", 1, 1, 0)
+    }
+
+    pub fn tinyint() -> Self {
+        DataType::sized("tinyint", 1, 3, 0)
+    }
+
+    pub fn int() -> Self {
+        DataType::sized("int", 4, 10, 0)
+    }
+
+    pub fn bigint() -> Self {
+        DataType::sized("bigint", 8, 19, 0)
+    }
+
+    pub fn decimal(precision: u8, scale: u8) -> Self {
+        DataType::sized("decimal", 17, precision, scale)
+    }
+
+    pub fn money() -> Self {
+        DataType::sized("money", 8, 19, 4)
+    }
+
+    pub fn datetime() -> Self {
+        DataType::sized("datetime", 8, 23, 3)
+    }
+
+    pub fn uniqueidentifier() -> Self {
+        DataType::sized("uniqueidentifier", 16, 0, 0)
+    }
+
+    pub fn varchar(length: i16) -> Self {
+        DataType::sized("varchar", length, 0, 0)
+    }
+
+    /// `nvarchar(length)`, the catalog records the size in bytes
+    pub fn nvarchar(length: i16) -> Self {
+        DataType::sized("nvarchar", length * 2, 0, 0)
+    }
+
+    /// `varchar(max)`, the catalog records -1 as its size
+    pub fn varchar_max() -> Self {
+        DataType::sized("varchar", -1, 0, 0)
+    }
+
+    /// `nvarchar(max)`
+    pub fn nvarchar_max() -> Self {
+        DataType::sized("nvarchar", -1, 0, 0)
+    }
+}
+
+/// A column of a `TableDefinition`.
+///
+/// Expressions are written as in T-SQL, without the parentheses the catalog adds around them.
+#[derive(Debug, Clone)]
+pub struct ColumnDefinition {
+    name: String,
+    data_type: DataType,
+    is_nullable: bool,
+    identity: Option<Identity>,
+    computed: Option<ComputedColumn>,
+    default: Option<(bool, String)>,
+    check: Option<String>,
+}
+
+impl ColumnDefinition {
+    /// A `not null` column
+    pub fn new(name: &str, data_type: DataType) -> Self {
+        ColumnDefinition {
+            name: name.to_owned(),
+            data_type,
+            is_nullable: false,
+            identity: None,
+            computed: None,
+            default: None,
+            check: None,
+        }
+    }
+
+    /// A computed column, `data_type` is the type of its expression
+    pub fn computed(name: &str, data_type: DataType, expression: &str) -> Self {
+        ColumnDefinition {
+            computed: Some(ComputedColumn {
+                definition: format!("({})", expression),
+                is_persisted: false,
+            }),
+            ..ColumnDefinition::new(name, data_type)
+        }
+    }
+
+    pub fn nullable(mut self) -> Self {
+        self.is_nullable = true;
+        self
+    }
+
+    pub fn identity(mut self, seed: i64, increment: i64) -> Self {
+        self.identity = Some(Identity { seed, increment });
+        self
+    }
+
+    /// A default constraint named `DF_<table>_<column>`
+    pub fn default(mut self, expression: &str) -> Self {
+        self.default = Some((true, format!("({})", expression)));
+        self
+    }
+
+    /// A default constraint named by SQL Server
+    pub fn unnamed_default(mut self, expression: &str) -> Self {
+        self.default = Some((false, format!("({})", expression)));
+        self
+    }
+
+    /// A check constraint named `CK_<table>_<column>`
+    pub fn check(mut self, expression: &str) -> Self {
+        self.check = Some(format!("({})", expression));
+        self
+    }
+}
+
+/// A table declared in Rust, built into the `TableSchema` the catalog would report for it.
+///
+/// Constraints are named after the table, so the same definition can be created
+/// under other names (`PK_SalesOrderHeader_SalesOrderID`, `CK_SalesOrderHeader_Status`).
+#[derive(Debug, Clone)]
+pub struct TableDefinition {
+    name: ObjectName,
+    columns: Vec<ColumnDefinition>,
+    primary_key: Vec<String>,
+}
+
+impl TableDefinition {
+    pub fn new(name: &ObjectName) -> Self {
+        TableDefinition {
+            name: name.clone(),
+            columns: Vec::new(),
+            primary_key: Vec::new(),
+        }
+    }
+
+    pub fn column(mut self, column: ColumnDefinition) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    /// A clustered primary key named `PK_<table>_<first column>`
+    pub fn primary_key(mut self, columns: &[&str]) -> Self {
+        self.primary_key = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    pub fn build(self) -> Result<TableSchema, InvalidIdentifier> {
+        let table = self.name.object().as_str().to_owned();
+        let constraint = |kind: &str, column: &str| {
+            Identifier::new(format!("{}_{}_{}", kind, table, column))
+                .map(|name| name.as_str().to_owned())
+        };
+
+        let mut schema = TableSchema {
+            name: self.name.clone(),
+            columns: Vec::with_capacity(self.columns.len()),
+            indexes: Vec::new(),
+            check_constraints: Vec::new(),
+            foreign_keys: Vec::new(),
+        };
+
+        for (column_id, column) in (1..).zip(self.columns) {
+            let default = match column.default {
+                Some((named, definition)) => Some(DefaultConstraint {
+                    name: if named {
+                        constraint("DF", &column.name)?
+                    } else {
+                        String::new()
+                    },
+                    is_system_named: !named,
+                    definition,
+                }),
+                None => None,
+            };
+
+            if let Some(definition) = column.check {
+                schema.check_constraints.push(CheckConstraint {
+                    name: constraint("CK", &column.name)?,
+                    definition,
+                    column: Some(column.name.clone()),
+                    is_disabled: false,
+                });
+            }
+
+            schema.columns.push(Column {
+                column_id,
+                name: column.name,
+                data_type: column.data_type,
+                is_nullable: column.is_nullable,
+                collation: None,
+                identity: column.identity,
+                computed: column.computed,
+                default,
+            });
+        }
+
+        if let Some(first) = self.primary_key.first() {
+            schema.indexes.push(Index {
+                index_id: 1,
+                name: constraint("PK", first)?,
+                kind: "CLUSTERED".to_owned(),
+                is_primary_key: true,
+                is_unique: true,
+                is_unique_constraint: false,
+                columns: self
+                    .primary_key
+                    .iter()
+                    .map(|name| IndexColumn {
+                        name: name.clone(),
+                        is_descending: false,
+                        is_included: false,
+                    })
+                    .collect(),
+            });
+        }
+
+        Ok(schema)
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tiberius::Client;
+use tokio::net::TcpStream;
+use tokio_util::compat::Compat;
+
+use crate::connections::connect_to_local_server;
+use crate::{
+    get_sale_order_by_id_procedure, get_sale_orders_by_ids_procedure, sales_order_header,
+    sales_order_status_text_function, sales_orders_with_total_due_more_than_function,
+    save_order_header_get_id_procedure, save_order_header_procedure, update_order_status_procedure,
+    ObjectName,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectKind {
+    Procedure,
+    ScalarFunction,
+    TableValuedFunction,
+    View,
+}
+
+impl ObjectKind {
+    fn keyword(&self) -> &'static str {
+        match self {
+            ObjectKind::Procedure => "procedure",
+            ObjectKind::ScalarFunction | ObjectKind::TableValuedFunction => "function",
+            ObjectKind::View => "view",
+        }
+    }
+
+    pub(crate) fn from_object_type(object_type: &str) -> Option<ObjectKind> {
+        match object_type {
+            "P" => Some(ObjectKind::Procedure),
+            "FN" => Some(ObjectKind::ScalarFunction),
+            "IF" | "TF" => Some(ObjectKind::TableValuedFunction),
+            "V" => Some(ObjectKind::View),
+            _ => None,
+        }
+    }
+
+    // Values of `sys.objects.type` for the kind
+    fn object_types(&self) -> &'static [&'static str] {
+        match self {
+            ObjectKind::Procedure => &["P"],
+            ObjectKind::ScalarFunction => &["FN"],
+            ObjectKind::TableValuedFunction => &["IF", "TF"],
+            ObjectKind::View => &["V"],
+        }
+    }
+}
+
+/// A procedure, function or view that can be deployed with `CREATE OR ALTER`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgrammableObject {
+    pub kind: ObjectKind,
+    pub name: ObjectName,
+    /// Everything after the object name: parameters, options and body
+    pub definition: String,
+}
+
+impl ProgrammableObject {
+    pub fn new(kind: ObjectKind, name: &ObjectName, definition: String) -> Self {
+        ProgrammableObject {
+            kind,
+            name: name.clone(),
+            definition,
+        }
+    }
+
+    /// The statement that creates or alters the object,
+    /// it's also the text SQL Server keeps in `sys.sql_modules`
+    pub fn statement(&self) -> String {
+        format!(
+            "create or alter {} {} {}",
+            self.kind.keyword(),
+            self.name,
+            self.definition.trim()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeploymentOutcome {
+    Created,
+    Altered,
+    Unchanged,
+}
+
+impl fmt::Display for DeploymentOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeploymentOutcome::Created => write!(f, "Created"),
+            DeploymentOutcome::Altered => write!(f, "Altered"),
+            DeploymentOutcome::Unchanged => write!(f, "Unchanged"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeploymentResult {
+    pub name: ObjectName,
+    pub kind: ObjectKind,
+    pub outcome: DeploymentOutcome,
+}
+
+/// Creates the object or alters it when its definition changed.
+///
+/// The hash of the statement is compared with the hash of the definition
+/// in `sys.sql_modules`, so an object deployed with the same text is not altered.
+pub async fn deploy_object(
+    client: &mut Client<Compat<TcpStream>>,
+    object: &ProgrammableObject,
+) -> Result<DeploymentOutcome, Box<dyn std::error::Error>> {
+    let statement = object.statement();
+
+    let existing = client
+        .query(
+            r#"
+select rtrim(o.type) as ObjectType,
+       case
+           when hashbytes('SHA2_256', m.definition) = hashbytes('SHA2_256', @P2) then 1
+           else 0
+       end as Unchanged
+from sys.objects o
+     left join sys.sql_modules m on m.object_id = o.object_id
+where o.object_id = object_id(@P1)
+        "#,
+            &[&object.name.to_string(), &statement],
+        )
+        .await?
+        .into_row()
+        .await?;
+
+    let outcome = match existing {
+        None => DeploymentOutcome::Created,
+        Some(row) => {
+            let object_type: &str = row.get("ObjectType").unwrap_or_default();
+
+            // CREATE OR ALTER can't change a function into a procedure, for instance
+            if !object.kind.object_types().contains(&object_type) {
+                return Err(format!(
+                    "{} already exists and it is not a {:?} (type {})",
+                    object.name, object.kind, object_type
+                )
+                .into());
+            }
+
+            if row.get::<i32, _>("Unchanged") == Some(1) {
+                return Ok(DeploymentOutcome::Unchanged);
+            }
+
+            DeploymentOutcome::Altered
+        }
+    };
+
+    // CREATE OR ALTER must be the only statement in the batch
+    client.simple_query(statement).await?.into_results().await?;
+
+    Ok(outcome)
+}
+
+/// Deploys the objects in order, an object must come after the ones it depends on.
+pub async fn deploy_objects(
+    client: &mut Client<Compat<TcpStream>>,
+    objects: &[ProgrammableObject],
+) -> Result<Vec<DeploymentResult>, Box<dyn std::error::Error>> {
+    let mut results = Vec::with_capacity(objects.len());
+
+    for object in objects {
+        let outcome = deploy_object(client, object).await?;
+
+        results.push(DeploymentResult {
+            name: object.name.clone(),
+            kind: object.kind,
+            outcome,
+        });
+    }
+
+    Ok(results)
+}
+
+/// The procedures and functions of the examples over `table`
+pub fn sales_order_objects(
+    table: &ObjectName,
+) -> Result<Vec<ProgrammableObject>, Box<dyn std::error::Error>> {
+    let name = |object: &str| table.sibling(object);
+
+    Ok(vec![
+        save_order_header_procedure(&name("uspSaveOrderHeader")?, table),
+        save_order_header_get_id_procedure(&name("uspSaveOrderHeaderGetID")?, table),
+        update_order_status_procedure(&name("uspUpdateOrderStatus")?, table),
+        get_sale_order_by_id_procedure(&name("uspGetSaleOrderByID")?, table),
+        get_sale_orders_by_ids_procedure(&name("uspGetSaleOrdersByIDs")?, table),
+        sales_order_status_text_function(&name("ufnGetSalesOrderStatusText")?),
+        sales_orders_with_total_due_more_than_function(
+            &name("ufnGetSalesOrderWithTotalDueMoreThan")?,
+            table,
+        ),
+    ])
+}
+
+pub async fn deploy_sales_order_objects() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let objects = sales_order_objects(&sales_order_header())?;
+
+    // Running it again reports every object as unchanged
+    for result in deploy_objects(&mut client, &objects).await? {
+        println!("{} {:?}: {}", result.outcome, result.kind, result.name);
+    }
+
+    client.close().await?;
+
+    Ok(())
+}
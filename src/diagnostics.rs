@@ -0,0 +1,345 @@
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tiberius::AuthMethod;
+use tokio::net::TcpStream;
+
+use crate::connections::local_server;
+use crate::identifiers::quote;
+use crate::{ConnectionSpec, EncryptionMode, ServerConfig, SqlServerBrowser};
+
+/// The steps of a connection, in the order they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Dns,
+    SqlBrowser,
+    TcpConnect,
+    TlsHandshake,
+    Login,
+    DatabaseAccess,
+    RoundTrip,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Dns => "DNS resolution",
+            Stage::SqlBrowser => "SQL Server Browser",
+            Stage::TcpConnect => "TCP connection",
+            Stage::TlsHandshake => "TLS handshake",
+            Stage::Login => "Login",
+            Stage::DatabaseAccess => "Database access",
+            Stage::RoundTrip => "Round trip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StageOutcome {
+    /// What the stage found, the addresses or the certificate for instance
+    Passed(String),
+    /// The settings don't need the stage, the reason
+    Skipped(String),
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageResult {
+    pub stage: Stage,
+    pub outcome: StageOutcome,
+    pub duration: Duration,
+}
+
+/// What `diagnose` found, stage by stage until the first one that failed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiagnosticsReport {
+    /// The connection settings, without the password
+    pub server: String,
+    pub stages: Vec<StageResult>,
+    /// `16.0.1000.6` for instance
+    pub server_version: Option<String>,
+    pub edition: Option<String>,
+    pub collation: Option<String>,
+}
+
+impl DiagnosticsReport {
+    /// The stage that failed, `None` when the connection works
+    pub fn failed_stage(&self) -> Option<Stage> {
+        self.stages
+            .iter()
+            .find(|result| matches!(result.outcome, StageOutcome::Failed(_)))
+            .map(|result| result.stage)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.failed_stage().is_none()
+    }
+
+    // Runs a stage for at most `limit`, its error stops the diagnostics
+    async fn check<T>(
+        &mut self,
+        stage: Stage,
+        limit: Option<Duration>,
+        work: impl Future<Output = Result<(T, String), Box<dyn std::error::Error>>>,
+    ) -> Option<T> {
+        let started = Instant::now();
+        let result = match limit {
+            Some(limit) => tokio::time::timeout(limit, work)
+                .await
+                .unwrap_or_else(|_| Err(format!("did not finish in {:?}", limit).into())),
+            None => work.await,
+        };
+
+        let (value, outcome) = match result {
+            Ok((value, detail)) => (Some(value), StageOutcome::Passed(detail)),
+            Err(e) => (None, StageOutcome::Failed(e.to_string())),
+        };
+        self.stages.push(StageResult {
+            stage,
+            outcome,
+            duration: started.elapsed(),
+        });
+
+        value
+    }
+
+    fn skip(&mut self, stage: Stage, reason: &str) {
+        self.stages.push(StageResult {
+            stage,
+            outcome: StageOutcome::Skipped(reason.to_owned()),
+            duration: Duration::ZERO,
+        });
+    }
+}
+
+impl fmt::Display for DiagnosticsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Diagnostics of {}", self.server)?;
+        for result in &self.stages {
+            let (status, detail) = match &result.outcome {
+                StageOutcome::Passed(detail) => ("ok", detail),
+                StageOutcome::Skipped(reason) => ("skipped", reason),
+                StageOutcome::Failed(message) => ("FAILED", message),
+            };
+            writeln!(
+                f,
+                "  {:<8} {:<20} {:>10.1} ms  {}",
+                status,
+                result.stage,
+                result.duration.as_secs_f64() * 1000.0,
+                detail
+            )?;
+        }
+
+        let properties = [
+            ("Version", &self.server_version),
+            ("Edition", &self.edition),
+            ("Collation", &self.collation),
+        ];
+        for (name, value) in properties {
+            if let Some(value) = value {
+                writeln!(f, "  {}: {}", name, value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Connects step by step and reports how long each stage took and the first one that failed,
+/// where `connect` only returns the last error.
+///
+/// The login is done without the database of the settings, so a database that can't be
+/// opened is told apart from a login that is rejected.
+pub async fn diagnose(
+    spec: impl Into<ConnectionSpec>,
+) -> Result<DiagnosticsReport, Box<dyn std::error::Error>> {
+    let spec = spec.into();
+    let server = spec.server_config()?;
+    let mut report = DiagnosticsReport {
+        server: spec.to_string(),
+        ..DiagnosticsReport::default()
+    };
+
+    run_stages(&server, &mut report).await;
+
+    Ok(report)
+}
+
+async fn run_stages(server: &ServerConfig, report: &mut DiagnosticsReport) {
+    let timeouts = server.timeouts;
+    let port = match (server.port, &server.instance_name) {
+        (Some(port), _) => port,
+        (None, Some(_)) => 1434,
+        (None, None) => 1433,
+    };
+
+    let Some(mut addresses) = report
+        .check(Stage::Dns, timeouts.connect, async {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((server.host.as_str(), port))
+                .await?
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no address", server.host).into());
+            }
+            let detail = join(&addresses);
+            Ok((addresses, detail))
+        })
+        .await
+    else {
+        return;
+    };
+
+    match &server.instance_name {
+        Some(instance_name) => {
+            let browser = SqlServerBrowser::new()
+                .port(port)
+                .time_to_live(Duration::ZERO);
+            let Some(tcp_port) = report
+                .check(Stage::SqlBrowser, timeouts.connect, async {
+                    let instance = browser.instance(&server.host, instance_name).await?;
+                    let tcp_port = instance
+                        .tcp_port
+                        .ok_or_else(|| format!("TCP is turned off for {}", instance_name))?;
+                    let detail = format!(
+                        "{} is version {} on port {}",
+                        instance_name, instance.version, tcp_port
+                    );
+                    Ok((tcp_port, detail))
+                })
+                .await
+            else {
+                return;
+            };
+            for address in &mut addresses {
+                address.set_port(tcp_port);
+            }
+        }
+        None => report.skip(Stage::SqlBrowser, "no instance name"),
+    }
+
+    let Some(tcp) = report
+        .check(Stage::TcpConnect, timeouts.connect, async {
+            let mut error = None;
+            for address in &addresses {
+                match TcpStream::connect(address).await {
+                    Ok(tcp) => return Ok((tcp, address.to_string())),
+                    Err(e) => error = Some(format!("{}: {}", address, e)),
+                }
+            }
+            Err(error.unwrap_or_default().into())
+        })
+        .await
+    else {
+        return;
+    };
+    let address = tcp.peer_addr().unwrap_or(addresses[0]);
+
+    let server_name = server.tls.server_name.as_deref().unwrap_or(&server.host);
+    if server.tls.encryption == EncryptionMode::Plaintext {
+        report.skip(Stage::TlsHandshake, "encryption is turned off");
+    } else {
+        let checked = report
+            .check(Stage::TlsHandshake, timeouts.login, async {
+                server.tls.validate()?;
+                let (fingerprint, _) =
+                    crate::tls::server_certificate(address, server_name, &server.tls).await?;
+                let checked_with = match &server.tls.ca_bundle {
+                    Some(path) => format!("checked with {}", path.display()),
+                    None => "checked at the login".to_owned(),
+                };
+                Ok(((), format!("certificate {}, {}", fingerprint, checked_with)))
+            })
+            .await;
+        if checked.is_none() {
+            return;
+        }
+    }
+
+    // The address found above, and the default database of the login
+    let mut login_server = server.clone();
+    login_server.port = Some(address.port());
+    login_server.instance_name = None;
+    login_server.database = None;
+    let Some(mut client) = report
+        .check(Stage::Login, None, async {
+            let client = crate::connections::login(&login_server, tcp).await?;
+            let detail = match &server.authentication {
+                AuthMethod::SqlServer(_) => "SQL Server authentication",
+                _ => "accepted",
+            };
+            Ok((client, detail.to_owned()))
+        })
+        .await
+    else {
+        return;
+    };
+
+    match &server.database {
+        Some(database) => {
+            let opened = report
+                .check(Stage::DatabaseAccess, timeouts.query, async {
+                    client
+                        .execute(format!("USE {}", quote(database)), &[])
+                        .await?;
+                    Ok(((), database.clone()))
+                })
+                .await;
+            if opened.is_none() {
+                return;
+            }
+        }
+        None => report.skip(
+            Stage::DatabaseAccess,
+            "no database, the default one is used",
+        ),
+    }
+
+    let properties = report
+        .check(Stage::RoundTrip, timeouts.query, async {
+            let row = client
+                .simple_query(
+                    "select cast(SERVERPROPERTY('ProductVersion') as nvarchar(128)), \
+                     cast(SERVERPROPERTY('Edition') as nvarchar(128)), \
+                     cast(SERVERPROPERTY('Collation') as nvarchar(128))",
+                )
+                .await?
+                .into_row()
+                .await?
+                .ok_or("the query returned no row")?;
+            let property = |index: usize| row.get::<&str, _>(index).map(str::to_owned);
+            let properties = (property(0), property(1), property(2));
+            Ok((properties, "select SERVERPROPERTY(...)".to_owned()))
+        })
+        .await;
+
+    if let Some((version, edition, collation)) = properties {
+        report.server_version = version;
+        report.edition = edition;
+        report.collation = collation;
+    }
+    let _ = client.close().await;
+}
+
+fn join(addresses: &[SocketAddr]) -> String {
+    addresses
+        .iter()
+        .map(SocketAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub async fn diagnose_connection_with_host_port() -> Result<(), Box<dyn std::error::Error>> {
+    let server = local_server()?;
+
+    let report = diagnose(server).await?;
+    print!("{}", report);
+
+    match report.failed_stage() {
+        Some(stage) => Err(format!("{} failed", stage).into()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tiberius::Client;
+use tokio::net::TcpStream;
+use tokio_util::compat::Compat;
+
+use crate::{ConnectionSpec, ServerConfig};
+
+/// Another server to try when the host can't be reached,
+/// the mirroring partner or a second listener of an availability group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailoverPartner {
+    pub host: String,
+    pub port: Option<u16>,
+    /// A named instance, its port is asked to SQL Server Browser
+    pub instance_name: Option<String>,
+}
+
+impl FailoverPartner {
+    pub fn new(host: &str, port: Option<u16>) -> Self {
+        FailoverPartner {
+            host: host.to_owned(),
+            port,
+            instance_name: None,
+        }
+    }
+}
+
+impl FromStr for FailoverPartner {
+    type Err = String;
+
+    /// `host`, `host,port` or `host\instance` as in the `Failover Partner` of ADO.NET
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("tcp:").unwrap_or(s);
+        let (server, port) = match s.rsplit_once(',') {
+            Some((server, port)) => (
+                server,
+                Some(
+                    port.trim()
+                        .parse()
+                        .map_err(|_| format!("{} is not a port", port))?,
+                ),
+            ),
+            None => (s, None),
+        };
+        let (host, instance_name) = match server.split_once('\\') {
+            Some((host, instance)) => (host, Some(instance.to_owned())),
+            None => (server, None),
+        };
+        if host.is_empty() {
+            return Err(format!("{} has no host", s));
+        }
+
+        Ok(FailoverPartner {
+            host: host.to_owned(),
+            port,
+            instance_name,
+        })
+    }
+}
+
+impl fmt::Display for FailoverPartner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.host)?;
+        if let Some(instance_name) = &self.instance_name {
+            write!(f, "\\{}", instance_name)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ",{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+/// None of the servers could be connected to, the error of each one.
+#[derive(Debug)]
+pub struct FailoverError {
+    pub attempts: Vec<(String, Box<dyn std::error::Error>)>,
+}
+
+impl fmt::Display for FailoverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No server could be connected to")?;
+        for (server, error) in &self.attempts {
+            write!(f, "\n  {}: {}", server, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FailoverError {}
+
+/// Whether the error means the connection is lost or the server is not the primary replica
+/// any more, so the same work may succeed on a new connection.
+pub fn is_failover_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    // The server refused or reset the connection, other I/O errors are not about it
+    if let Some(e) = e.downcast_ref::<std::io::Error>() {
+        return matches!(
+            e.kind(),
+            std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    match e.downcast_ref::<tiberius::error::Error>() {
+        // The socket was closed when the replica went down
+        Some(tiberius::error::Error::Io { .. }) => true,
+        // 976: the database is not accessible on this replica for queries
+        // 978: the secondary replica accepts read-only connections only
+        // 983: the replica is not primary, or resolving its role
+        // 40197, 40613: an Azure SQL database is moving to another node
+        Some(tiberius::error::Error::Server(token)) => {
+            matches!(token.code(), 976 | 978 | 983 | 40197 | 40613)
+        }
+        _ => false,
+    }
+}
+
+pub type FailoverFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, Box<dyn std::error::Error>>> + 'a>>;
+
+/// A connection that is opened again on whichever server is primary now,
+/// when it's lost in a failover.
+pub struct FailoverClient {
+    server: ServerConfig,
+    client: Client<Compat<TcpStream>>,
+    attempts: u32,
+    delay: Duration,
+}
+
+impl FailoverClient {
+    /// Connects as `connect` does, then reconnects 5 times, 2 seconds apart, after a failover.
+    pub async fn connect(
+        spec: impl Into<ConnectionSpec>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let server = spec.into().server_config()?;
+        let client = crate::connect(server.clone()).await?;
+
+        Ok(FailoverClient {
+            server,
+            client,
+            attempts: 5,
+            delay: Duration::from_secs(2),
+        })
+    }
+
+    /// How many times to connect again after a failover, and how long to wait before each time.
+    ///
+    /// The attempts are a total for a `run`: a second failover during the same run
+    /// gets the attempts the first one left.
+    pub fn retries(mut self, attempts: u32, delay: Duration) -> Self {
+        self.attempts = attempts;
+        self.delay = delay;
+        self
+    }
+
+    pub fn client(&mut self) -> &mut Client<Compat<TcpStream>> {
+        &mut self.client
+    }
+
+    pub fn into_client(self) -> Client<Compat<TcpStream>> {
+        self.client
+    }
+
+    /// Runs `work` and, when it fails with a failover error, runs it again on a new connection.
+    ///
+    /// The work runs again from the start: it should read, or write in a transaction
+    /// that was rolled back with the lost connection.
+    pub async fn run<T, F>(&mut self, mut work: F) -> Result<T, Box<dyn std::error::Error>>
+    where
+        F: FnMut(&mut Client<Compat<TcpStream>>) -> FailoverFuture<'_, T>,
+    {
+        // A total for the run, so work that fails on every new connection is not run forever
+        let mut attempt = 0;
+
+        loop {
+            let mut error = match work(&mut self.client).await {
+                Err(e) if is_failover_error(e.as_ref()) => e,
+                result => return result,
+            };
+
+            // The new primary takes a few seconds to accept connections
+            loop {
+                if attempt == self.attempts {
+                    return Err(error);
+                }
+                attempt += 1;
+                tokio::time::sleep(self.delay).await;
+
+                match crate::connect(self.server.clone()).await {
+                    Ok(client) => {
+                        self.client = client;
+                        break;
+                    }
+                    Err(e) if crate::connections::is_login_failure(e.as_ref()) => return Err(e),
+                    Err(e) => error = e,
+                }
+            }
+        }
+    }
+}
@@ -1,29 +1,20 @@
-use tiberius::{AuthMethod, Client, Config, QueryItem};
-use tokio::net::TcpStream;
-use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
-use tokio_stream::StreamExt;
-
-async fn connect_with_host_port() -> Result<Client<Compat<TcpStream>>, Box<dyn std::error::Error>> {
-    let mut config = Config::new();
-    config.authentication(AuthMethod::Integrated);
-    config.host("127.0.0.1");
-    config.port(22828);
-    config.database("FakeAdventureWorks");
-    config.trust_cert();
+use std::time::Duration;
 
-    let tcp = TcpStream::connect(config.get_addr()).await?;
-    let client = Client::connect(config, tcp.compat_write()).await?;
-
-    Ok(client)
-}
-
-pub async fn create_scalar_function() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+use tiberius::{QueryItem, Row};
+use tokio_stream::StreamExt;
 
-    let _ = client
-        .simple_query(
-            r#"
-create function dbo.ufnGetSalesOrderStatusText(@Status tinyint)
+use crate::connections::{connect_to_local_server, local_server};
+use crate::{
+    connect, deploy_object, sales_order_header, ObjectKind, ObjectName, ProgrammableObject,
+    QueryTimeout, SlowQueryLog, SqlValue, StatisticsCapture, TracedClient,
+};
+
+/// Returns the text of a sales order status
+pub fn sales_order_status_text_function(function: &ObjectName) -> ProgrammableObject {
+    ProgrammableObject::new(
+        ObjectKind::ScalarFunction,
+        function,
+        r#"(@Status tinyint)
     returns nvarchar(15)
 as
 -- Returns the sales order status text representation for the status value.
@@ -43,25 +34,51 @@ begin
 
     return @ret
 end
-    "#,
-        )
-        .await;
+"#
+        .to_owned(),
+    )
+}
+
+pub async fn create_scalar_function() -> Result<(), Box<dyn std::error::Error>> {
+    create_scalar_function_named(&ObjectName::new("dbo", "ufnGetSalesOrderStatusText")?).await
+}
 
-    println!("Created scalar function.");
+pub async fn create_scalar_function_named(
+    function: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let object = sales_order_status_text_function(function);
+    let outcome = deploy_object(&mut client, &object).await?;
+
+    println!("{} scalar function.", outcome);
 
     Ok(())
 }
 
 pub async fn call_scalar_function() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    call_scalar_function_named(
+        &ObjectName::new("dbo", "ufnGetSalesOrderStatusText")?,
+        &sales_order_header(),
+    )
+    .await
+}
+
+pub async fn call_scalar_function_named(
+    function: &ObjectName,
+    table: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
 
     let mut query = client
         .query(
-            r#"
-SELECT SalesOrderID, Status, dbo.ufnGetSalesOrderStatusText(Status) AS StatusDescription
-FROM dbo.SalesOrderHeader
+            format!(
+                r#"
+SELECT SalesOrderID, Status, {function}(Status) AS StatusDescription
+FROM {table}
 WHERE SalesOrderID = @P1
-    "#,
+    "#
+            ),
             &[&2], // SalesOrderID
         )
         .await?;
@@ -81,13 +98,16 @@ WHERE SalesOrderID = @P1
     Ok(())
 }
 
-pub async fn create_table_valued_function() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
-
-    let _ = client
-        .simple_query(
-            r#"
-create function dbo.ufnGetSalesOrderWithTotalDueMoreThan(@TotalDue money)
+/// Returns the sales orders with a total due above the given value
+pub fn sales_orders_with_total_due_more_than_function(
+    function: &ObjectName,
+    table: &ObjectName,
+) -> ProgrammableObject {
+    ProgrammableObject::new(
+        ObjectKind::TableValuedFunction,
+        function,
+        format!(
+            r#"(@TotalDue money)
     returns table
         as
         return
@@ -96,54 +116,138 @@ create function dbo.ufnGetSalesOrderWithTotalDueMoreThan(@TotalDue money)
                TaxAmt,
                Freight,
                TotalDue
-        from dbo.SalesOrderHeader
+        from {table}
         where
             TotalDue > @TotalDue
-    "#,
-        )
-        .await?;
+"#
+        ),
+    )
+}
+
+pub async fn create_table_valued_function() -> Result<(), Box<dyn std::error::Error>> {
+    create_table_valued_function_named(
+        &ObjectName::new("dbo", "ufnGetSalesOrderWithTotalDueMoreThan")?,
+        &sales_order_header(),
+    )
+    .await
+}
+
+pub async fn create_table_valued_function_named(
+    function: &ObjectName,
+    table: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let object = sales_orders_with_total_due_more_than_function(function, table);
+    let outcome = deploy_object(&mut client, &object).await?;
 
-    println!("Created table valued function.");
+    println!("{} table valued function.", outcome);
 
     Ok(())
 }
 
 pub async fn call_table_valued_function() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    call_table_valued_function_named(&ObjectName::new(
+        "dbo",
+        "ufnGetSalesOrderWithTotalDueMoreThan",
+    )?)
+    .await
+}
 
-    let due = -1;
+pub async fn call_table_valued_function_named(
+    function: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut server = local_server()?;
+    server.timeouts.query = Some(Duration::from_secs(30));
+
+    let mut client = connect(server.clone()).await?;
+    let timeout = QueryTimeout::new(&server.timeouts);
+
+    let due = SqlValue::money(-1.0);
+
+    // The query is given up when the rows take more than 30 seconds, its connection
+    // is then dropped with the error
+    timeout
+        .run(async {
+            // Query the Sql Server function as if it was a normal table
+            // using the `select` statement
+            let mut query = client
+                .query(total_due_more_than_query(function), &[&due]) // TotalDue
+                .await?;
+
+            // Iterate over the result set
+            while let Some(row) = query.try_next().await? {
+                if let QueryItem::Row(r) = row {
+                    print_sales_order_totals(&r);
+                }
+            }
+
+            Ok(())
+        })
+        .await
+}
 
-    // Query the Sql Server function as if it was a normal table
-    // using the `select` statement
-    let mut query = client
-        .query(
-            r#"
+/// Calls the table valued function as a read of a traced connection: a listener routes it
+/// to a readable secondary replica, and it's logged as a slow query, with its reads,
+/// when it takes more than half a second.
+pub async fn call_table_valued_function_traced() -> Result<(), Box<dyn std::error::Error>> {
+    let function = ObjectName::new("dbo", "ufnGetSalesOrderWithTotalDueMoreThan")?;
+
+    let mut server = local_server()?;
+    server.timeouts.query = Some(Duration::from_secs(30));
+    server.readonly = true;
+
+    let client = connect(server.clone()).await?;
+    let timeout = QueryTimeout::new(&server.timeouts);
+    let mut client = TracedClient::new(client, &server.host, server.database.as_deref())
+        .slow_queries(
+            SlowQueryLog::new(Duration::from_millis(500)).statistics(StatisticsCapture::Rerun),
+        );
+
+    let due = SqlValue::money(-1.0);
+    let sql = total_due_more_than_query(&function);
+
+    // The rows of the first result are read before they are returned
+    timeout
+        .run(async {
+            for r in client.query(&sql, &[&due]).await? {
+                print_sales_order_totals(&r);
+            }
+
+            Ok(())
+        })
+        .await?;
+
+    // Once the query is done, a slow one runs again for its reads, out of the 30 seconds
+    client.rerun_slow_query(&sql, &[&due]).await?;
+
+    Ok(())
+}
+
+// The rows of the table valued function with a total due above @P1
+fn total_due_more_than_query(function: &ObjectName) -> String {
+    format!(
+        r#"
 select SalesOrderID,
        SubTotal,
        TaxAmt,
        Freight,
        TotalDue
-from dbo.ufnGetSalesOrderWithTotalDueMoreThan(@P1)
+from {}(@P1)
     "#,
-            &[&due], // TotalDue
-        )
-        .await?;
-
-    // Iterate over the result set
-    while let Some(row) = query.try_next().await? {
-        if let QueryItem::Row(r) = row {
-            let sales_order_id: i32 = r.get("SalesOrderID").unwrap();
-            let subtotal: f64 = r.get("SubTotal").unwrap();
-            let tax_amt: f64 = r.get("TaxAmt").unwrap();
-            let freight: f64 = r.get("Freight").unwrap();
-            let total_due: f64 = r.get("TotalDue").unwrap();
-            println!("Sale order with ID: {}", sales_order_id);
-            println!("Subtotal: {}", subtotal);
-            println!("Tax amount: {}", tax_amt);
-            println!("Freight: {}", freight);
-            println!("Total due: {}", total_due);
-        }
-    }
+        function
+    )
+}
 
-    Ok(())
-}
\ No newline at end of file
+fn print_sales_order_totals(r: &Row) {
+    let sales_order_id: i32 = r.get("SalesOrderID").unwrap();
+    let subtotal: f64 = r.get("SubTotal").unwrap();
+    let tax_amt: f64 = r.get("TaxAmt").unwrap();
+    let freight: f64 = r.get("Freight").unwrap();
+    let total_due: f64 = r.get("TotalDue").unwrap();
+    println!("Sale order with ID: {}", sales_order_id);
+    println!("Subtotal: {}", subtotal);
+    println!("Tax amount: {}", tax_amt);
+    println!("Freight: {}", freight);
+    println!("Total due: {}", total_due);
+}
@@ -0,0 +1,283 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+// Longest name SQL Server accepts (sysname), QUOTENAME returns NULL above it
+const MAX_IDENTIFIER_LENGTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidIdentifier {
+    name: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for InvalidIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid identifier `{}`: {}", self.name, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidIdentifier {}
+
+fn invalid(name: &str, reason: &'static str) -> InvalidIdentifier {
+    InvalidIdentifier {
+        name: name.to_owned(),
+        reason,
+    }
+}
+
+/// Quotes a name the way `QUOTENAME` does: `Sales]Order` becomes `[Sales]]Order]`
+pub(crate) fn quote(name: &str) -> String {
+    format!("[{}]", name.replace(']', "]]"))
+}
+
+/// The name of a schema, table, column, procedure or any other database object.
+///
+/// Any name can be used because it's always quoted when embedded in T-SQL.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Identifier(String);
+
+impl Identifier {
+    pub fn new(name: impl Into<String>) -> Result<Self, InvalidIdentifier> {
+        let name = name.into();
+
+        if name.is_empty() {
+            return Err(invalid(&name, "it is empty"));
+        }
+
+        if name.chars().count() > MAX_IDENTIFIER_LENGTH {
+            return Err(invalid(&name, "it is longer than 128 characters"));
+        }
+
+        if name.contains('\0') {
+            return Err(invalid(&name, "it contains a NUL character"));
+        }
+
+        Ok(Identifier(name))
+    }
+
+    /// The name as it's stored in the catalog, without brackets
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The name between brackets, ready to be embedded in T-SQL
+    pub fn quoted(&self) -> String {
+        quote(&self.0)
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.quoted())
+    }
+}
+
+/// A name with up to four parts: `server.database.schema.object`.
+///
+/// Parts other than the object can be omitted, like in `dbo.SalesOrderHeader`
+/// or `FakeAdventureWorks..SalesOrderHeader` (default schema).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ObjectName {
+    server: Option<Identifier>,
+    database: Option<Identifier>,
+    schema: Option<Identifier>,
+    object: Identifier,
+}
+
+impl ObjectName {
+    /// A schema qualified name like `dbo.SalesOrderHeader`
+    pub fn new(schema: &str, object: &str) -> Result<Self, InvalidIdentifier> {
+        Ok(ObjectName {
+            server: None,
+            database: None,
+            schema: Some(Identifier::new(schema)?),
+            object: Identifier::new(object)?,
+        })
+    }
+
+    /// Parses a multipart name where each part may be a regular identifier
+    /// or be delimited with brackets or double quotes:
+    /// `[Linked Server].FakeAdventureWorks.dbo."Sales.Order"`
+    pub fn parse(name: &str) -> Result<Self, InvalidIdentifier> {
+        let parts = split_parts(name)?;
+
+        if parts.len() > 4 {
+            return Err(invalid(name, "it has more than four parts"));
+        }
+
+        let mut parts = parts.into_iter().rev();
+        let object = match parts.next() {
+            Some(Some(object)) => object,
+            _ => return Err(invalid(name, "the object name is missing")),
+        };
+        let schema = parts.next().flatten();
+        let database = parts.next().flatten();
+        let server = parts.next().flatten();
+
+        if server.is_some() && database.is_none() {
+            return Err(invalid(name, "the database name is missing"));
+        }
+
+        Ok(ObjectName {
+            server,
+            database,
+            schema,
+            object,
+        })
+    }
+
+    pub fn in_database(mut self, database: &str) -> Result<Self, InvalidIdentifier> {
+        self.database = Some(Identifier::new(database)?);
+        Ok(self)
+    }
+
+    pub fn on_server(mut self, server: &str) -> Result<Self, InvalidIdentifier> {
+        self.server = Some(Identifier::new(server)?);
+        Ok(self)
+    }
+
+    pub fn server(&self) -> Option<&Identifier> {
+        self.server.as_ref()
+    }
+
+    pub fn database(&self) -> Option<&Identifier> {
+        self.database.as_ref()
+    }
+
+    pub fn schema(&self) -> Option<&Identifier> {
+        self.schema.as_ref()
+    }
+
+    pub fn object(&self) -> &Identifier {
+        &self.object
+    }
+
+    /// The name with every part quoted, `[dbo].[SalesOrderHeader]` for instance
+    pub fn quoted(&self) -> String {
+        let parts = [&self.server, &self.database, &self.schema];
+        let first = parts
+            .iter()
+            .position(|p| p.is_some())
+            .unwrap_or(parts.len());
+
+        // Omitted parts in the middle are left empty: [db]..[object]
+        let mut quoted = parts[first..]
+            .iter()
+            .map(|part| part.as_ref().map(Identifier::quoted).unwrap_or_default())
+            .collect::<Vec<_>>();
+        quoted.push(self.object.quoted());
+
+        quoted.join(".")
+    }
+
+    /// Creates a name in the same schema (and database) with another object name,
+    /// used to name constraints after their table
+    pub fn sibling(&self, object: &str) -> Result<Self, InvalidIdentifier> {
+        Ok(ObjectName {
+            object: Identifier::new(object)?,
+            ..self.clone()
+        })
+    }
+}
+
+impl fmt::Display for ObjectName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.quoted())
+    }
+}
+
+impl FromStr for ObjectName {
+    type Err = InvalidIdentifier;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ObjectName::parse(s)
+    }
+}
+
+// Kept in snapshots as the quoted name, `"[dbo].[SalesOrderHeader]"`
+impl Serialize for ObjectName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.quoted())
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        ObjectName::parse(&name).map_err(de::Error::custom)
+    }
+}
+
+// Splits a multipart name on the dots that are not inside delimiters,
+// empty parts are `None`
+fn split_parts(name: &str) -> Result<Vec<Option<Identifier>>, InvalidIdentifier> {
+    let mut parts = Vec::new();
+    let mut chars = name.chars().peekable();
+
+    loop {
+        let mut part = String::new();
+
+        match chars.peek() {
+            Some('[') | Some('"') => {
+                let close = if chars.next() == Some('[') { ']' } else { '"' };
+
+                loop {
+                    match chars.next() {
+                        // A doubled delimiter is an escaped one
+                        Some(c) if c == close && chars.peek() == Some(&close) => {
+                            chars.next();
+                            part.push(c);
+                        }
+                        Some(c) if c == close => break,
+                        Some(c) => part.push(c),
+                        None => return Err(invalid(name, "a delimited part is not closed")),
+                    }
+                }
+
+                parts.push(Some(Identifier::new(part)?));
+            }
+            _ => {
+                while let Some(&c) = chars.peek() {
+                    if c == '.' {
+                        break;
+                    }
+                    part.push(c);
+                    chars.next();
+                }
+
+                if part.is_empty() {
+                    parts.push(None);
+                } else if is_regular_identifier(&part) {
+                    parts.push(Some(Identifier::new(part)?));
+                } else {
+                    return Err(invalid(
+                        name,
+                        "a part must be delimited with brackets or double quotes",
+                    ));
+                }
+            }
+        }
+
+        match chars.next() {
+            Some('.') => continue,
+            None => break,
+            Some(_) => return Err(invalid(name, "a delimited part is followed by text")),
+        }
+    }
+
+    Ok(parts)
+}
+
+// Rules of regular identifiers, the ones that can be used without delimiters
+fn is_regular_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' || c == '@' || c == '#' => {}
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '$'))
+}
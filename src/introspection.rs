@@ -0,0 +1,543 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tiberius::{Client, Row};
+use tokio::net::TcpStream;
+use tokio_util::compat::Compat;
+
+use crate::connections::connect_to_local_server;
+use crate::pagination::required;
+use crate::{sales_order_header, ObjectName};
+
+/// The layout of a table as recorded in the catalog views.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableSchema {
+    pub name: ObjectName,
+    /// In the order of `column_id`
+    pub columns: Vec<Column>,
+    pub indexes: Vec<Index>,
+    pub check_constraints: Vec<CheckConstraint>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+impl TableSchema {
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn primary_key(&self) -> Option<&Index> {
+        self.indexes.iter().find(|i| i.is_primary_key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Column {
+    pub column_id: i32,
+    pub name: String,
+    pub data_type: DataType,
+    pub is_nullable: bool,
+    pub collation: Option<String>,
+    pub identity: Option<Identity>,
+    pub computed: Option<ComputedColumn>,
+    pub default: Option<DefaultConstraint>,
+}
+
+/// A type from `sys.types` with the size of the column.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataType {
+    pub name: String,
+    /// Size in bytes, -1 for `max` types
+    pub max_length: i16,
+    pub precision: u8,
+    pub scale: u8,
+}
+
+impl fmt::Display for DataType {
+    /// The type as written in a column definition, `nvarchar(128)` for instance
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let length = |bytes: i16| {
+            if self.max_length == -1 {
+                "max".to_owned()
+            } else {
+                (self.max_length / bytes).to_string()
+            }
+        };
+
+        match self.name.as_str() {
+            "char" | "varchar" | "binary" | "varbinary" => {
+                write!(f, "{}({})", self.name, length(1))
+            }
+            // Unicode types take two bytes per character
+            "nchar" | "nvarchar" => write!(f, "{}({})", self.name, length(2)),
+            "decimal" | "numeric" => write!(f, "{}({}, {})", self.name, self.precision, self.scale),
+            "datetime2" | "datetimeoffset" | "time" => write!(f, "{}({})", self.name, self.scale),
+            _ => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    pub seed: i64,
+    pub increment: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComputedColumn {
+    pub definition: String,
+    pub is_persisted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefaultConstraint {
+    pub name: String,
+    /// The constraint was declared without a name
+    pub is_system_named: bool,
+    pub definition: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Index {
+    pub index_id: i32,
+    pub name: String,
+    /// `type_desc` of `sys.indexes`: CLUSTERED, NONCLUSTERED, ...
+    pub kind: String,
+    pub is_primary_key: bool,
+    pub is_unique: bool,
+    pub is_unique_constraint: bool,
+    /// Key columns in key order followed by the included columns
+    pub columns: Vec<IndexColumn>,
+}
+
+impl Index {
+    pub fn is_clustered(&self) -> bool {
+        self.kind.starts_with("CLUSTERED")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexColumn {
+    pub name: String,
+    pub is_descending: bool,
+    pub is_included: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckConstraint {
+    pub name: String,
+    pub definition: String,
+    /// Set for constraints declared with a column
+    pub column: Option<String>,
+    pub is_disabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForeignKey {
+    pub name: String,
+    pub referenced_table: ObjectName,
+    pub columns: Vec<ForeignKeyColumn>,
+    pub on_delete: ReferentialAction,
+    pub on_update: ReferentialAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForeignKeyColumn {
+    pub column: String,
+    pub referenced_column: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferentialAction {
+    NoAction,
+    Cascade,
+    SetNull,
+    SetDefault,
+}
+
+impl ReferentialAction {
+    fn from_desc(desc: &str) -> tiberius::Result<Self> {
+        match desc {
+            "NO_ACTION" => Ok(ReferentialAction::NoAction),
+            "CASCADE" => Ok(ReferentialAction::Cascade),
+            "SET_NULL" => Ok(ReferentialAction::SetNull),
+            "SET_DEFAULT" => Ok(ReferentialAction::SetDefault),
+            _ => Err(tiberius::error::Error::Conversion(
+                format!("Unknown referential action {}", desc).into(),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReferentialAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReferentialAction::NoAction => write!(f, "no action"),
+            ReferentialAction::Cascade => write!(f, "cascade"),
+            ReferentialAction::SetNull => write!(f, "set null"),
+            ReferentialAction::SetDefault => write!(f, "set default"),
+        }
+    }
+}
+
+fn text(row: &Row, column: &str) -> tiberius::Result<String> {
+    required::<&str>(row, column).map(str::to_owned)
+}
+
+fn optional_text(row: &Row, column: &str) -> tiberius::Result<Option<String>> {
+    Ok(row.try_get::<&str, _>(column)?.map(str::to_owned))
+}
+
+/// Lists the user tables of the current database.
+pub async fn list_tables(
+    client: &mut Client<Compat<TcpStream>>,
+) -> Result<Vec<ObjectName>, Box<dyn std::error::Error>> {
+    let rows = client
+        .simple_query(
+            r#"
+select schema_name(schema_id) as SchemaName, name
+from sys.tables
+where is_ms_shipped = 0
+order by SchemaName, name
+        "#,
+        )
+        .await?
+        .into_first_result()
+        .await?;
+
+    let mut tables = Vec::with_capacity(rows.len());
+    for row in &rows {
+        tables.push(ObjectName::new(
+            required(row, "SchemaName")?,
+            required(row, "name")?,
+        )?);
+    }
+
+    Ok(tables)
+}
+
+/// Reads the layout of `table` from the catalog views of the current database,
+/// `None` if there is no such table.
+pub async fn describe_table(
+    client: &mut Client<Compat<TcpStream>>,
+    table: &ObjectName,
+) -> Result<Option<TableSchema>, Box<dyn std::error::Error>> {
+    let object_id: Option<i32> = client
+        .query(
+            "select object_id from sys.tables where object_id = object_id(@P1)",
+            &[&table.to_string()],
+        )
+        .await?
+        .into_row()
+        .await?
+        .and_then(|row| row.get(0));
+
+    let object_id = match object_id {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+
+    Ok(Some(TableSchema {
+        name: table.clone(),
+        columns: read_columns(client, object_id).await?,
+        indexes: read_indexes(client, object_id).await?,
+        check_constraints: read_check_constraints(client, object_id).await?,
+        foreign_keys: read_foreign_keys(client, object_id).await?,
+    }))
+}
+
+async fn read_columns(
+    client: &mut Client<Compat<TcpStream>>,
+    object_id: i32,
+) -> Result<Vec<Column>, Box<dyn std::error::Error>> {
+    // Identity values are sql_variant, the cast makes them readable
+    let rows = client
+        .query(
+            r#"
+select c.column_id,
+       c.name,
+       t.name                               as type_name,
+       c.max_length,
+       c.precision,
+       c.scale,
+       c.is_nullable,
+       c.collation_name,
+       cast(ic.seed_value as bigint)      as seed_value,
+       cast(ic.increment_value as bigint) as increment_value,
+       cc.definition                        as computed_definition,
+       cc.is_persisted,
+       dc.name                              as default_name,
+       dc.is_system_named                   as default_system_named,
+       dc.definition                        as default_definition
+from sys.columns c
+     join sys.types t on t.user_type_id = c.user_type_id
+     left join sys.identity_columns ic on ic.object_id = c.object_id and ic.column_id = c.column_id
+     left join sys.computed_columns cc on cc.object_id = c.object_id and cc.column_id = c.column_id
+     left join sys.default_constraints dc on dc.object_id = c.default_object_id
+where c.object_id = @P1
+order by c.column_id
+            "#,
+            &[&object_id],
+        )
+        .await?
+        .into_first_result()
+        .await?;
+
+    let mut columns = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let identity = match row.try_get::<i64, _>("seed_value")? {
+            Some(seed) => Some(Identity {
+                seed,
+                increment: required(row, "increment_value")?,
+            }),
+            None => None,
+        };
+
+        let computed = match optional_text(row, "computed_definition")? {
+            Some(definition) => Some(ComputedColumn {
+                definition,
+                is_persisted: required(row, "is_persisted")?,
+            }),
+            None => None,
+        };
+
+        let default = match optional_text(row, "default_name")? {
+            Some(name) => Some(DefaultConstraint {
+                name,
+                is_system_named: required(row, "default_system_named")?,
+                definition: text(row, "default_definition")?,
+            }),
+            None => None,
+        };
+
+        columns.push(Column {
+            column_id: required(row, "column_id")?,
+            name: text(row, "name")?,
+            data_type: DataType {
+                name: text(row, "type_name")?,
+                max_length: required(row, "max_length")?,
+                precision: required(row, "precision")?,
+                scale: required(row, "scale")?,
+            },
+            is_nullable: row.try_get("is_nullable")?.unwrap_or(true),
+            collation: optional_text(row, "collation_name")?,
+            identity,
+            computed,
+            default,
+        });
+    }
+
+    Ok(columns)
+}
+
+async fn read_indexes(
+    client: &mut Client<Compat<TcpStream>>,
+    object_id: i32,
+) -> Result<Vec<Index>, Box<dyn std::error::Error>> {
+    // Heaps have a row in sys.indexes with type 0, they are not indexes
+    let rows = client
+        .query(
+            r#"
+select i.index_id,
+       i.name,
+       i.type_desc,
+       i.is_primary_key,
+       i.is_unique,
+       i.is_unique_constraint,
+       c.name as column_name,
+       ic.is_descending_key,
+       ic.is_included_column
+from sys.indexes i
+     join sys.index_columns ic on ic.object_id = i.object_id and ic.index_id = i.index_id
+     join sys.columns c on c.object_id = ic.object_id and c.column_id = ic.column_id
+where i.object_id = @P1
+  and i.type > 0
+order by i.index_id, ic.is_included_column, ic.key_ordinal, ic.index_column_id
+            "#,
+            &[&object_id],
+        )
+        .await?
+        .into_first_result()
+        .await?;
+
+    let mut indexes: Vec<Index> = Vec::new();
+    for row in &rows {
+        let index_id: i32 = required(row, "index_id")?;
+        let column = IndexColumn {
+            name: text(row, "column_name")?,
+            is_descending: required(row, "is_descending_key")?,
+            is_included: required(row, "is_included_column")?,
+        };
+
+        // The rows of an index come together, one for each of its columns
+        match indexes.last_mut() {
+            Some(index) if index.index_id == index_id => index.columns.push(column),
+            _ => indexes.push(Index {
+                index_id,
+                name: text(row, "name")?,
+                kind: text(row, "type_desc")?,
+                is_primary_key: required(row, "is_primary_key")?,
+                is_unique: required(row, "is_unique")?,
+                is_unique_constraint: required(row, "is_unique_constraint")?,
+                columns: vec![column],
+            }),
+        }
+    }
+
+    Ok(indexes)
+}
+
+async fn read_check_constraints(
+    client: &mut Client<Compat<TcpStream>>,
+    object_id: i32,
+) -> Result<Vec<CheckConstraint>, Box<dyn std::error::Error>> {
+    let rows = client
+        .query(
+            r#"
+select cc.name,
+       cc.definition,
+       c.name as column_name,
+       cc.is_disabled
+from sys.check_constraints cc
+     left join sys.columns c on c.object_id = cc.parent_object_id and c.column_id = cc.parent_column_id
+where cc.parent_object_id = @P1
+order by cc.name
+            "#,
+            &[&object_id],
+        )
+        .await?
+        .into_first_result()
+        .await?;
+
+    let mut constraints = Vec::with_capacity(rows.len());
+    for row in &rows {
+        constraints.push(CheckConstraint {
+            name: text(row, "name")?,
+            definition: text(row, "definition")?,
+            column: optional_text(row, "column_name")?,
+            is_disabled: required(row, "is_disabled")?,
+        });
+    }
+
+    Ok(constraints)
+}
+
+async fn read_foreign_keys(
+    client: &mut Client<Compat<TcpStream>>,
+    object_id: i32,
+) -> Result<Vec<ForeignKey>, Box<dyn std::error::Error>> {
+    let rows = client
+        .query(
+            r#"
+select fk.object_id,
+       fk.name,
+       schema_name(rt.schema_id) as referenced_schema,
+       rt.name                   as referenced_table,
+       pc.name                   as column_name,
+       rc.name                   as referenced_column,
+       fk.delete_referential_action_desc,
+       fk.update_referential_action_desc
+from sys.foreign_keys fk
+     join sys.foreign_key_columns fkc on fkc.constraint_object_id = fk.object_id
+     join sys.tables rt on rt.object_id = fk.referenced_object_id
+     join sys.columns pc on pc.object_id = fkc.parent_object_id and pc.column_id = fkc.parent_column_id
+     join sys.columns rc on rc.object_id = fkc.referenced_object_id and rc.column_id = fkc.referenced_column_id
+where fk.parent_object_id = @P1
+order by fk.name, fkc.constraint_column_id
+            "#,
+            &[&object_id],
+        )
+        .await?
+        .into_first_result()
+        .await?;
+
+    let mut foreign_keys: Vec<(i32, ForeignKey)> = Vec::new();
+    for row in &rows {
+        let constraint_id: i32 = required(row, "object_id")?;
+        let column = ForeignKeyColumn {
+            column: text(row, "column_name")?,
+            referenced_column: text(row, "referenced_column")?,
+        };
+
+        match foreign_keys.last_mut() {
+            Some((id, foreign_key)) if *id == constraint_id => foreign_key.columns.push(column),
+            _ => foreign_keys.push((
+                constraint_id,
+                ForeignKey {
+                    name: text(row, "name")?,
+                    referenced_table: ObjectName::new(
+                        required(row, "referenced_schema")?,
+                        required(row, "referenced_table")?,
+                    )?,
+                    columns: vec![column],
+                    on_delete: ReferentialAction::from_desc(required(
+                        row,
+                        "delete_referential_action_desc",
+                    )?)?,
+                    on_update: ReferentialAction::from_desc(required(
+                        row,
+                        "update_referential_action_desc",
+                    )?)?,
+                },
+            )),
+        }
+    }
+
+    Ok(foreign_keys.into_iter().map(|(_, fk)| fk).collect())
+}
+
+pub async fn describe_sales_order_header() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let table = sales_order_header();
+    let schema = describe_table(&mut client, &table)
+        .await?
+        .ok_or_else(|| format!("Table {} not found", table))?;
+
+    println!("Table: {}", schema.name);
+    for column in &schema.columns {
+        print!(
+            "  {} {}{}",
+            column.name,
+            column.data_type,
+            if column.is_nullable { "" } else { " not null" }
+        );
+        if let Some(identity) = &column.identity {
+            print!(" identity({}, {})", identity.seed, identity.increment);
+        }
+        if let Some(computed) = &column.computed {
+            print!(" as {}", computed.definition);
+        }
+        if let Some(default) = &column.default {
+            print!(" default {}", default.definition);
+        }
+        println!();
+    }
+
+    for index in &schema.indexes {
+        let columns: Vec<&str> = index.columns.iter().map(|c| c.name.as_str()).collect();
+        println!(
+            "Index {} ({}): {}",
+            index.name,
+            index.kind,
+            columns.join(", ")
+        );
+    }
+
+    for constraint in &schema.check_constraints {
+        println!("Check {}: {}", constraint.name, constraint.definition);
+    }
+
+    for foreign_key in &schema.foreign_keys {
+        println!(
+            "Foreign key {} references {}",
+            foreign_key.name, foreign_key.referenced_table
+        );
+    }
+
+    client.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// The buckets of the histograms in seconds, from 5 ms to 10 s
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Where the metrics of the connections, pools and queries go.
+///
+/// The labels are `(name, value)` pairs. A recorder for another metrics library forwards
+/// the calls, `PrometheusRecorder` keeps them and renders them for Prometheus.
+pub trait MetricsRecorder: Send + Sync {
+    fn increment_counter(&self, name: &str, labels: &[(&str, &str)], value: u64);
+    fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64);
+    fn record_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64);
+}
+
+static RECORDER: RwLock<Option<Arc<dyn MetricsRecorder>>> = RwLock::new(None);
+
+/// Sends the metrics to `recorder` from now on, nothing is recorded until it's set.
+pub fn set_metrics_recorder(recorder: Arc<dyn MetricsRecorder>) {
+    *RECORDER.write().unwrap() = Some(recorder);
+}
+
+pub fn clear_metrics_recorder() {
+    *RECORDER.write().unwrap() = None;
+}
+
+fn with_recorder(record: impl FnOnce(&dyn MetricsRecorder)) {
+    if let Some(recorder) = RECORDER.read().unwrap().as_deref() {
+        record(recorder);
+    }
+}
+
+/// The time it took to get a connection, `sql_connection_acquire_seconds`, and its error
+/// in `sql_errors_total` when it failed.
+pub fn record_connection_acquired(
+    server: &str,
+    duration: Duration,
+    error: Option<&(dyn std::error::Error + 'static)>,
+) {
+    let outcome = if error.is_some() { "error" } else { "ok" };
+    with_recorder(|recorder| {
+        recorder.record_histogram(
+            "sql_connection_acquire_seconds",
+            &[("server", server), ("outcome", outcome)],
+            duration.as_secs_f64(),
+        )
+    });
+    if let Some(e) = error {
+        record_error(e);
+    }
+}
+
+/// The connections of a pool: `sql_pool_size`, `sql_pool_idle` and `sql_pool_in_use`.
+///
+/// The crate opens connections without a pool, the pool the application uses, bb8 or
+/// deadpool for instance, reports its state here when it hands out a connection.
+pub fn record_pool_state(pool: &str, size: u32, idle: u32, in_use: u32) {
+    with_recorder(|recorder| {
+        let labels = [("pool", pool)];
+        recorder.set_gauge("sql_pool_size", &labels, size as f64);
+        recorder.set_gauge("sql_pool_idle", &labels, idle as f64);
+        recorder.set_gauge("sql_pool_in_use", &labels, in_use as f64);
+    });
+}
+
+/// How long a statement took, `sql_query_duration_seconds`, and the rows it returned
+/// or changed, `sql_rows_returned_total` and `sql_rows_affected_total`.
+///
+/// The statement is a name, the procedure or the operation, not the SQL: each label
+/// value is a new series.
+pub fn record_query(statement: &str, duration: Duration, rows_returned: u64, rows_affected: u64) {
+    with_recorder(|recorder| {
+        let labels = [("statement", statement)];
+        recorder.record_histogram(
+            "sql_query_duration_seconds",
+            &labels,
+            duration.as_secs_f64(),
+        );
+        recorder.increment_counter("sql_rows_returned_total", &labels, rows_returned);
+        recorder.increment_counter("sql_rows_affected_total", &labels, rows_affected);
+    });
+}
+
+/// Counts the error in `sql_errors_total` by the number of the SQL Server error,
+/// `none` when the error does not come from the server.
+pub fn record_error(e: &(dyn std::error::Error + 'static)) {
+    let number = match sql_error_number(e) {
+        Some(number) => number.to_string(),
+        None => "none".to_owned(),
+    };
+    with_recorder(|recorder| {
+        recorder.increment_counter("sql_errors_total", &[("number", &number)], 1)
+    });
+}
+
+/// The number of a SQL Server error, 18456 for a login failure for instance.
+pub fn sql_error_number(e: &(dyn std::error::Error + 'static)) -> Option<u32> {
+    match e.downcast_ref::<tiberius::error::Error>() {
+        Some(tiberius::error::Error::Server(token)) => Some(token.code()),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(u64),
+    Gauge(f64),
+    Histogram {
+        // Not cumulative, one count per bucket and the last one for +Inf
+        counts: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram { .. } => "histogram",
+        }
+    }
+}
+
+/// Keeps the metrics in memory and renders them in the Prometheus text format.
+#[derive(Default)]
+pub struct PrometheusRecorder {
+    // By name, then by the rendered labels
+    metrics: Mutex<BTreeMap<String, BTreeMap<String, Metric>>>,
+}
+
+impl PrometheusRecorder {
+    pub fn new() -> Self {
+        PrometheusRecorder::default()
+    }
+
+    fn update(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        new: Metric,
+        update: impl FnOnce(&mut Metric),
+    ) {
+        let mut metrics = self.metrics.lock().unwrap();
+        let series = metrics.entry(name.to_owned()).or_default();
+        let metric = series.entry(render_labels(labels)).or_insert(new);
+        update(metric);
+    }
+
+    /// All the metrics, as Prometheus scrapes them from `/metrics`
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().unwrap();
+        let mut text = String::new();
+
+        for (name, series) in metrics.iter() {
+            let Some(kind) = series.values().next().map(Metric::kind) else {
+                continue;
+            };
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+
+            for (labels, metric) in series {
+                match metric {
+                    Metric::Counter(value) => {
+                        let _ = writeln!(text, "{}{} {}", name, braces(labels), value);
+                    }
+                    Metric::Gauge(value) => {
+                        let _ = writeln!(text, "{}{} {}", name, braces(labels), value);
+                    }
+                    Metric::Histogram { counts, sum, count } => {
+                        let bounds = LATENCY_BUCKETS.iter().map(f64::to_string);
+                        let mut cumulative = 0;
+                        for (bound, bucket) in bounds.chain(["+Inf".to_owned()]).zip(counts) {
+                            cumulative += bucket;
+                            let le = format!("le=\"{}\"", bound);
+                            let labels = if labels.is_empty() {
+                                le
+                            } else {
+                                format!("{},{}", labels, le)
+                            };
+                            let _ = writeln!(text, "{}_bucket{{{}}} {}", name, labels, cumulative);
+                        }
+                        let _ = writeln!(text, "{}_sum{} {}", name, braces(labels), sum);
+                        let _ = writeln!(text, "{}_count{} {}", name, braces(labels), count);
+                    }
+                }
+            }
+        }
+
+        text
+    }
+
+    /// Answers every HTTP request on `listener` with the metrics, until the task is dropped.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (mut stream, _) = listener.accept().await?;
+            let recorder = self.clone();
+            tokio::spawn(async move {
+                // The request is read, whatever its path
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let body = recorder.render();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\n\
+                     Content-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    }
+}
+
+impl MetricsRecorder for PrometheusRecorder {
+    fn increment_counter(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.update(name, labels, Metric::Counter(0), |metric| {
+            if let Metric::Counter(total) = metric {
+                *total += value;
+            }
+        });
+    }
+
+    fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.update(name, labels, Metric::Gauge(value), |metric| {
+            *metric = Metric::Gauge(value);
+        });
+    }
+
+    fn record_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let empty = Metric::Histogram {
+            counts: vec![0; LATENCY_BUCKETS.len() + 1],
+            sum: 0.0,
+            count: 0,
+        };
+        self.update(name, labels, empty, |metric| {
+            if let Metric::Histogram { counts, sum, count } = metric {
+                let bucket = LATENCY_BUCKETS
+                    .iter()
+                    .position(|bound| value <= *bound)
+                    .unwrap_or(LATENCY_BUCKETS.len());
+                counts[bucket] += 1;
+                *sum += value;
+                *count += 1;
+            }
+        });
+    }
+}
+
+// `server="127.0.0.1",outcome="ok"`, sorted so the same labels are the same series
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    let mut labels = labels.to_vec();
+    labels.sort();
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn braces(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
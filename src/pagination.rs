@@ -0,0 +1,436 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+use tiberius::{Client, Row};
+use tokio::net::TcpStream;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::compat::Compat;
+
+use crate::connections::connect_to_local_server;
+use crate::{sales_order_header, Condition, ObjectName, Select, SqlValue};
+
+// Format used to keep `OrderDate` values inside a continuation token
+const TOKEN_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// A row of `dbo.SalesOrderHeader` or a table with the same columns.
+#[derive(Debug, Clone)]
+pub struct SalesOrder {
+    pub sales_order_id: i32,
+    pub revision_number: u8,
+    pub order_date: NaiveDateTime,
+    pub due_date: NaiveDateTime,
+    pub ship_date: Option<NaiveDateTime>,
+    pub status: u8,
+    pub sales_order_number: String,
+    pub credit_card_approval_code: Option<String>,
+    pub sub_total: f64,
+    pub tax_amt: f64,
+    pub freight: f64,
+    pub total_due: f64,
+    pub comment: Option<String>,
+    pub rowguid: uuid::Uuid,
+    pub modified_date: Option<NaiveDateTime>,
+}
+
+impl SalesOrder {
+    /// Reads a sales order from a row that has the columns
+    /// of `dbo.SalesOrderHeader` (see `SALES_ORDER_COLUMNS`).
+    pub fn from_row(row: &Row) -> tiberius::Result<Self> {
+        Ok(SalesOrder {
+            sales_order_id: required(row, "SalesOrderID")?,
+            revision_number: required(row, "RevisionNumber")?,
+            order_date: required(row, "OrderDate")?,
+            due_date: required(row, "DueDate")?,
+            ship_date: row.try_get("ShipDate")?,
+            status: required(row, "Status")?,
+            sales_order_number: required::<&str>(row, "SalesOrderNumber")?.to_owned(),
+            credit_card_approval_code: row
+                .try_get::<&str, _>("CreditCardApprovalCode")?
+                .map(str::to_owned),
+            sub_total: required(row, "SubTotal")?,
+            tax_amt: required(row, "TaxAmt")?,
+            freight: required(row, "Freight")?,
+            total_due: required(row, "TotalDue")?,
+            comment: row.try_get::<&str, _>("Comment")?.map(str::to_owned),
+            rowguid: required(row, "rowguid")?,
+            modified_date: row.try_get("ModifiedDate")?,
+        })
+    }
+}
+
+pub(crate) fn required<'a, R>(row: &'a Row, column: &str) -> tiberius::Result<R>
+where
+    R: tiberius::FromSql<'a>,
+{
+    row.try_get(column)?.ok_or_else(|| {
+        tiberius::error::Error::Conversion(format!("Column {} is NULL", column).into())
+    })
+}
+
+/// The columns read by `SalesOrder::from_row`, in the order of its fields. They are checked
+/// against `sales_order_header_definition`, the table the columns come from.
+pub(crate) const SALES_ORDER_COLUMNS: [&str; 15] = [
+    "SalesOrderID",
+    "RevisionNumber",
+    "OrderDate",
+    "DueDate",
+    "ShipDate",
+    "Status",
+    "SalesOrderNumber",
+    "CreditCardApprovalCode",
+    "SubTotal",
+    "TaxAmt",
+    "Freight",
+    "TotalDue",
+    "Comment",
+    "rowguid",
+    "ModifiedDate",
+];
+
+/// Conditions a sales order must meet to be listed.
+///
+/// Every field left as `None` is not applied.
+#[derive(Debug, Clone, Default)]
+pub struct SalesOrderFilter {
+    /// Orders placed on or after this date
+    pub order_date_from: Option<NaiveDateTime>,
+    /// Orders placed before this date
+    pub order_date_to: Option<NaiveDateTime>,
+    pub status: Option<u8>,
+    /// Same condition as `dbo.ufnGetSalesOrderWithTotalDueMoreThan`
+    pub total_due_more_than: Option<f64>,
+}
+
+/// Column used to order the listed sales orders.
+///
+/// `SalesOrderID` is always appended as a tie breaker so the order is stable,
+/// which keyset pagination relies on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SalesOrderSort {
+    SalesOrderId,
+    OrderDate,
+    TotalDue,
+}
+
+impl SalesOrderSort {
+    fn column(&self) -> &'static str {
+        match self {
+            SalesOrderSort::SalesOrderId => "SalesOrderID",
+            SalesOrderSort::OrderDate => "OrderDate",
+            SalesOrderSort::TotalDue => "TotalDue",
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            SalesOrderSort::SalesOrderId => "i",
+            SalesOrderSort::OrderDate => "o",
+            SalesOrderSort::TotalDue => "t",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+impl SortDirection {
+    pub(crate) fn keyword(&self) -> &'static str {
+        match self {
+            SortDirection::Ascending => "asc",
+            SortDirection::Descending => "desc",
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            SortDirection::Ascending => "a",
+            SortDirection::Descending => "d",
+        }
+    }
+
+    // Condition a key must meet to come after another one
+    fn after(&self, column: &str, value: impl Into<SqlValue>) -> Condition {
+        match self {
+            SortDirection::Ascending => Condition::gt(column, value),
+            SortDirection::Descending => Condition::lt(column, value),
+        }
+    }
+}
+
+/// How many sales orders to list and where to start.
+#[derive(Debug, Clone)]
+pub enum Page {
+    /// Skips `offset` rows using `OFFSET ... FETCH`
+    Offset { offset: u64, size: u64 },
+    /// Seeks past the last row of the previous page,
+    /// `after` is `None` for the first page
+    Keyset {
+        after: Option<ContinuationToken>,
+        size: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SortKey {
+    SalesOrderId,
+    OrderDate(NaiveDateTime),
+    TotalDue(f64),
+}
+
+/// Marks the last sales order of a page in keyset pagination.
+///
+/// It can be sent to callers as an opaque string (`to_string`)
+/// and read back with `parse`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContinuationToken {
+    key: SortKey,
+    direction: SortDirection,
+    sales_order_id: i32,
+}
+
+impl ContinuationToken {
+    /// Creates the token to get the page that follows `last`
+    /// when the orders are sorted by `sort` in `direction`
+    pub fn after(last: &SalesOrder, sort: SalesOrderSort, direction: SortDirection) -> Self {
+        let key = match sort {
+            SalesOrderSort::SalesOrderId => SortKey::SalesOrderId,
+            SalesOrderSort::OrderDate => SortKey::OrderDate(last.order_date),
+            SalesOrderSort::TotalDue => SortKey::TotalDue(last.total_due),
+        };
+
+        ContinuationToken {
+            key,
+            direction,
+            sales_order_id: last.sales_order_id,
+        }
+    }
+
+    fn sort(&self) -> SalesOrderSort {
+        match self.key {
+            SortKey::SalesOrderId => SalesOrderSort::SalesOrderId,
+            SortKey::OrderDate(_) => SalesOrderSort::OrderDate,
+            SortKey::TotalDue(_) => SalesOrderSort::TotalDue,
+        }
+    }
+}
+
+impl fmt::Display for ContinuationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match &self.key {
+            SortKey::SalesOrderId => String::new(),
+            SortKey::OrderDate(date) => date.format(TOKEN_DATE_FORMAT).to_string(),
+            SortKey::TotalDue(total) => total.to_string(),
+        };
+        let plain = format!(
+            "{}|{}|{}|{}",
+            self.sort().tag(),
+            self.direction.tag(),
+            value,
+            self.sales_order_id
+        );
+
+        // Hex encoding keeps the token opaque and URL safe
+        for byte in plain.bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidContinuationToken;
+
+impl fmt::Display for InvalidContinuationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The continuation token is not valid")
+    }
+}
+
+impl std::error::Error for InvalidContinuationToken {}
+
+impl FromStr for ContinuationToken {
+    type Err = InvalidContinuationToken;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.len().is_multiple_of(2) || !s.is_ascii() {
+            return Err(InvalidContinuationToken);
+        }
+
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| InvalidContinuationToken)?;
+        let plain = String::from_utf8(bytes).map_err(|_| InvalidContinuationToken)?;
+
+        let parts = plain.splitn(4, '|').collect::<Vec<_>>();
+        let [tag, direction, value, id] = parts[..] else {
+            return Err(InvalidContinuationToken);
+        };
+
+        let direction = match direction {
+            "a" => SortDirection::Ascending,
+            "d" => SortDirection::Descending,
+            _ => return Err(InvalidContinuationToken),
+        };
+
+        let key = match tag {
+            "i" if value.is_empty() => SortKey::SalesOrderId,
+            "o" => SortKey::OrderDate(
+                NaiveDateTime::parse_from_str(value, TOKEN_DATE_FORMAT)
+                    .map_err(|_| InvalidContinuationToken)?,
+            ),
+            "t" => SortKey::TotalDue(value.parse().map_err(|_| InvalidContinuationToken)?),
+            _ => return Err(InvalidContinuationToken),
+        };
+
+        Ok(ContinuationToken {
+            key,
+            direction,
+            sales_order_id: id.parse().map_err(|_| InvalidContinuationToken)?,
+        })
+    }
+}
+
+/// Lists the sales orders that match `filter` one page at a time.
+///
+/// Rows are read from the server as the returned stream is polled,
+/// so large pages are never buffered in memory.
+pub async fn list_sales_orders<'a>(
+    client: &'a mut Client<Compat<TcpStream>>,
+    table: &ObjectName,
+    filter: &SalesOrderFilter,
+    sort: SalesOrderSort,
+    direction: SortDirection,
+    page: &Page,
+) -> Result<impl Stream<Item = tiberius::Result<SalesOrder>> + 'a, Box<dyn std::error::Error>> {
+    let mut select = Select::from(table).columns(&SALES_ORDER_COLUMNS);
+
+    if let Some(from) = filter.order_date_from {
+        select = select.filter(Condition::ge("OrderDate", from));
+    }
+
+    if let Some(to) = filter.order_date_to {
+        select = select.filter(Condition::lt("OrderDate", to));
+    }
+
+    if let Some(status) = filter.status {
+        select = select.filter(Condition::eq("Status", status));
+    }
+
+    if let Some(total_due) = filter.total_due_more_than {
+        select = select.filter(Condition::gt("TotalDue", SqlValue::money(total_due)));
+    }
+
+    let (offset, size) = match page {
+        Page::Offset { offset, size } => (*offset, *size),
+        Page::Keyset { after, size } => {
+            if let Some(token) = after {
+                if token.sort() != sort {
+                    return Err(
+                        "The continuation token was created for a different sort column".into(),
+                    );
+                }
+
+                if token.direction != direction {
+                    return Err(
+                        "The continuation token was created for a different sort direction".into(),
+                    );
+                }
+
+                // Seek past the last row of the previous page using
+                // the sort column and SalesOrderID as the tie breaker
+                let id = token.sales_order_id;
+                let seek = match token.key {
+                    SortKey::SalesOrderId => direction.after("SalesOrderID", id),
+                    SortKey::OrderDate(date) => direction
+                        .after("OrderDate", date)
+                        .or(Condition::eq("OrderDate", date)
+                            .and(direction.after("SalesOrderID", id))),
+                    SortKey::TotalDue(total) => direction
+                        .after("TotalDue", SqlValue::money(total))
+                        .or(Condition::eq("TotalDue", SqlValue::money(total))
+                            .and(direction.after("SalesOrderID", id))),
+                };
+                select = select.filter(seek);
+            }
+
+            (0, *size)
+        }
+    };
+
+    if sort != SalesOrderSort::SalesOrderId {
+        select = select.order_by(sort.column(), direction);
+    }
+
+    let query = select
+        .order_by("SalesOrderID", direction)
+        .offset(offset)
+        .fetch(size)
+        .build()?
+        .into_query();
+
+    let stream = query.query(client).await?.into_row_stream();
+
+    Ok(stream.map(|row| row.and_then(|r| SalesOrder::from_row(&r))))
+}
+
+pub async fn list_sales_orders_by_page() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    // Shipped orders (status 5), two at a time, the most expensive first
+    let filter = SalesOrderFilter {
+        status: Some(5),
+        ..Default::default()
+    };
+    let table = sales_order_header();
+    let sort = SalesOrderSort::TotalDue;
+    let direction = SortDirection::Descending;
+
+    let mut page = Page::Keyset {
+        after: None,
+        size: 2,
+    };
+
+    for page_number in 1..=3 {
+        let mut last = None;
+
+        {
+            let orders =
+                list_sales_orders(&mut client, &table, &filter, sort, direction, &page).await?;
+            tokio::pin!(orders);
+
+            println!("Page {}:", page_number);
+            while let Some(order) = orders.try_next().await? {
+                println!(
+                    "SalesOrderID: {}, TotalDue: {}",
+                    order.sales_order_id, order.total_due
+                );
+                last = Some(order);
+            }
+        }
+
+        match last {
+            Some(order) => {
+                // The token can be handed to a caller and parsed back later
+                let token = ContinuationToken::after(&order, sort, direction).to_string();
+                println!("Continuation token: {}", token);
+
+                page = Page::Keyset {
+                    after: Some(token.parse()?),
+                    size: 2,
+                };
+            }
+            None => break,
+        }
+    }
+
+    client.close().await?;
+
+    Ok(())
+}
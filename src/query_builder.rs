@@ -0,0 +1,998 @@
+use std::fmt;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use tiberius::numeric::Numeric;
+use tiberius::{ColumnData, IntoSql, Query, QueryItem, ToSql};
+use tokio_stream::StreamExt;
+
+use crate::connections::connect_to_local_server;
+use crate::identifiers::quote;
+use crate::{sales_order_header, ObjectName, SortDirection};
+
+/// An owned parameter value of a built query.
+///
+/// `None` values are sent as a typed `NULL`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Bit(Option<bool>),
+    TinyInt(Option<u8>),
+    SmallInt(Option<i16>),
+    Int(Option<i32>),
+    BigInt(Option<i64>),
+    Real(Option<f32>),
+    Float(Option<f64>),
+    Decimal(Option<Numeric>),
+    NVarChar(Option<String>),
+    VarBinary(Option<Vec<u8>>),
+    Guid(Option<uuid::Uuid>),
+    Date(Option<NaiveDate>),
+    DateTime(Option<NaiveDateTime>),
+}
+
+impl<'a> IntoSql<'a> for SqlValue {
+    fn into_sql(self) -> ColumnData<'a> {
+        match self {
+            SqlValue::Bit(value) => value.into_sql(),
+            SqlValue::TinyInt(value) => value.into_sql(),
+            SqlValue::SmallInt(value) => value.into_sql(),
+            SqlValue::Int(value) => value.into_sql(),
+            SqlValue::BigInt(value) => value.into_sql(),
+            SqlValue::Real(value) => value.into_sql(),
+            SqlValue::Float(value) => value.into_sql(),
+            SqlValue::Decimal(value) => value.into_sql(),
+            SqlValue::NVarChar(value) => value.into_sql(),
+            SqlValue::VarBinary(value) => value.into_sql(),
+            SqlValue::Guid(value) => value.into_sql(),
+            SqlValue::Date(value) => value.into_sql(),
+            SqlValue::DateTime(value) => value.into_sql(),
+        }
+    }
+}
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> ColumnData<'_> {
+        self.clone().into_sql()
+    }
+}
+
+impl SqlValue {
+    /// A `money` value, rounded to its four decimals.
+    ///
+    /// Tiberius reads `money` columns as `f64`, sending one back as a `Float`
+    /// compares the column with an inexact binary fraction.
+    pub fn money(value: f64) -> Self {
+        SqlValue::Decimal(Some(Numeric::new_with_scale(
+            (value * 10_000.0).round() as i128,
+            4,
+        )))
+    }
+}
+
+macro_rules! sql_value_from {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for SqlValue {
+                fn from(value: $ty) -> Self {
+                    SqlValue::$variant(Some(value))
+                }
+            }
+
+            impl From<Option<$ty>> for SqlValue {
+                fn from(value: Option<$ty>) -> Self {
+                    SqlValue::$variant(value)
+                }
+            }
+        )*
+    };
+}
+
+sql_value_from!(
+    bool => Bit,
+    u8 => TinyInt,
+    i16 => SmallInt,
+    i32 => Int,
+    i64 => BigInt,
+    f32 => Real,
+    f64 => Float,
+    Numeric => Decimal,
+    String => NVarChar,
+    Vec<u8> => VarBinary,
+    uuid::Uuid => Guid,
+    NaiveDate => Date,
+    NaiveDateTime => DateTime,
+);
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self {
+        SqlValue::NVarChar(Some(value.to_owned()))
+    }
+}
+
+impl From<Option<&str>> for SqlValue {
+    fn from(value: Option<&str>) -> Self {
+        SqlValue::NVarChar(value.map(str::to_owned))
+    }
+}
+
+/// The T-SQL text of a built statement and the values of its
+/// `@P1`, `@P2`, ... parameters in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuiltQuery {
+    pub sql: String,
+    pub params: Vec<SqlValue>,
+}
+
+impl BuiltQuery {
+    /// Creates a `tiberius::Query` with all the parameters bound
+    pub fn into_query(self) -> Query<'static> {
+        let mut query = Query::new(self.sql);
+
+        for param in self.params {
+            query.bind(param);
+        }
+
+        query
+    }
+}
+
+// Collects the statement text and numbers the parameters as they are added
+#[derive(Default)]
+struct SqlWriter {
+    sql: String,
+    params: Vec<SqlValue>,
+}
+
+impl SqlWriter {
+    fn push(&mut self, sql: &str) {
+        self.sql.push_str(sql);
+    }
+
+    fn param(&mut self, value: SqlValue) {
+        self.params.push(value);
+        self.sql.push_str(&format!("@P{}", self.params.len()));
+    }
+
+    fn finish(self) -> BuiltQuery {
+        BuiltQuery {
+            sql: self.sql,
+            params: self.params,
+        }
+    }
+}
+
+/// Quotes every part of a column reference, `o.SalesOrderID`
+/// becomes `[o].[SalesOrderID]`.
+///
+/// `*` is left as is so `o.*` can be selected.
+fn quote_name(name: &str) -> String {
+    name.split('.')
+        .map(|part| {
+            if part == "*" {
+                part.to_owned()
+            } else {
+                quote(part)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn quote_names(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| quote_name(name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Column lists of INSERT and MERGE name columns of a single table,
+// a `.` is part of the name
+fn quote_columns(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| quote(name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A statement that can't be built because a required part is missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryBuilderError {
+    /// An `UPDATE` without any `set` value
+    NoValues,
+    /// A `MERGE` without `using_columns`, rows, `on` columns or action
+    IncompleteMerge(&'static str),
+    /// A `MERGE` row doesn't have a value for each of the `using_columns`
+    RowLength {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A `TOP`, `OFFSET` or `FETCH` row count above the `bigint` range
+    RowCount { clause: &'static str, rows: u64 },
+}
+
+impl fmt::Display for QueryBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryBuilderError::NoValues => write!(f, "The update has no column to set"),
+            QueryBuilderError::IncompleteMerge(missing) => {
+                write!(f, "The merge has no {}", missing)
+            }
+            QueryBuilderError::RowLength {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Row {} of the merge has {} values, {} columns are used",
+                row, found, expected
+            ),
+            QueryBuilderError::RowCount { clause, rows } => write!(
+                f,
+                "The {} row count {} is above the bigint maximum {}",
+                clause,
+                rows,
+                i64::MAX
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QueryBuilderError {}
+
+// Row counts are sent as bigint, which can't hold every u64
+fn row_count(clause: &'static str, rows: u64) -> Result<i64, QueryBuilderError> {
+    i64::try_from(rows).map_err(|_| QueryBuilderError::RowCount { clause, rows })
+}
+
+/// A boolean expression used in `WHERE` and `ON` clauses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// Compares a column with a parameter using the operator (`=`, `<>`, `>`, ...)
+    Compare(String, &'static str, SqlValue),
+    /// Compares two columns, as in joins
+    Columns(String, &'static str, String),
+    IsNull(String),
+    IsNotNull(String),
+    Like(String, SqlValue),
+    In(String, Vec<SqlValue>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    pub fn eq(column: &str, value: impl Into<SqlValue>) -> Self {
+        Condition::Compare(column.to_owned(), "=", value.into())
+    }
+
+    pub fn ne(column: &str, value: impl Into<SqlValue>) -> Self {
+        Condition::Compare(column.to_owned(), "<>", value.into())
+    }
+
+    pub fn gt(column: &str, value: impl Into<SqlValue>) -> Self {
+        Condition::Compare(column.to_owned(), ">", value.into())
+    }
+
+    pub fn ge(column: &str, value: impl Into<SqlValue>) -> Self {
+        Condition::Compare(column.to_owned(), ">=", value.into())
+    }
+
+    pub fn lt(column: &str, value: impl Into<SqlValue>) -> Self {
+        Condition::Compare(column.to_owned(), "<", value.into())
+    }
+
+    pub fn le(column: &str, value: impl Into<SqlValue>) -> Self {
+        Condition::Compare(column.to_owned(), "<=", value.into())
+    }
+
+    pub fn columns_eq(left: &str, right: &str) -> Self {
+        Condition::Columns(left.to_owned(), "=", right.to_owned())
+    }
+
+    pub fn is_null(column: &str) -> Self {
+        Condition::IsNull(column.to_owned())
+    }
+
+    pub fn is_not_null(column: &str) -> Self {
+        Condition::IsNotNull(column.to_owned())
+    }
+
+    pub fn like(column: &str, pattern: impl Into<SqlValue>) -> Self {
+        Condition::Like(column.to_owned(), pattern.into())
+    }
+
+    pub fn is_in<V: Into<SqlValue>>(column: &str, values: impl IntoIterator<Item = V>) -> Self {
+        Condition::In(
+            column.to_owned(),
+            values.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    pub fn and(self, other: Condition) -> Self {
+        match self {
+            Condition::And(mut conditions) => {
+                conditions.push(other);
+                Condition::And(conditions)
+            }
+            condition => Condition::And(vec![condition, other]),
+        }
+    }
+
+    pub fn or(self, other: Condition) -> Self {
+        match self {
+            Condition::Or(mut conditions) => {
+                conditions.push(other);
+                Condition::Or(conditions)
+            }
+            condition => Condition::Or(vec![condition, other]),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Condition::Not(Box::new(self))
+    }
+
+    fn write(&self, w: &mut SqlWriter) {
+        match self {
+            Condition::Compare(column, operator, value) => {
+                w.push(&format!("{} {} ", quote_name(column), operator));
+                w.param(value.clone());
+            }
+            Condition::Columns(left, operator, right) => {
+                w.push(&format!(
+                    "{} {} {}",
+                    quote_name(left),
+                    operator,
+                    quote_name(right)
+                ));
+            }
+            Condition::IsNull(column) => w.push(&format!("{} is null", quote_name(column))),
+            Condition::IsNotNull(column) => w.push(&format!("{} is not null", quote_name(column))),
+            Condition::Like(column, pattern) => {
+                w.push(&format!("{} like ", quote_name(column)));
+                w.param(pattern.clone());
+            }
+            Condition::In(column, values) => {
+                // An empty list matches nothing
+                if values.is_empty() {
+                    w.push("1 = 0");
+                    return;
+                }
+
+                w.push(&format!("{} in (", quote_name(column)));
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        w.push(", ");
+                    }
+                    w.param(value.clone());
+                }
+                w.push(")");
+            }
+            Condition::And(conditions) => write_group(w, conditions, " and ", "1 = 1"),
+            Condition::Or(conditions) => write_group(w, conditions, " or ", "1 = 0"),
+            Condition::Not(condition) => {
+                w.push("not (");
+                condition.write(w);
+                w.push(")");
+            }
+        }
+    }
+}
+
+fn write_group(w: &mut SqlWriter, conditions: &[Condition], separator: &str, empty: &str) {
+    if conditions.is_empty() {
+        w.push(empty);
+        return;
+    }
+
+    w.push("(");
+    for (i, condition) in conditions.iter().enumerate() {
+        if i > 0 {
+            w.push(separator);
+        }
+        condition.write(w);
+    }
+    w.push(")");
+}
+
+fn write_where(w: &mut SqlWriter, conditions: &[Condition]) {
+    for (i, condition) in conditions.iter().enumerate() {
+        w.push(if i == 0 { "\nwhere " } else { "\n  and " });
+        condition.write(w);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+impl JoinKind {
+    fn keyword(&self) -> &'static str {
+        match self {
+            JoinKind::Inner => "inner join",
+            JoinKind::Left => "left join",
+            JoinKind::Right => "right join",
+            JoinKind::Full => "full join",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Join {
+    kind: JoinKind,
+    table: ObjectName,
+    alias: Option<String>,
+    on: Condition,
+}
+
+// A column of the `OUTPUT` clause
+#[derive(Debug, Clone)]
+enum OutputColumn {
+    Inserted(String),
+    Deleted(String),
+    Action,
+}
+
+fn write_output(w: &mut SqlWriter, output: &[OutputColumn]) {
+    if output.is_empty() {
+        return;
+    }
+
+    let columns = output
+        .iter()
+        .map(|column| match column {
+            OutputColumn::Inserted(name) => format!("inserted.{}", quote(name)),
+            OutputColumn::Deleted(name) => format!("deleted.{}", quote(name)),
+            OutputColumn::Action => "$action".to_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    w.push(&format!("\noutput {}", columns.join(", ")));
+}
+
+fn write_table(w: &mut SqlWriter, table: &ObjectName, alias: &Option<String>) {
+    w.push(&table.quoted());
+    if let Some(alias) = alias {
+        w.push(&format!(" as {}", quote(alias)));
+    }
+}
+
+/// Builds a `SELECT` statement.
+#[derive(Debug, Clone)]
+pub struct Select {
+    table: ObjectName,
+    alias: Option<String>,
+    columns: Vec<String>,
+    top: Option<u64>,
+    joins: Vec<Join>,
+    conditions: Vec<Condition>,
+    order_by: Vec<(String, SortDirection)>,
+    offset: Option<u64>,
+    fetch: Option<u64>,
+}
+
+impl Select {
+    /// Selects from a table, view or table valued function without parameters
+    pub fn from(table: &ObjectName) -> Self {
+        Select {
+            table: table.clone(),
+            alias: None,
+            columns: Vec::new(),
+            top: None,
+            joins: Vec::new(),
+            conditions: Vec::new(),
+            order_by: Vec::new(),
+            offset: None,
+            fetch: None,
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.alias = Some(alias.to_owned());
+        self
+    }
+
+    /// Adds columns to the select list, all columns are selected if none is added
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns.extend(columns.iter().map(|c| c.to_string()));
+        self
+    }
+
+    pub fn top(mut self, rows: u64) -> Self {
+        self.top = Some(rows);
+        self
+    }
+
+    pub fn join(
+        mut self,
+        kind: JoinKind,
+        table: &ObjectName,
+        alias: Option<&str>,
+        on: Condition,
+    ) -> Self {
+        self.joins.push(Join {
+            kind,
+            table: table.clone(),
+            alias: alias.map(str::to_owned),
+            on,
+        });
+        self
+    }
+
+    /// Adds a condition, all of them are combined with `and`
+    pub fn filter(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn order_by(mut self, column: &str, direction: SortDirection) -> Self {
+        self.order_by.push((column.to_owned(), direction));
+        self
+    }
+
+    pub fn offset(mut self, rows: u64) -> Self {
+        self.offset = Some(rows);
+        self
+    }
+
+    pub fn fetch(mut self, rows: u64) -> Self {
+        self.fetch = Some(rows);
+        self
+    }
+
+    /// Fails when a `top`, `offset` or `fetch` row count doesn't fit in a `bigint`
+    pub fn build(&self) -> Result<BuiltQuery, QueryBuilderError> {
+        let mut w = SqlWriter::default();
+
+        w.push("select ");
+        if let Some(top) = self.top {
+            w.push(&format!("top ({}) ", row_count("top", top)?));
+        }
+
+        if self.columns.is_empty() {
+            w.push("*");
+        } else {
+            w.push(&quote_names(&self.columns));
+        }
+
+        w.push("\nfrom ");
+        write_table(&mut w, &self.table, &self.alias);
+
+        for join in &self.joins {
+            w.push(&format!("\n{} ", join.kind.keyword()));
+            write_table(&mut w, &join.table, &join.alias);
+            w.push(" on ");
+            join.on.write(&mut w);
+        }
+
+        write_where(&mut w, &self.conditions);
+
+        let paged = self.offset.is_some() || self.fetch.is_some();
+
+        if !self.order_by.is_empty() {
+            let order_by = self
+                .order_by
+                .iter()
+                .map(|(column, direction)| {
+                    format!("{} {}", quote_name(column), direction.keyword())
+                })
+                .collect::<Vec<_>>();
+            w.push(&format!("\norder by {}", order_by.join(", ")));
+        } else if paged {
+            // OFFSET requires an ORDER BY clause
+            w.push("\norder by (select null)");
+        }
+
+        if paged {
+            w.push("\noffset ");
+            let offset = row_count("offset", self.offset.unwrap_or(0))?;
+            w.param(SqlValue::BigInt(Some(offset)));
+            w.push(" rows");
+
+            if let Some(fetch) = self.fetch {
+                w.push(" fetch next ");
+                w.param(SqlValue::BigInt(Some(row_count("fetch", fetch)?)));
+                w.push(" rows only");
+            }
+        }
+
+        Ok(w.finish())
+    }
+}
+
+/// Builds an `INSERT` statement of a single row.
+#[derive(Debug, Clone)]
+pub struct Insert {
+    table: ObjectName,
+    values: Vec<(String, SqlValue)>,
+    output: Vec<OutputColumn>,
+}
+
+impl Insert {
+    pub fn into(table: &ObjectName) -> Self {
+        Insert {
+            table: table.clone(),
+            values: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn value(mut self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.values.push((column.to_owned(), value.into()));
+        self
+    }
+
+    /// Returns the value of `column` in the inserted row, as identities or defaults
+    pub fn output_inserted(mut self, column: &str) -> Self {
+        self.output.push(OutputColumn::Inserted(column.to_owned()));
+        self
+    }
+
+    pub fn build(&self) -> BuiltQuery {
+        let mut w = SqlWriter::default();
+
+        w.push(&format!("insert into {}", self.table.quoted()));
+
+        if self.values.is_empty() {
+            write_output(&mut w, &self.output);
+            w.push("\ndefault values");
+            return w.finish();
+        }
+
+        let columns = self
+            .values
+            .iter()
+            .map(|(c, _)| c.clone())
+            .collect::<Vec<_>>();
+        w.push(&format!(" ({})", quote_columns(&columns)));
+        write_output(&mut w, &self.output);
+
+        w.push("\nvalues (");
+        for (i, (_, value)) in self.values.iter().enumerate() {
+            if i > 0 {
+                w.push(", ");
+            }
+            w.param(value.clone());
+        }
+        w.push(")");
+
+        w.finish()
+    }
+}
+
+/// Builds an `UPDATE` statement.
+#[derive(Debug, Clone)]
+pub struct Update {
+    table: ObjectName,
+    top: Option<u64>,
+    values: Vec<(String, SqlValue)>,
+    conditions: Vec<Condition>,
+    output: Vec<OutputColumn>,
+}
+
+impl Update {
+    pub fn table(table: &ObjectName) -> Self {
+        Update {
+            table: table.clone(),
+            top: None,
+            values: Vec::new(),
+            conditions: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn top(mut self, rows: u64) -> Self {
+        self.top = Some(rows);
+        self
+    }
+
+    pub fn set(mut self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.values.push((column.to_owned(), value.into()));
+        self
+    }
+
+    pub fn filter(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn output_inserted(mut self, column: &str) -> Self {
+        self.output.push(OutputColumn::Inserted(column.to_owned()));
+        self
+    }
+
+    pub fn output_deleted(mut self, column: &str) -> Self {
+        self.output.push(OutputColumn::Deleted(column.to_owned()));
+        self
+    }
+
+    /// Fails when no value is `set`
+    pub fn build(&self) -> Result<BuiltQuery, QueryBuilderError> {
+        if self.values.is_empty() {
+            return Err(QueryBuilderError::NoValues);
+        }
+
+        let mut w = SqlWriter::default();
+
+        w.push("update ");
+        if let Some(top) = self.top {
+            w.push(&format!("top ({}) ", top));
+        }
+        w.push(&self.table.quoted());
+
+        w.push("\nset ");
+        for (i, (column, value)) in self.values.iter().enumerate() {
+            if i > 0 {
+                w.push(",\n    ");
+            }
+            w.push(&format!("{} = ", quote(column)));
+            w.param(value.clone());
+        }
+
+        write_output(&mut w, &self.output);
+        write_where(&mut w, &self.conditions);
+
+        Ok(w.finish())
+    }
+}
+
+/// Builds a `DELETE` statement.
+#[derive(Debug, Clone)]
+pub struct Delete {
+    table: ObjectName,
+    top: Option<u64>,
+    conditions: Vec<Condition>,
+    output: Vec<OutputColumn>,
+}
+
+impl Delete {
+    pub fn from(table: &ObjectName) -> Self {
+        Delete {
+            table: table.clone(),
+            top: None,
+            conditions: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn top(mut self, rows: u64) -> Self {
+        self.top = Some(rows);
+        self
+    }
+
+    pub fn filter(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn output_deleted(mut self, column: &str) -> Self {
+        self.output.push(OutputColumn::Deleted(column.to_owned()));
+        self
+    }
+
+    pub fn build(&self) -> BuiltQuery {
+        let mut w = SqlWriter::default();
+
+        w.push("delete ");
+        if let Some(top) = self.top {
+            w.push(&format!("top ({}) ", top));
+        }
+        w.push(&format!("from {}", self.table.quoted()));
+
+        write_output(&mut w, &self.output);
+        write_where(&mut w, &self.conditions);
+
+        w.finish()
+    }
+}
+
+/// Builds a `MERGE` statement that upserts rows given as values.
+///
+/// The rows are matched with the target table on the `on` columns.
+#[derive(Debug, Clone)]
+pub struct Merge {
+    table: ObjectName,
+    columns: Vec<String>,
+    rows: Vec<Vec<SqlValue>>,
+    on: Vec<String>,
+    update: Vec<String>,
+    insert: Vec<String>,
+    delete_not_matched_by_source: bool,
+    output: Vec<OutputColumn>,
+}
+
+impl Merge {
+    pub fn into(table: &ObjectName) -> Self {
+        Merge {
+            table: table.clone(),
+            columns: Vec::new(),
+            rows: Vec::new(),
+            on: Vec::new(),
+            update: Vec::new(),
+            insert: Vec::new(),
+            delete_not_matched_by_source: false,
+            output: Vec::new(),
+        }
+    }
+
+    /// Names the columns of the source rows
+    pub fn using_columns(mut self, columns: &[&str]) -> Self {
+        self.columns = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    /// Adds a source row, values are in the order of `using_columns`
+    pub fn row(mut self, values: Vec<SqlValue>) -> Self {
+        self.rows.push(values);
+        self
+    }
+
+    pub fn on(mut self, columns: &[&str]) -> Self {
+        self.on = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    pub fn when_matched_update(mut self, columns: &[&str]) -> Self {
+        self.update = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    pub fn when_not_matched_insert(mut self, columns: &[&str]) -> Self {
+        self.insert = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    pub fn when_not_matched_by_source_delete(mut self) -> Self {
+        self.delete_not_matched_by_source = true;
+        self
+    }
+
+    /// Adds `$action` (`INSERT`, `UPDATE` or `DELETE`) to the output
+    pub fn output_action(mut self) -> Self {
+        self.output.push(OutputColumn::Action);
+        self
+    }
+
+    pub fn output_inserted(mut self, column: &str) -> Self {
+        self.output.push(OutputColumn::Inserted(column.to_owned()));
+        self
+    }
+
+    pub fn output_deleted(mut self, column: &str) -> Self {
+        self.output.push(OutputColumn::Deleted(column.to_owned()));
+        self
+    }
+
+    /// Fails when the source rows, the `on` columns or the actions are missing
+    pub fn build(&self) -> Result<BuiltQuery, QueryBuilderError> {
+        if self.columns.is_empty() {
+            return Err(QueryBuilderError::IncompleteMerge("source columns"));
+        }
+        if self.rows.is_empty() {
+            return Err(QueryBuilderError::IncompleteMerge("source rows"));
+        }
+        if self.on.is_empty() {
+            return Err(QueryBuilderError::IncompleteMerge("on columns"));
+        }
+        if self.update.is_empty() && self.insert.is_empty() && !self.delete_not_matched_by_source {
+            return Err(QueryBuilderError::IncompleteMerge("action"));
+        }
+        for (i, row) in self.rows.iter().enumerate() {
+            if row.len() != self.columns.len() {
+                return Err(QueryBuilderError::RowLength {
+                    row: i + 1,
+                    expected: self.columns.len(),
+                    found: row.len(),
+                });
+            }
+        }
+
+        let mut w = SqlWriter::default();
+
+        w.push(&format!(
+            "merge into {} with (holdlock) as [target]\nusing (values ",
+            self.table.quoted()
+        ));
+
+        for (i, row) in self.rows.iter().enumerate() {
+            if i > 0 {
+                w.push(", ");
+            }
+            w.push("(");
+            for (j, value) in row.iter().enumerate() {
+                if j > 0 {
+                    w.push(", ");
+                }
+                w.param(value.clone());
+            }
+            w.push(")");
+        }
+
+        w.push(&format!(") as [source] ({})", quote_columns(&self.columns)));
+
+        let on = self
+            .on
+            .iter()
+            .map(|c| format!("[target].{c} = [source].{c}", c = quote(c)))
+            .collect::<Vec<_>>();
+        w.push(&format!("\non {}", on.join(" and ")));
+
+        if !self.update.is_empty() {
+            let set = self
+                .update
+                .iter()
+                .map(|c| format!("[target].{c} = [source].{c}", c = quote(c)))
+                .collect::<Vec<_>>();
+            w.push(&format!(
+                "\nwhen matched then update set {}",
+                set.join(", ")
+            ));
+        }
+
+        if !self.insert.is_empty() {
+            let values = self
+                .insert
+                .iter()
+                .map(|c| format!("[source].{}", quote(c)))
+                .collect::<Vec<_>>();
+            w.push(&format!(
+                "\nwhen not matched by target then insert ({}) values ({})",
+                quote_columns(&self.insert),
+                values.join(", ")
+            ));
+        }
+
+        if self.delete_not_matched_by_source {
+            w.push("\nwhen not matched by source then delete");
+        }
+
+        write_output(&mut w, &self.output);
+
+        // MERGE must be terminated by a semicolon
+        w.push(";");
+
+        Ok(w.finish())
+    }
+}
+
+pub async fn select_rows_with_query_builder() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    // Shipped orders with a total due above 1000, the newest first
+    let query = Select::from(&sales_order_header())
+        .columns(&["SalesOrderID", "OrderDate", "Status", "TotalDue"])
+        .filter(Condition::eq("Status", 5u8))
+        .filter(Condition::gt("TotalDue", 1000f64).or(Condition::is_null("ShipDate")))
+        .order_by("OrderDate", SortDirection::Descending)
+        .offset(0)
+        .fetch(10)
+        .build()?;
+
+    println!("{}", query.sql);
+
+    let mut rows = query.into_query().query(&mut client).await?;
+
+    while let Some(row) = rows.try_next().await? {
+        if let QueryItem::Row(r) = row {
+            let sales_order_id: i32 = r.get("SalesOrderID").unwrap();
+            let order_date: chrono::NaiveDateTime = r.get("OrderDate").unwrap();
+            let total_due: f64 = r.get("TotalDue").unwrap();
+            println!("SalesOrderID: {}", sales_order_id);
+            println!("OrderDate: {}", order_date);
+            println!("TotalDue: {}", total_due);
+        }
+    }
+
+    // The stream borrows the client, it must be dropped before closing
+    drop(rows);
+    client.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use serde_json::Value;
+use tiberius::{Client, ColumnData, FromSql, QueryItem};
+use tokio::net::TcpStream;
+use tokio_stream::StreamExt;
+use tokio_util::compat::Compat;
+
+/// The rows of one result of a batch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    /// The values in the order of `columns`, `Value::Null` for NULL
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Converts a value read from SQL Server to JSON.
+///
+/// Dates and times are written in ISO 8601, binary values as `0x...` hex strings
+/// and decimal values as numbers, which may round the ones with more than 15 digits.
+pub fn cell_value(data: &ColumnData<'static>) -> tiberius::Result<Value> {
+    let value = match data {
+        ColumnData::U8(v) => v.map(Value::from),
+        ColumnData::I16(v) => v.map(Value::from),
+        ColumnData::I32(v) => v.map(Value::from),
+        ColumnData::I64(v) => v.map(Value::from),
+        ColumnData::F32(v) => v.map(Value::from),
+        ColumnData::F64(v) => v.map(Value::from),
+        ColumnData::Bit(v) => v.map(Value::from),
+        ColumnData::String(v) => v.as_ref().map(|s| Value::from(s.as_ref())),
+        ColumnData::Guid(v) => v.map(|g| Value::from(g.to_string().to_uppercase())),
+        ColumnData::Binary(v) => v.as_ref().map(|bytes| {
+            let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            Value::from(format!("0x{}", hex))
+        }),
+        ColumnData::Numeric(v) => v.map(|n| Value::from(f64::from(n))),
+        ColumnData::Xml(v) => v.as_ref().map(|xml| Value::from(xml.to_string())),
+        ColumnData::DateTime(_) | ColumnData::SmallDateTime(_) | ColumnData::DateTime2(_) => {
+            NaiveDateTime::from_sql(data)?
+                .map(|d| Value::from(d.format("%Y-%m-%dT%H:%M:%S%.f").to_string()))
+        }
+        ColumnData::Date(_) => NaiveDate::from_sql(data)?.map(|d| Value::from(d.to_string())),
+        ColumnData::Time(_) => NaiveTime::from_sql(data)?.map(|t| Value::from(t.to_string())),
+        ColumnData::DateTimeOffset(_) => {
+            DateTime::<FixedOffset>::from_sql(data)?.map(|d| Value::from(d.to_rfc3339()))
+        }
+    };
+
+    Ok(value.unwrap_or(Value::Null))
+}
+
+/// Runs a batch and reads all its results.
+///
+/// A statement that returns no rows still has a result set with its columns,
+/// statements without results (`insert`, `update`, ...) have none.
+pub async fn query_result_sets(
+    client: &mut Client<Compat<TcpStream>>,
+    sql: &str,
+) -> tiberius::Result<Vec<ResultSet>> {
+    let mut stream = client.simple_query(sql).await?;
+    let mut result_sets: Vec<ResultSet> = Vec::new();
+
+    while let Some(item) = stream.try_next().await? {
+        match item {
+            QueryItem::Metadata(meta) => result_sets.push(ResultSet {
+                columns: meta.columns().iter().map(|c| c.name().to_owned()).collect(),
+                rows: Vec::new(),
+            }),
+            QueryItem::Row(row) => {
+                let values = row
+                    .cells()
+                    .map(|(_, data)| cell_value(data))
+                    .collect::<tiberius::Result<Vec<_>>>()?;
+
+                if let Some(result_set) = result_sets.last_mut() {
+                    result_set.rows.push(values);
+                }
+            }
+        }
+    }
+
+    Ok(result_sets)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned columns with a header, like sqlcmd
+    Table,
+    /// RFC 4180, with a header and NULL as an empty field
+    Csv,
+    /// An array of objects for each result set
+    Json,
+    /// A block of `column | value` lines for each row
+    Expanded,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "expanded" => Ok(OutputFormat::Expanded),
+            _ => Err(format!(
+                "Unknown output format {}, use table, csv, json or expanded",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Table => write!(f, "table"),
+            OutputFormat::Csv => write!(f, "csv"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Expanded => write!(f, "expanded"),
+        }
+    }
+}
+
+// The text of a value in the table and CSV formats
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn format_table(result_set: &ResultSet) -> String {
+    let texts: Vec<Vec<String>> = result_set
+        .rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|v| match v {
+                    Value::Null => "NULL".to_owned(),
+                    v => cell_text(v),
+                })
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = result_set
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            texts
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain(std::iter::once(name.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let line = |cells: Vec<String>| cells.join(" ").trim_end().to_owned() + "\n";
+
+    let mut table = line(
+        result_set
+            .columns
+            .iter()
+            .zip(&widths)
+            .map(|(name, width)| format!("{:<width$}", name, width = width))
+            .collect(),
+    );
+    table.push_str(&line(
+        widths.iter().map(|width| "-".repeat(*width)).collect(),
+    ));
+
+    for (row, values) in texts.iter().zip(&result_set.rows) {
+        table.push_str(&line(
+            row.iter()
+                .zip(values)
+                .zip(&widths)
+                .map(|((text, value), width)| {
+                    // Numbers are aligned to the right
+                    if value.is_number() {
+                        format!("{:>width$}", text, width = width)
+                    } else {
+                        format!("{:<width$}", text, width = width)
+                    }
+                })
+                .collect(),
+        ));
+    }
+
+    table.push_str(&rows_affected(result_set));
+    table
+}
+
+fn rows_affected(result_set: &ResultSet) -> String {
+    let count = result_set.rows.len();
+    format!(
+        "\n({} row{} affected)\n",
+        count,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+fn format_expanded(result_set: &ResultSet) -> String {
+    let width = result_set
+        .columns
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut text = String::new();
+    for (number, row) in result_set.rows.iter().enumerate() {
+        text.push_str(&format!(
+            "-[ RECORD {} ]{}\n",
+            number + 1,
+            "-".repeat(width)
+        ));
+        for (name, value) in result_set.columns.iter().zip(row) {
+            let value = match value {
+                Value::Null => "NULL".to_owned(),
+                v => cell_text(v),
+            };
+            text.push_str(&format!("{:<width$} | {}\n", name, value, width = width));
+        }
+    }
+
+    text.push_str(&rows_affected(result_set));
+    text
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+fn format_csv(result_set: &ResultSet) -> String {
+    let mut csv = String::new();
+    let mut line = |fields: Vec<String>| {
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    };
+
+    line(result_set.columns.iter().map(|c| csv_field(c)).collect());
+    for row in &result_set.rows {
+        line(
+            row.iter()
+                .map(|v| match v {
+                    Value::Null => String::new(),
+                    v => csv_field(&cell_text(v)),
+                })
+                .collect(),
+        );
+    }
+
+    csv
+}
+
+// Written by hand because `serde_json::Map` sorts the keys,
+// the properties keep the order of the columns
+fn format_json(result_set: &ResultSet) -> String {
+    if result_set.rows.is_empty() {
+        return "[]\n".to_owned();
+    }
+
+    let mut keys: Vec<String> = Vec::with_capacity(result_set.columns.len());
+    for (i, name) in result_set.columns.iter().enumerate() {
+        // Columns without a name or with a repeated one are named after their position
+        let key = if name.is_empty() || keys.contains(name) {
+            format!("column{}", i + 1)
+        } else {
+            name.clone()
+        };
+        keys.push(key);
+    }
+
+    let rows: Vec<String> = result_set
+        .rows
+        .iter()
+        .map(|row| {
+            let properties: Vec<String> = keys
+                .iter()
+                .zip(row)
+                .map(|(key, value)| format!("    {}: {}", Value::from(key.as_str()), value))
+                .collect();
+            format!("  {{\n{}\n  }}", properties.join(",\n"))
+        })
+        .collect();
+
+    format!("[\n{}\n]\n", rows.join(",\n"))
+}
+
+/// Writes a result set in the given format.
+pub fn format_result_set(result_set: &ResultSet, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Table => format_table(result_set),
+        OutputFormat::Csv => format_csv(result_set),
+        OutputFormat::Json => format_json(result_set),
+        OutputFormat::Expanded => format_expanded(result_set),
+    }
+}
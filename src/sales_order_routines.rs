@@ -0,0 +1,199 @@
+// Generated from a schema snapshot with `cargo run --bin codegen -- generate`,
+// do not edit. Take a new snapshot and generate it again when a signature changes.
+
+fn null_column(column: &str) -> tiberius::error::Error {
+    tiberius::error::Error::Conversion(format!("Column {} is NULL", column).into())
+}
+
+/// Output of `[dbo].[uspSaveOrderHeader]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UspSaveOrderHeaderOutput {
+    /// Value of the `return` statement
+    pub return_value: i32,
+}
+
+/// Calls `[dbo].[uspSaveOrderHeader]`.
+///
+/// Arguments set to `None` take the default of the parameter.
+pub async fn usp_save_order_header(
+    client: &mut tiberius::Client<tokio_util::compat::Compat<tokio::net::TcpStream>>,
+    due_date: chrono::NaiveDateTime,
+    ship_date: chrono::NaiveDateTime,
+    credit_card_approval_code: &str,
+    comment: Option<&str>,
+    modified_date: chrono::NaiveDateTime,
+) -> tiberius::Result<UspSaveOrderHeaderOutput> {
+    let sql = format!(
+        "declare @ReturnValue int;\nexec @ReturnValue = [dbo].[uspSaveOrderHeader] @DueDate = @P1, @ShipDate = @P2, @CreditCardApprovalCode = @P3, @Comment = {}, @ModifiedDate = @P5;\nselect @ReturnValue as [ReturnValue];",
+        if comment.is_some() { "@P4" } else { "default" },
+    );
+    let mut query = tiberius::Query::new(sql);
+    query.bind(due_date);
+    query.bind(ship_date);
+    query.bind(credit_card_approval_code);
+    query.bind(comment);
+    query.bind(modified_date);
+
+    let results = query.query(client).await?.into_results().await?;
+    let row = results.last().and_then(|rows| rows.first()).ok_or_else(|| {
+        tiberius::error::Error::Protocol("The procedure returned no output".into())
+    })?;
+    let output = UspSaveOrderHeaderOutput {
+        return_value: row.try_get::<i32, _>("ReturnValue")?.ok_or_else(|| null_column("ReturnValue"))?,
+    };
+
+    Ok(output)
+}
+
+/// Output of `[dbo].[uspUpdateOrderStatus]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UspUpdateOrderStatusOutput {
+    /// Value of the `return` statement
+    pub return_value: i32,
+}
+
+/// Calls `[dbo].[uspUpdateOrderStatus]`.
+pub async fn usp_update_order_status(
+    client: &mut tiberius::Client<tokio_util::compat::Compat<tokio::net::TcpStream>>,
+    sales_order_id: i32,
+    status: i32,
+) -> tiberius::Result<UspUpdateOrderStatusOutput> {
+    let mut query = tiberius::Query::new("declare @ReturnValue int;\nexec @ReturnValue = [dbo].[uspUpdateOrderStatus] @SalesOrderID = @P1, @Status = @P2;\nselect @ReturnValue as [ReturnValue];");
+    query.bind(sales_order_id);
+    query.bind(status);
+
+    let results = query.query(client).await?.into_results().await?;
+    let row = results.last().and_then(|rows| rows.first()).ok_or_else(|| {
+        tiberius::error::Error::Protocol("The procedure returned no output".into())
+    })?;
+    let output = UspUpdateOrderStatusOutput {
+        return_value: row.try_get::<i32, _>("ReturnValue")?.ok_or_else(|| null_column("ReturnValue"))?,
+    };
+
+    Ok(output)
+}
+
+/// A row returned by `[dbo].[uspGetSaleOrderByID]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UspGetSaleOrderByIdRow {
+    pub sales_order_id: i32,
+    pub revision_number: u8,
+    pub order_date: chrono::NaiveDateTime,
+    pub due_date: chrono::NaiveDateTime,
+    pub ship_date: Option<chrono::NaiveDateTime>,
+    pub status: u8,
+    pub sales_order_number: String,
+    pub credit_card_approval_code: Option<String>,
+    pub sub_total: f64,
+    pub tax_amt: f64,
+    pub freight: f64,
+    pub total_due: f64,
+    pub comment: Option<String>,
+    pub rowguid: uuid::Uuid,
+    pub modified_date: Option<chrono::NaiveDateTime>,
+}
+
+impl UspGetSaleOrderByIdRow {
+    pub fn from_row(row: &tiberius::Row) -> tiberius::Result<Self> {
+        Ok(UspGetSaleOrderByIdRow {
+            sales_order_id: row.try_get::<i32, _>("SalesOrderID")?.ok_or_else(|| null_column("SalesOrderID"))?,
+            revision_number: row.try_get::<u8, _>("RevisionNumber")?.ok_or_else(|| null_column("RevisionNumber"))?,
+            order_date: row.try_get::<chrono::NaiveDateTime, _>("OrderDate")?.ok_or_else(|| null_column("OrderDate"))?,
+            due_date: row.try_get::<chrono::NaiveDateTime, _>("DueDate")?.ok_or_else(|| null_column("DueDate"))?,
+            ship_date: row.try_get::<chrono::NaiveDateTime, _>("ShipDate")?,
+            status: row.try_get::<u8, _>("Status")?.ok_or_else(|| null_column("Status"))?,
+            sales_order_number: row.try_get::<&str, _>("SalesOrderNumber")?.map(str::to_owned).ok_or_else(|| null_column("SalesOrderNumber"))?,
+            credit_card_approval_code: row.try_get::<&str, _>("CreditCardApprovalCode")?.map(str::to_owned),
+            sub_total: row.try_get::<f64, _>("SubTotal")?.ok_or_else(|| null_column("SubTotal"))?,
+            tax_amt: row.try_get::<f64, _>("TaxAmt")?.ok_or_else(|| null_column("TaxAmt"))?,
+            freight: row.try_get::<f64, _>("Freight")?.ok_or_else(|| null_column("Freight"))?,
+            total_due: row.try_get::<f64, _>("TotalDue")?.ok_or_else(|| null_column("TotalDue"))?,
+            comment: row.try_get::<&str, _>("Comment")?.map(str::to_owned),
+            rowguid: row.try_get::<uuid::Uuid, _>("rowguid")?.ok_or_else(|| null_column("rowguid"))?,
+            modified_date: row.try_get::<chrono::NaiveDateTime, _>("ModifiedDate")?,
+        })
+    }
+}
+
+/// Output of `[dbo].[uspGetSaleOrderByID]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UspGetSaleOrderByIdOutput {
+    /// Value of the `return` statement
+    pub return_value: i32,
+}
+
+/// Calls `[dbo].[uspGetSaleOrderByID]`.
+pub async fn usp_get_sale_order_by_id(
+    client: &mut tiberius::Client<tokio_util::compat::Compat<tokio::net::TcpStream>>,
+    sales_order_id: i32,
+) -> tiberius::Result<(Vec<UspGetSaleOrderByIdRow>, UspGetSaleOrderByIdOutput)> {
+    let mut query = tiberius::Query::new("declare @ReturnValue int;\nexec @ReturnValue = [dbo].[uspGetSaleOrderByID] @SalesOrderID = @P1;\nselect @ReturnValue as [ReturnValue];");
+    query.bind(sales_order_id);
+
+    let results = query.query(client).await?.into_results().await?;
+    let row = results.last().and_then(|rows| rows.first()).ok_or_else(|| {
+        tiberius::error::Error::Protocol("The procedure returned no output".into())
+    })?;
+    let output = UspGetSaleOrderByIdOutput {
+        return_value: row.try_get::<i32, _>("ReturnValue")?.ok_or_else(|| null_column("ReturnValue"))?,
+    };
+
+    let rows = match results.first() {
+        Some(rows) if results.len() > 1 => rows
+            .iter()
+            .map(UspGetSaleOrderByIdRow::from_row)
+            .collect::<tiberius::Result<Vec<_>>>()?,
+        _ => Vec::new(),
+    };
+
+    Ok((rows, output))
+}
+
+/// Calls `[dbo].[ufnGetSalesOrderStatusText]`.
+pub async fn ufn_get_sales_order_status_text(
+    client: &mut tiberius::Client<tokio_util::compat::Compat<tokio::net::TcpStream>>,
+    status: u8,
+) -> tiberius::Result<Option<String>> {
+    let mut query = tiberius::Query::new("select [dbo].[ufnGetSalesOrderStatusText](@P1) as [Value];");
+    query.bind(status);
+
+    let row = query.query(client).await?.into_row().await?;
+    match row {
+        Some(row) => Ok(row.try_get::<&str, _>("Value")?.map(str::to_owned)),
+        None => Ok(None),
+    }
+}
+
+/// A row returned by `[dbo].[ufnGetSalesOrderWithTotalDueMoreThan]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UfnGetSalesOrderWithTotalDueMoreThanRow {
+    pub sales_order_id: i32,
+    pub sub_total: f64,
+    pub tax_amt: f64,
+    pub freight: f64,
+    pub total_due: f64,
+}
+
+impl UfnGetSalesOrderWithTotalDueMoreThanRow {
+    pub fn from_row(row: &tiberius::Row) -> tiberius::Result<Self> {
+        Ok(UfnGetSalesOrderWithTotalDueMoreThanRow {
+            sales_order_id: row.try_get::<i32, _>("SalesOrderID")?.ok_or_else(|| null_column("SalesOrderID"))?,
+            sub_total: row.try_get::<f64, _>("SubTotal")?.ok_or_else(|| null_column("SubTotal"))?,
+            tax_amt: row.try_get::<f64, _>("TaxAmt")?.ok_or_else(|| null_column("TaxAmt"))?,
+            freight: row.try_get::<f64, _>("Freight")?.ok_or_else(|| null_column("Freight"))?,
+            total_due: row.try_get::<f64, _>("TotalDue")?.ok_or_else(|| null_column("TotalDue"))?,
+        })
+    }
+}
+
+/// Calls `[dbo].[ufnGetSalesOrderWithTotalDueMoreThan]`.
+pub async fn ufn_get_sales_order_with_total_due_more_than(
+    client: &mut tiberius::Client<tokio_util::compat::Compat<tokio::net::TcpStream>>,
+    total_due: f64,
+) -> tiberius::Result<Vec<UfnGetSalesOrderWithTotalDueMoreThanRow>> {
+    let mut query = tiberius::Query::new("select [SalesOrderID], [SubTotal], [TaxAmt], [Freight], [TotalDue] from [dbo].[ufnGetSalesOrderWithTotalDueMoreThan](@P1);");
+    query.bind(total_due);
+
+    let rows = query.query(client).await?.into_first_result().await?;
+    rows.iter().map(UfnGetSalesOrderWithTotalDueMoreThanRow::from_row).collect()
+}
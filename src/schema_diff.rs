@@ -0,0 +1,789 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tiberius::Client;
+use tokio::net::TcpStream;
+use tokio_util::compat::Compat;
+
+use crate::connections::connect_to_local_server;
+use crate::identifiers::quote;
+use crate::pagination::required;
+use crate::{
+    check_constraint, column_definition, create_index_statement, create_table_statements,
+    describe_table, foreign_key_constraint, index_constraint, is_constraint_index,
+    sales_order_header, sales_order_header_definition, sales_order_objects, CheckConstraint,
+    Column, DefaultConstraint, ForeignKey, Index, ObjectKind, ObjectName, ProgrammableObject,
+    TableSchema,
+};
+
+/// The text of a procedure, function or view as kept in `sys.sql_modules`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleDefinition {
+    pub name: ObjectName,
+    pub kind: ObjectKind,
+    pub definition: String,
+}
+
+impl From<&ProgrammableObject> for ModuleDefinition {
+    fn from(object: &ProgrammableObject) -> Self {
+        ModuleDefinition {
+            name: object.name.clone(),
+            kind: object.kind,
+            definition: object.statement(),
+        }
+    }
+}
+
+/// Tables and modules to compare, read from a database or from a file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseSchema {
+    pub tables: Vec<TableSchema>,
+    pub modules: Vec<ModuleDefinition>,
+}
+
+impl DatabaseSchema {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        Ok(json)
+    }
+}
+
+/// Reads the given tables and modules from the current database,
+/// the ones that don't exist are left out.
+pub async fn read_database_schema(
+    client: &mut Client<Compat<TcpStream>>,
+    tables: &[ObjectName],
+    modules: &[ObjectName],
+) -> Result<DatabaseSchema, Box<dyn std::error::Error>> {
+    let mut schema = DatabaseSchema::default();
+
+    for table in tables {
+        if let Some(table) = describe_table(client, table).await? {
+            schema.tables.push(table);
+        }
+    }
+
+    for name in modules {
+        let row = client
+            .query(
+                r#"
+select rtrim(o.type) as ObjectType, m.definition
+from sys.objects o
+     join sys.sql_modules m on m.object_id = o.object_id
+where o.object_id = object_id(@P1)
+                "#,
+                &[&name.to_string()],
+            )
+            .await?
+            .into_row()
+            .await?;
+
+        if let Some(row) = row {
+            let object_type: &str = required(&row, "ObjectType")?;
+            schema.modules.push(ModuleDefinition {
+                name: name.clone(),
+                kind: ObjectKind::from_object_type(object_type)
+                    .ok_or_else(|| format!("Unknown object type {}", object_type))?,
+                definition: required::<&str>(&row, "definition")?.to_owned(),
+            });
+        }
+    }
+
+    Ok(schema)
+}
+
+/// A difference between the desired schema and the live one.
+///
+/// Changed constraints and indexes are dropped and created again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaChange {
+    CreateTable(TableSchema),
+    DropTable(ObjectName),
+    AddColumn {
+        table: ObjectName,
+        column: Column,
+    },
+    DropColumn {
+        table: ObjectName,
+        column: Column,
+    },
+    AlterColumn {
+        table: ObjectName,
+        from: Column,
+        to: Column,
+    },
+    AddDefault {
+        table: ObjectName,
+        column: String,
+        default: DefaultConstraint,
+    },
+    DropDefault {
+        table: ObjectName,
+        column: String,
+        default: DefaultConstraint,
+    },
+    ChangeDefault {
+        table: ObjectName,
+        column: String,
+        from: DefaultConstraint,
+        to: DefaultConstraint,
+    },
+    AddCheck {
+        table: ObjectName,
+        check: CheckConstraint,
+    },
+    DropCheck {
+        table: ObjectName,
+        check: CheckConstraint,
+    },
+    ChangeCheck {
+        table: ObjectName,
+        from: CheckConstraint,
+        to: CheckConstraint,
+    },
+    AddIndex {
+        table: ObjectName,
+        index: Index,
+    },
+    DropIndex {
+        table: ObjectName,
+        index: Index,
+    },
+    ChangeIndex {
+        table: ObjectName,
+        from: Index,
+        to: Index,
+    },
+    AddForeignKey {
+        table: ObjectName,
+        foreign_key: ForeignKey,
+    },
+    DropForeignKey {
+        table: ObjectName,
+        foreign_key: ForeignKey,
+    },
+    ChangeForeignKey {
+        table: ObjectName,
+        from: ForeignKey,
+        to: ForeignKey,
+    },
+    CreateModule(ModuleDefinition),
+    AlterModule {
+        from: ModuleDefinition,
+        to: ModuleDefinition,
+    },
+    DropModule(ModuleDefinition),
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaChange::CreateTable(table) => write!(f, "Table {} added", table.name),
+            SchemaChange::DropTable(table) => write!(f, "Table {} removed", table),
+            SchemaChange::AddColumn { table, column } => {
+                write!(f, "Column {}.{} added", table, quote(&column.name))
+            }
+            SchemaChange::DropColumn { table, column } => {
+                write!(f, "Column {}.{} removed", table, quote(&column.name))
+            }
+            SchemaChange::AlterColumn { table, from, to } => write!(
+                f,
+                "Column {}.{} changed from {} to {}",
+                table,
+                quote(&to.name),
+                column_definition(&without_default(from)),
+                column_definition(&without_default(to))
+            ),
+            SchemaChange::AddDefault {
+                table,
+                column,
+                default,
+            } => write!(
+                f,
+                "Default {} added to {}.{}",
+                default.definition,
+                table,
+                quote(column)
+            ),
+            SchemaChange::DropDefault { table, column, .. } => {
+                write!(f, "Default of {}.{} removed", table, quote(column))
+            }
+            SchemaChange::ChangeDefault {
+                table,
+                column,
+                from,
+                to,
+            } => write!(
+                f,
+                "Default of {}.{} changed from {} to {}",
+                table,
+                quote(column),
+                from.definition,
+                to.definition
+            ),
+            SchemaChange::AddCheck { table, check } => {
+                write!(f, "Check {} added to {}", quote(&check.name), table)
+            }
+            SchemaChange::DropCheck { table, check } => {
+                write!(f, "Check {} removed from {}", quote(&check.name), table)
+            }
+            SchemaChange::ChangeCheck { table, from, to } => write!(
+                f,
+                "Check {} of {} changed from {} to {}",
+                quote(&to.name),
+                table,
+                from.definition,
+                to.definition
+            ),
+            SchemaChange::AddIndex { table, index } => {
+                write!(f, "Index {} added to {}", quote(&index.name), table)
+            }
+            SchemaChange::DropIndex { table, index } => {
+                write!(f, "Index {} removed from {}", quote(&index.name), table)
+            }
+            SchemaChange::ChangeIndex { table, to, .. } => {
+                write!(f, "Index {} of {} changed", quote(&to.name), table)
+            }
+            SchemaChange::AddForeignKey { table, foreign_key } => write!(
+                f,
+                "Foreign key {} added to {}",
+                quote(&foreign_key.name),
+                table
+            ),
+            SchemaChange::DropForeignKey { table, foreign_key } => write!(
+                f,
+                "Foreign key {} removed from {}",
+                quote(&foreign_key.name),
+                table
+            ),
+            SchemaChange::ChangeForeignKey { table, to, .. } => {
+                write!(f, "Foreign key {} of {} changed", quote(&to.name), table)
+            }
+            SchemaChange::CreateModule(module) => {
+                write!(f, "{:?} {} added", module.kind, module.name)
+            }
+            SchemaChange::AlterModule { to, .. } => {
+                write!(f, "{:?} {} changed", to.kind, to.name)
+            }
+            SchemaChange::DropModule(module) => {
+                write!(f, "{:?} {} removed", module.kind, module.name)
+            }
+        }
+    }
+}
+
+// The order of the statements in the script, objects are dropped
+// before the things they depend on and created after them
+const DROP_FOREIGN_KEYS: u8 = 0;
+const DROP_CONSTRAINTS: u8 = 1;
+const DROP_MODULES: u8 = 2;
+const DROP_COLUMNS: u8 = 3;
+const ALTER_COLUMNS: u8 = 4;
+const ADD_COLUMNS: u8 = 5;
+const ADD_CONSTRAINTS: u8 = 6;
+const ADD_FOREIGN_KEYS: u8 = 7;
+const CREATE_MODULES: u8 = 8;
+
+fn drop_constraint(table: &ObjectName, name: &str) -> String {
+    format!("alter table {} drop constraint {}", table, quote(name))
+}
+
+fn drop_index(table: &ObjectName, index: &Index) -> String {
+    if is_constraint_index(index) {
+        drop_constraint(table, &index.name)
+    } else {
+        format!("drop index {} on {}", quote(&index.name), table)
+    }
+}
+
+fn add_index(table: &ObjectName, index: &Index) -> String {
+    if is_constraint_index(index) {
+        format!("alter table {} add {}", table, index_constraint(index))
+    } else {
+        create_index_statement(table, index)
+    }
+}
+
+fn add_default(table: &ObjectName, column: &str, default: &DefaultConstraint) -> String {
+    let name = if default.is_system_named {
+        String::new()
+    } else {
+        format!(" constraint {}", quote(&default.name))
+    };
+
+    format!(
+        "alter table {} add{} default {} for {}",
+        table,
+        name,
+        default.definition,
+        quote(column)
+    )
+}
+
+fn add_column(table: &ObjectName, column: &Column) -> String {
+    format!("alter table {} add {}", table, column_definition(column))
+}
+
+fn drop_column(table: &ObjectName, column: &Column) -> String {
+    format!("alter table {} drop column {}", table, quote(&column.name))
+}
+
+fn without_default(column: &Column) -> Column {
+    Column {
+        default: None,
+        ..column.clone()
+    }
+}
+
+// `alter column` can't change an identity or a computed column,
+// the column is dropped and added again
+fn needs_rebuild(from: &Column, to: &Column) -> bool {
+    from.identity != to.identity || from.computed.is_some() || to.computed.is_some()
+}
+
+impl SchemaChange {
+    /// The statements that apply the change, with the order they take in the script.
+    fn statements(&self) -> Vec<(u8, String)> {
+        match self {
+            SchemaChange::CreateTable(table) => create_table_statements(table)
+                .into_iter()
+                .map(|s| (ADD_COLUMNS, s))
+                .collect(),
+            SchemaChange::DropTable(table) => {
+                vec![(DROP_COLUMNS, format!("drop table {}", table))]
+            }
+            SchemaChange::AddColumn { table, column } => {
+                vec![(ADD_COLUMNS, add_column(table, column))]
+            }
+            SchemaChange::DropColumn { table, column } => {
+                let mut statements = Vec::new();
+                if let Some(default) = &column.default {
+                    statements.push((DROP_CONSTRAINTS, drop_constraint(table, &default.name)));
+                }
+                statements.push((DROP_COLUMNS, drop_column(table, column)));
+                statements
+            }
+            SchemaChange::AlterColumn { table, from, to } if needs_rebuild(from, to) => {
+                let mut statements = Vec::new();
+                if let Some(default) = &from.default {
+                    statements.push((DROP_CONSTRAINTS, drop_constraint(table, &default.name)));
+                }
+                statements.push((DROP_COLUMNS, drop_column(table, from)));
+                statements.push((ADD_COLUMNS, add_column(table, to)));
+                statements
+            }
+            SchemaChange::AlterColumn { table, to, .. } => {
+                let mut definition = format!("{} {}", quote(&to.name), to.data_type);
+                if let Some(collation) = &to.collation {
+                    definition.push_str(" collate ");
+                    definition.push_str(collation);
+                }
+                definition.push_str(if to.is_nullable { " null" } else { " not null" });
+
+                vec![(
+                    ALTER_COLUMNS,
+                    format!("alter table {} alter column {}", table, definition),
+                )]
+            }
+            SchemaChange::AddDefault {
+                table,
+                column,
+                default,
+            } => vec![(ADD_CONSTRAINTS, add_default(table, column, default))],
+            SchemaChange::DropDefault { table, default, .. } => {
+                vec![(DROP_CONSTRAINTS, drop_constraint(table, &default.name))]
+            }
+            SchemaChange::ChangeDefault {
+                table,
+                column,
+                from,
+                to,
+            } => vec![
+                (DROP_CONSTRAINTS, drop_constraint(table, &from.name)),
+                (ADD_CONSTRAINTS, add_default(table, column, to)),
+            ],
+            SchemaChange::AddCheck { table, check } => vec![(
+                ADD_CONSTRAINTS,
+                format!("alter table {} add {}", table, check_constraint(check)),
+            )],
+            SchemaChange::DropCheck { table, check } => {
+                vec![(DROP_CONSTRAINTS, drop_constraint(table, &check.name))]
+            }
+            SchemaChange::ChangeCheck { table, from, to } => vec![
+                (DROP_CONSTRAINTS, drop_constraint(table, &from.name)),
+                (
+                    ADD_CONSTRAINTS,
+                    format!("alter table {} add {}", table, check_constraint(to)),
+                ),
+            ],
+            SchemaChange::AddIndex { table, index } => {
+                vec![(ADD_CONSTRAINTS, add_index(table, index))]
+            }
+            SchemaChange::DropIndex { table, index } => {
+                vec![(DROP_CONSTRAINTS, drop_index(table, index))]
+            }
+            SchemaChange::ChangeIndex { table, from, to } => vec![
+                (DROP_CONSTRAINTS, drop_index(table, from)),
+                (ADD_CONSTRAINTS, add_index(table, to)),
+            ],
+            SchemaChange::AddForeignKey { table, foreign_key } => vec![(
+                ADD_FOREIGN_KEYS,
+                format!(
+                    "alter table {} add {}",
+                    table,
+                    foreign_key_constraint(foreign_key)
+                ),
+            )],
+            SchemaChange::DropForeignKey { table, foreign_key } => {
+                vec![(DROP_FOREIGN_KEYS, drop_constraint(table, &foreign_key.name))]
+            }
+            SchemaChange::ChangeForeignKey { table, from, to } => vec![
+                (DROP_FOREIGN_KEYS, drop_constraint(table, &from.name)),
+                (
+                    ADD_FOREIGN_KEYS,
+                    format!("alter table {} add {}", table, foreign_key_constraint(to)),
+                ),
+            ],
+            SchemaChange::CreateModule(module) | SchemaChange::AlterModule { to: module, .. } => {
+                vec![(CREATE_MODULES, module.definition.trim().to_owned())]
+            }
+            SchemaChange::DropModule(module) => {
+                let keyword = match module.kind {
+                    ObjectKind::Procedure => "procedure",
+                    ObjectKind::ScalarFunction | ObjectKind::TableValuedFunction => "function",
+                    ObjectKind::View => "view",
+                };
+                vec![(DROP_MODULES, format!("drop {} {}", keyword, module.name))]
+            }
+        }
+    }
+}
+
+// Compares expressions the way SQL Server keeps them and the way they are written:
+// `((0))` and `0`, `([Status]>=(0))` and `[Status] >= 0` are the same
+fn normalize_expression(expression: &str) -> String {
+    let mut normalized = String::with_capacity(expression.len());
+    let mut in_string = false;
+
+    for c in expression.chars() {
+        if c == '\'' {
+            in_string = !in_string;
+        }
+
+        if in_string || c == '\'' {
+            normalized.push(c);
+        } else if !c.is_whitespace() && !matches!(c, '(' | ')' | '[' | ']') {
+            normalized.extend(c.to_lowercase());
+        }
+    }
+
+    normalized
+}
+
+fn same_expression(a: &str, b: &str) -> bool {
+    normalize_expression(a) == normalize_expression(b)
+}
+
+// Compares what `alter column` changes; defaults are compared on their own.
+// A column without collation in the desired schema takes the one of the database.
+fn same_column(desired: &Column, live: &Column) -> bool {
+    let same_collation = desired.collation.is_none() || desired.collation == live.collation;
+    let same_computed = match (&desired.computed, &live.computed) {
+        (Some(a), Some(b)) => {
+            a.is_persisted == b.is_persisted && same_expression(&a.definition, &b.definition)
+        }
+        (None, None) => true,
+        _ => false,
+    };
+
+    // The nullability of a computed column comes from its expression
+    let same_nullability = desired.computed.is_some() || desired.is_nullable == live.is_nullable;
+
+    desired.name == live.name
+        && same_computed
+        && same_nullability
+        && same_collation
+        && desired.identity == live.identity
+        && (desired.computed.is_some()
+            || desired.data_type.to_string() == live.data_type.to_string())
+}
+
+fn same_index(a: &Index, b: &Index) -> bool {
+    a.is_primary_key == b.is_primary_key
+        && a.is_unique == b.is_unique
+        && a.is_unique_constraint == b.is_unique_constraint
+        && a.is_clustered() == b.is_clustered()
+        && a.columns.len() == b.columns.len()
+        && a.columns.iter().zip(&b.columns).all(|(a, b)| {
+            a.name.eq_ignore_ascii_case(&b.name)
+                && a.is_descending == b.is_descending
+                && a.is_included == b.is_included
+        })
+}
+
+fn same_foreign_key(a: &ForeignKey, b: &ForeignKey) -> bool {
+    a.referenced_table == b.referenced_table
+        && a.on_delete == b.on_delete
+        && a.on_update == b.on_update
+        && a.columns.len() == b.columns.len()
+        && a.columns.iter().zip(&b.columns).all(|(a, b)| {
+            a.column.eq_ignore_ascii_case(&b.column)
+                && a.referenced_column
+                    .eq_ignore_ascii_case(&b.referenced_column)
+        })
+}
+
+// Pairs the items of both lists by name: (desired, live)
+fn pair_by_name<'a, T>(
+    desired: &'a [T],
+    live: &'a [T],
+    name: impl Fn(&T) -> &str,
+) -> Vec<(Option<&'a T>, Option<&'a T>)> {
+    let mut pairs: Vec<(Option<&T>, Option<&T>)> = desired
+        .iter()
+        .map(|d| {
+            let found = live.iter().find(|l| name(l).eq_ignore_ascii_case(name(d)));
+            (Some(d), found)
+        })
+        .collect();
+
+    pairs.extend(
+        live.iter()
+            .filter(|l| {
+                !desired
+                    .iter()
+                    .any(|d| name(d).eq_ignore_ascii_case(name(l)))
+            })
+            .map(|l| (None, Some(l))),
+    );
+
+    pairs
+}
+
+/// Compares the desired layout of a table with the live one.
+pub fn diff_tables(desired: &TableSchema, live: &TableSchema) -> Vec<SchemaChange> {
+    let table = &desired.name;
+    let mut changes = Vec::new();
+
+    for pair in pair_by_name(&desired.columns, &live.columns, |c| &c.name) {
+        match pair {
+            (Some(to), None) => changes.push(SchemaChange::AddColumn {
+                table: table.clone(),
+                column: to.clone(),
+            }),
+            (None, Some(from)) => changes.push(SchemaChange::DropColumn {
+                table: table.clone(),
+                column: from.clone(),
+            }),
+            (Some(to), Some(from)) => {
+                if !same_column(to, from) {
+                    changes.push(SchemaChange::AlterColumn {
+                        table: table.clone(),
+                        from: from.clone(),
+                        to: to.clone(),
+                    });
+                    // A column added again comes with its default
+                    if needs_rebuild(from, to) {
+                        continue;
+                    }
+                }
+
+                let column = to.name.clone();
+                match (&to.default, &from.default) {
+                    (Some(default), None) => changes.push(SchemaChange::AddDefault {
+                        table: table.clone(),
+                        column,
+                        default: default.clone(),
+                    }),
+                    (None, Some(default)) => changes.push(SchemaChange::DropDefault {
+                        table: table.clone(),
+                        column,
+                        default: default.clone(),
+                    }),
+                    (Some(to), Some(from))
+                        if !same_expression(&to.definition, &from.definition) =>
+                    {
+                        changes.push(SchemaChange::ChangeDefault {
+                            table: table.clone(),
+                            column,
+                            from: from.clone(),
+                            to: to.clone(),
+                        })
+                    }
+                    _ => {}
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    for pair in pair_by_name(&desired.check_constraints, &live.check_constraints, |c| {
+        &c.name
+    }) {
+        match pair {
+            (Some(to), None) => changes.push(SchemaChange::AddCheck {
+                table: table.clone(),
+                check: to.clone(),
+            }),
+            (None, Some(from)) => changes.push(SchemaChange::DropCheck {
+                table: table.clone(),
+                check: from.clone(),
+            }),
+            (Some(to), Some(from)) if !same_expression(&to.definition, &from.definition) => changes
+                .push(SchemaChange::ChangeCheck {
+                    table: table.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                }),
+            _ => {}
+        }
+    }
+
+    for pair in pair_by_name(&desired.indexes, &live.indexes, |i| &i.name) {
+        match pair {
+            (Some(to), None) => changes.push(SchemaChange::AddIndex {
+                table: table.clone(),
+                index: to.clone(),
+            }),
+            (None, Some(from)) => changes.push(SchemaChange::DropIndex {
+                table: table.clone(),
+                index: from.clone(),
+            }),
+            (Some(to), Some(from)) if !same_index(to, from) => {
+                changes.push(SchemaChange::ChangeIndex {
+                    table: table.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    for pair in pair_by_name(&desired.foreign_keys, &live.foreign_keys, |f| &f.name) {
+        match pair {
+            (Some(to), None) => changes.push(SchemaChange::AddForeignKey {
+                table: table.clone(),
+                foreign_key: to.clone(),
+            }),
+            (None, Some(from)) => changes.push(SchemaChange::DropForeignKey {
+                table: table.clone(),
+                foreign_key: from.clone(),
+            }),
+            (Some(to), Some(from)) if !same_foreign_key(to, from) => {
+                changes.push(SchemaChange::ChangeForeignKey {
+                    table: table.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    changes
+}
+
+/// Compares the desired schema with the live one.
+///
+/// Tables and modules only in the live schema are reported as removed,
+/// compare with a live schema read for the same names to leave the others alone.
+pub fn diff_schemas(desired: &DatabaseSchema, live: &DatabaseSchema) -> Vec<SchemaChange> {
+    let mut changes = Vec::new();
+
+    for table in &desired.tables {
+        match live.tables.iter().find(|t| t.name == table.name) {
+            Some(live_table) => changes.extend(diff_tables(table, live_table)),
+            None => changes.push(SchemaChange::CreateTable(table.clone())),
+        }
+    }
+    for table in &live.tables {
+        if !desired.tables.iter().any(|t| t.name == table.name) {
+            changes.push(SchemaChange::DropTable(table.name.clone()));
+        }
+    }
+
+    // Definitions are compared as they are, like `deploy_object` does
+    for module in &desired.modules {
+        match live.modules.iter().find(|m| m.name == module.name) {
+            Some(live_module) if live_module.definition.trim() != module.definition.trim() => {
+                changes.push(SchemaChange::AlterModule {
+                    from: live_module.clone(),
+                    to: module.clone(),
+                })
+            }
+            Some(_) => {}
+            None => changes.push(SchemaChange::CreateModule(module.clone())),
+        }
+    }
+    for module in &live.modules {
+        if !desired.modules.iter().any(|m| m.name == module.name) {
+            changes.push(SchemaChange::DropModule(module.clone()));
+        }
+    }
+
+    changes
+}
+
+/// Writes the script that turns the live schema into the desired one,
+/// with a `GO` after every statement so it can be run with `run_script`.
+///
+/// Dropped columns lose their data, so do the columns that are dropped and added again
+/// because `alter column` can't change them (identity and computed columns).
+pub fn alter_script(changes: &[SchemaChange]) -> String {
+    let mut statements: Vec<(u8, String)> =
+        changes.iter().flat_map(SchemaChange::statements).collect();
+    // The sort is stable, statements of the same step keep the order of the changes
+    statements.sort_by_key(|(step, _)| *step);
+
+    let mut script = String::new();
+    for (_, statement) in statements {
+        script.push_str(&statement);
+        script.push_str("\ngo\n\n");
+    }
+
+    script
+}
+
+/// The desired layout of the tables of the examples, with the modules they deploy
+pub fn sales_order_schema() -> Result<DatabaseSchema, Box<dyn std::error::Error>> {
+    let table = sales_order_header();
+
+    Ok(DatabaseSchema {
+        tables: vec![sales_order_header_definition(&table).build()?],
+        modules: sales_order_objects(&table)?
+            .iter()
+            .map(ModuleDefinition::from)
+            .collect(),
+    })
+}
+
+pub async fn compare_sales_order_schema() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let desired = sales_order_schema()?;
+    let tables: Vec<ObjectName> = desired.tables.iter().map(|t| t.name.clone()).collect();
+    let modules: Vec<ObjectName> = desired.modules.iter().map(|m| m.name.clone()).collect();
+    let live = read_database_schema(&mut client, &tables, &modules).await?;
+
+    let changes = diff_schemas(&desired, &live);
+    if changes.is_empty() {
+        println!("The database has the expected schema");
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+
+    if !changes.is_empty() {
+        println!("\n{}", alter_script(&changes));
+    }
+
+    client.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use tiberius::Client;
+use tokio::net::TcpStream;
+use tokio_util::compat::Compat;
+
+use crate::connections::connect_to_local_server;
+
+/// A piece of a script between `GO` separators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub sql: String,
+    /// Line of the script where the batch starts (1 based)
+    pub start_line: usize,
+    /// How many times the batch runs, from `GO n`
+    pub repeat: u32,
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(std::io::Error),
+    /// A `$(Name)` variable has no value
+    UndefinedVariable {
+        name: String,
+        line: usize,
+    },
+    /// A `:setvar` command or a `$(` has no valid syntax
+    InvalidCommand {
+        line: usize,
+        message: String,
+    },
+    /// The count of `GO n` is not a positive number
+    InvalidRepeatCount {
+        line: usize,
+    },
+    /// The server rejected a batch, `line` is the line in the script
+    Batch {
+        batch: usize,
+        line: usize,
+        source: tiberius::error::Error,
+    },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Io(e) => write!(f, "Cannot read the script: {}", e),
+            ScriptError::UndefinedVariable { name, line } => {
+                write!(f, "Line {}: the variable $({}) is not defined", line, name)
+            }
+            ScriptError::InvalidCommand { line, message } => {
+                write!(f, "Line {}: {}", line, message)
+            }
+            ScriptError::InvalidRepeatCount { line } => {
+                write!(f, "Line {}: the GO count must be a positive number", line)
+            }
+            ScriptError::Batch {
+                batch,
+                line,
+                source,
+            } => write!(f, "Batch {} failed at line {}: {}", batch, line, source),
+        }
+    }
+}
+
+impl ScriptError {
+    /// The error of the batch with the given number (1 based),
+    /// at the line of the script where the server reported it
+    pub fn batch_failed(number: usize, batch: &Batch, source: tiberius::error::Error) -> Self {
+        // The server counts lines from the start of the batch
+        let line = match &source {
+            tiberius::error::Error::Server(token) if token.line() > 0 => {
+                batch.start_line + token.line() as usize - 1
+            }
+            _ => batch.start_line,
+        };
+
+        ScriptError::Batch {
+            batch: number,
+            line,
+            source,
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScriptError::Io(e) => Some(e),
+            ScriptError::Batch { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ScriptError {
+    fn from(e: std::io::Error) -> Self {
+        ScriptError::Io(e)
+    }
+}
+
+// Where the scanner is, the GO separator only counts in `Code`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScanState {
+    Code,
+    LineComment,
+    // T-SQL block comments can be nested
+    BlockComment(usize),
+    SingleQuote,
+    DoubleQuote,
+    Bracket,
+}
+
+// Returns the repeat count if the line is a batch separator: `GO`, `go 5`, `GO -- done`
+pub(crate) fn separator(line: &str, line_number: usize) -> Result<Option<u32>, ScriptError> {
+    let trimmed = line.trim();
+    let code = match trimmed.find("--") {
+        Some(comment) => trimmed[..comment].trim_end(),
+        None => trimmed,
+    };
+
+    // `get` as the line may start with a character of several bytes
+    let Some(count) = code
+        .get(..2)
+        .filter(|go| go.eq_ignore_ascii_case("go"))
+        .map(|_| &code[2..])
+    else {
+        return Ok(None);
+    };
+    if count.is_empty() {
+        return Ok(Some(1));
+    }
+
+    // `GOTO label` and similar statements are not separators
+    if !count.starts_with(char::is_whitespace) {
+        return Ok(None);
+    }
+
+    match count.trim().parse::<u32>() {
+        Ok(n) if n > 0 => Ok(Some(n)),
+        _ => Err(ScriptError::InvalidRepeatCount { line: line_number }),
+    }
+}
+
+// Moves the scanner through a line that is not a separator
+pub(crate) fn scan_line(line: &str, state: ScanState) -> ScanState {
+    // Line comments end with the line
+    match scan(line, state) {
+        ScanState::LineComment => ScanState::Code,
+        state => state,
+    }
+}
+
+// Moves the scanner through a piece of a line
+pub(crate) fn scan(line: &str, mut state: ScanState) -> ScanState {
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        state = match (state, c) {
+            (ScanState::Code, '-') if chars.peek() == Some(&'-') => ScanState::LineComment,
+            (ScanState::Code, '/') if chars.peek() == Some(&'*') => {
+                chars.next();
+                ScanState::BlockComment(1)
+            }
+            (ScanState::Code, '\'') => ScanState::SingleQuote,
+            (ScanState::Code, '"') => ScanState::DoubleQuote,
+            (ScanState::Code, '[') => ScanState::Bracket,
+            (ScanState::BlockComment(depth), '/') if chars.peek() == Some(&'*') => {
+                chars.next();
+                ScanState::BlockComment(depth + 1)
+            }
+            (ScanState::BlockComment(depth), '*') if chars.peek() == Some(&'/') => {
+                chars.next();
+                if depth == 1 {
+                    ScanState::Code
+                } else {
+                    ScanState::BlockComment(depth - 1)
+                }
+            }
+            // A doubled quote is an escaped one and the string goes on
+            (ScanState::SingleQuote, '\'') if chars.peek() == Some(&'\'') => {
+                chars.next();
+                ScanState::SingleQuote
+            }
+            (ScanState::SingleQuote, '\'') => ScanState::Code,
+            (ScanState::DoubleQuote, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                ScanState::DoubleQuote
+            }
+            (ScanState::DoubleQuote, '"') => ScanState::Code,
+            (ScanState::Bracket, ']') if chars.peek() == Some(&']') => {
+                chars.next();
+                ScanState::Bracket
+            }
+            (ScanState::Bracket, ']') => ScanState::Code,
+            (state, _) => state,
+        };
+    }
+
+    state
+}
+
+/// Splits a script on its `GO` separators.
+///
+/// A `GO` inside a string, a quoted identifier or a comment is not a separator.
+/// Batches with only blank lines are skipped.
+pub fn split_batches(script: &str) -> Result<Vec<Batch>, ScriptError> {
+    let mut batches = Vec::new();
+    let mut state = ScanState::Code;
+    let mut sql = String::new();
+    let mut start_line = 1;
+
+    for (index, line) in script.lines().enumerate() {
+        let line_number = index + 1;
+
+        if state == ScanState::Code {
+            if let Some(repeat) = separator(line, line_number)? {
+                if !sql.trim().is_empty() {
+                    batches.push(Batch {
+                        sql: std::mem::take(&mut sql),
+                        start_line,
+                        repeat,
+                    });
+                }
+                sql.clear();
+                start_line = line_number + 1;
+                continue;
+            }
+        }
+
+        if sql.is_empty() && line.trim().is_empty() {
+            // Leading blank lines are not part of the batch
+            start_line = line_number + 1;
+            continue;
+        }
+
+        state = scan_line(line, state);
+        sql.push_str(line);
+        sql.push('\n');
+    }
+
+    if !sql.trim().is_empty() {
+        batches.push(Batch {
+            sql,
+            start_line,
+            repeat: 1,
+        });
+    }
+
+    Ok(batches)
+}
+
+/// Replaces the sqlcmd variables `$(Name)` of a batch with their values.
+///
+/// Variable names are case insensitive like in sqlcmd.
+pub fn substitute_variables(
+    batch: &Batch,
+    variables: &HashMap<String, String>,
+) -> Result<String, ScriptError> {
+    let mut sql = String::with_capacity(batch.sql.len());
+    let mut rest = batch.sql.as_str();
+
+    while let Some(start) = rest.find("$(") {
+        let line = batch.start_line
+            + batch.sql[..batch.sql.len() - rest.len() + start]
+                .matches('\n')
+                .count();
+
+        sql.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find(')').ok_or_else(|| ScriptError::InvalidCommand {
+            line,
+            message: "the variable has no closing parenthesis".to_owned(),
+        })?;
+
+        let name = &after[..end];
+        let value = variables
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .ok_or_else(|| ScriptError::UndefinedVariable {
+                name: name.to_owned(),
+                line,
+            })?;
+
+        sql.push_str(value);
+        rest = &after[end + 1..];
+    }
+
+    sql.push_str(rest);
+
+    Ok(sql)
+}
+
+// Reads the `:setvar Name "Value"` commands of a batch, they are removed from it
+fn take_setvar_commands(
+    batch: &mut Batch,
+    variables: &mut HashMap<String, String>,
+) -> Result<(), ScriptError> {
+    let mut sql = String::with_capacity(batch.sql.len());
+
+    for (index, line) in batch.sql.lines().enumerate() {
+        let trimmed = line.trim();
+
+        let command = trimmed.get(..7);
+        if command.is_some_and(|command| command.eq_ignore_ascii_case(":setvar")) {
+            let line_number = batch.start_line + index;
+            let mut parts = trimmed[7..].trim().splitn(2, char::is_whitespace);
+            let name = parts.next().filter(|n| !n.is_empty()).ok_or_else(|| {
+                ScriptError::InvalidCommand {
+                    line: line_number,
+                    message: ":setvar needs a variable name".to_owned(),
+                }
+            })?;
+            let value = parts.next().unwrap_or("").trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+
+            variables.insert(name.to_owned(), value.to_owned());
+
+            // Keep the line so the server reports the same line numbers
+            sql.push('\n');
+            continue;
+        }
+
+        sql.push_str(line);
+        sql.push('\n');
+    }
+
+    batch.sql = sql;
+
+    Ok(())
+}
+
+/// Takes the `:setvar` commands out of a batch and substitutes its variables.
+///
+/// Returns `None` when nothing is left to send to the server.
+pub fn prepare_batch(
+    batch: &mut Batch,
+    variables: &mut HashMap<String, String>,
+) -> Result<Option<String>, ScriptError> {
+    take_setvar_commands(batch, variables)?;
+
+    if batch.sql.trim().is_empty() {
+        return Ok(None);
+    }
+
+    substitute_variables(batch, variables).map(Some)
+}
+
+/// Runs the batches of a script in order.
+///
+/// `:setvar` commands in the script add to `variables`.
+/// Returns the number of batches that were sent.
+pub async fn run_script(
+    client: &mut Client<Compat<TcpStream>>,
+    script: &str,
+    variables: &HashMap<String, String>,
+) -> Result<usize, ScriptError> {
+    let mut variables = variables.clone();
+    let batches = split_batches(script)?;
+    let mut executed = 0;
+
+    for (index, mut batch) in batches.into_iter().enumerate() {
+        let sql = match prepare_batch(&mut batch, &mut variables)? {
+            Some(sql) => sql,
+            None => continue,
+        };
+
+        for _ in 0..batch.repeat {
+            let result = match client.simple_query(sql.as_str()).await {
+                Ok(stream) => stream.into_results().await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                return Err(ScriptError::batch_failed(index + 1, &batch, e));
+            }
+        }
+
+        executed += 1;
+    }
+
+    Ok(executed)
+}
+
+pub async fn run_script_file(
+    client: &mut Client<Compat<TcpStream>>,
+    path: impl AsRef<Path>,
+    variables: &HashMap<String, String>,
+) -> Result<usize, ScriptError> {
+    let script = tokio::fs::read_to_string(path).await?;
+    run_script(client, &script, variables).await
+}
+
+pub async fn run_sales_order_reports_script() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let mut variables = HashMap::new();
+    variables.insert("Schema".to_owned(), "dbo".to_owned());
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/sql/sales_order_reports.sql");
+    let batches = run_script_file(&mut client, path, &variables).await?;
+    println!("Batches executed: {}", batches);
+
+    client.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use tiberius::{Client, Query};
+use tokio::net::TcpStream;
+use tokio_stream::StreamExt;
+use tokio_util::compat::Compat;
+
+use crate::connections::connect_to_local_server;
+use crate::pagination::SALES_ORDER_COLUMNS;
+use crate::{sales_order_header, Condition, ObjectName, SalesOrder, Select, SqlValue};
+
+/// Most parameters SQL Server accepts in a single request.
+pub const MAX_PARAMETERS: usize = 2100;
+
+// Queries with parameters are sent through `sp_executesql`,
+// its statement and parameter definition take two of them
+const RESERVED_PARAMETERS: usize = 2;
+
+/// Splits `values` so each chunk can be expanded to `IN (@P1, ..., @Pn)`
+/// in a query that already has `other_parameters` parameters.
+pub fn in_list_chunks<T>(
+    values: &[T],
+    other_parameters: usize,
+) -> Result<std::slice::Chunks<'_, T>, Box<dyn std::error::Error>> {
+    let size = MAX_PARAMETERS
+        .checked_sub(RESERVED_PARAMETERS + other_parameters)
+        .filter(|size| *size > 0)
+        .ok_or("The query has no parameters left for the IN list")?;
+
+    Ok(values.chunks(size))
+}
+
+/// A value that can be written to a JSON array and read back with `OPENJSON`.
+pub trait JsonElement {
+    fn write_json(&self, out: &mut String);
+}
+
+macro_rules! json_number {
+    ($($ty:ty),*) => {
+        $(
+            impl JsonElement for $ty {
+                fn write_json(&self, out: &mut String) {
+                    out.push_str(&self.to_string());
+                }
+            }
+        )*
+    };
+}
+
+json_number!(u8, i16, i32, i64, f64);
+
+impl JsonElement for str {
+    fn write_json(&self, out: &mut String) {
+        out.push('"');
+        for c in self.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+}
+
+impl JsonElement for &str {
+    fn write_json(&self, out: &mut String) {
+        (**self).write_json(out);
+    }
+}
+
+impl JsonElement for String {
+    fn write_json(&self, out: &mut String) {
+        self.as_str().write_json(out);
+    }
+}
+
+impl JsonElement for uuid::Uuid {
+    fn write_json(&self, out: &mut String) {
+        self.to_string().as_str().write_json(out);
+    }
+}
+
+/// Writes `values` as a JSON array to be sent in a single `nvarchar(max)` parameter,
+/// the server reads it with `OPENJSON(@P1) WITH ([value] int '$')`.
+///
+/// Unlike an `IN` list it has no limit in the number of values.
+pub fn json_array<T: JsonElement>(values: &[T]) -> String {
+    let mut json = String::from("[");
+
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        value.write_json(&mut json);
+    }
+
+    json.push(']');
+    json
+}
+
+/// Gets the sales orders with the given IDs using `IN` lists,
+/// a query is sent for every chunk of IDs that fits in the parameter limit.
+pub async fn select_sales_orders_by_ids(
+    client: &mut Client<Compat<TcpStream>>,
+    table: &ObjectName,
+    ids: &[i32],
+) -> Result<Vec<SalesOrder>, Box<dyn std::error::Error>> {
+    let mut orders = Vec::with_capacity(ids.len());
+
+    for chunk in in_list_chunks(ids, 0)? {
+        let query = Select::from(table)
+            .columns(&SALES_ORDER_COLUMNS)
+            .filter(Condition::is_in("SalesOrderID", chunk.iter().copied()))
+            .build()?
+            .into_query();
+
+        let mut rows = query.query(client).await?.into_row_stream();
+        while let Some(row) = rows.try_next().await? {
+            orders.push(SalesOrder::from_row(&row)?);
+        }
+    }
+
+    Ok(orders)
+}
+
+/// Gets the sales orders with the given IDs sending all of them
+/// as a JSON array in a single parameter.
+pub async fn select_sales_orders_by_ids_json(
+    client: &mut Client<Compat<TcpStream>>,
+    table: &ObjectName,
+    ids: &[i32],
+) -> Result<Vec<SalesOrder>, Box<dyn std::error::Error>> {
+    let mut query = Query::new(format!(
+        r#"select {}
+from {}
+where SalesOrderID in (select [value] from openjson(@P1) with ([value] int '$'))"#,
+        SALES_ORDER_COLUMNS.join(", "),
+        table
+    ));
+    query.bind(SqlValue::from(json_array(ids)));
+
+    let mut orders = Vec::with_capacity(ids.len());
+    let mut rows = query.query(client).await?.into_row_stream();
+    while let Some(row) = rows.try_next().await? {
+        orders.push(SalesOrder::from_row(&row)?);
+    }
+
+    Ok(orders)
+}
+
+pub async fn select_rows_by_ids() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+    let table = sales_order_header();
+
+    // More IDs than parameters allowed in a request,
+    // they are split in several queries
+    let ids: Vec<i32> = (1..=5000).collect();
+    let orders = select_sales_orders_by_ids(&mut client, &table, &ids).await?;
+    println!("Sales orders found with IN lists: {}", orders.len());
+
+    // The same IDs in a single JSON parameter
+    let orders = select_sales_orders_by_ids_json(&mut client, &table, &ids).await?;
+    println!("Sales orders found with OPENJSON: {}", orders.len());
+
+    for order in orders.iter().take(5) {
+        println!(
+            "SalesOrderID: {}, SalesOrderNumber: {}",
+            order.sales_order_id, order.sales_order_number
+        );
+    }
+
+    client.close().await?;
+
+    Ok(())
+}
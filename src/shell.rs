@@ -0,0 +1,588 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde_json::Value;
+use tiberius::Client;
+use tokio::net::TcpStream;
+use tokio_util::compat::Compat;
+
+use crate::pagination::required;
+use crate::scripts::{scan, scan_line, separator, ScanState};
+use crate::{ResultSet, ScriptError, TableSchema};
+
+/// A command of the interactive shell that starts with a backslash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaCommand {
+    /// `\d` lists the tables, `\d name` describes one
+    Describe(Option<String>),
+    /// `\df` lists the procedures and functions
+    ListRoutines,
+    /// `\timing` shows or hides the time of every batch
+    Timing,
+    /// `\x` shows a row per column instead of a table
+    Expanded,
+    /// `\?`
+    Help,
+    /// `\q`
+    Quit,
+}
+
+impl FromStr for MetaCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, char::is_whitespace);
+        let command = parts.next().unwrap_or("");
+        let argument = parts.next().map(str::trim).filter(|a| !a.is_empty());
+
+        match (command, argument) {
+            ("\\d", argument) => Ok(MetaCommand::Describe(argument.map(str::to_owned))),
+            ("\\df", None) => Ok(MetaCommand::ListRoutines),
+            ("\\timing", None) => Ok(MetaCommand::Timing),
+            ("\\x", None) => Ok(MetaCommand::Expanded),
+            ("\\?", None) => Ok(MetaCommand::Help),
+            ("\\q", None) => Ok(MetaCommand::Quit),
+            _ => Err(format!(
+                "Unknown command {}, \\? lists the commands",
+                s.trim()
+            )),
+        }
+    }
+}
+
+pub const SHELL_HELP: &str = r"\d [table]  list the tables or describe one
+\df         list the procedures and functions
+\timing     show the time of every batch
+\x          show every row as a list of columns
+\q          quit
+End a batch with GO or a line that ends with a semicolon, Ctrl-C cancels it.";
+
+/// What the shell does with the lines typed so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellCommand {
+    Meta(MetaCommand),
+    Execute { sql: String, repeat: u32 },
+}
+
+/// Collects the lines of a batch until a `GO` line or a line that ends with `;`.
+///
+/// Separators inside strings, quoted identifiers and comments are ignored
+/// as in `split_batches`.
+#[derive(Debug)]
+pub struct ShellInput {
+    buffer: String,
+    lines: usize,
+    state: ScanState,
+}
+
+impl Default for ShellInput {
+    fn default() -> Self {
+        ShellInput {
+            buffer: String::new(),
+            lines: 0,
+            state: ScanState::Code,
+        }
+    }
+}
+
+impl ShellInput {
+    pub fn new() -> Self {
+        ShellInput::default()
+    }
+
+    /// Lines of the batch so far
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    pub fn clear(&mut self) {
+        *self = ShellInput::default();
+    }
+
+    pub fn push_line(&mut self, line: &str) -> Result<Option<ShellCommand>, ScriptError> {
+        let line_number = self.lines + 1;
+
+        if self.state == ScanState::Code {
+            if self.lines == 0 && line.trim_start().starts_with('\\') {
+                return line
+                    .parse()
+                    .map(|command| Some(ShellCommand::Meta(command)))
+                    .map_err(|message| ScriptError::InvalidCommand {
+                        line: line_number,
+                        message,
+                    });
+            }
+
+            if let Some(repeat) = separator(line, line_number)? {
+                let sql = std::mem::take(&mut self.buffer);
+                self.clear();
+
+                if sql.trim().is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(ShellCommand::Execute { sql, repeat }));
+            }
+        }
+
+        if self.lines == 0 && line.trim().is_empty() {
+            return Ok(None);
+        }
+
+        // The semicolon ends the batch when it's not in a string or a comment
+        let code = line.trim_end();
+        let ends_batch = code
+            .strip_suffix(';')
+            .map(|before| scan(before, self.state) == ScanState::Code)
+            .unwrap_or(false);
+
+        self.state = scan_line(line, self.state);
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        self.lines += 1;
+
+        if ends_batch {
+            let sql = std::mem::take(&mut self.buffer);
+            self.clear();
+            return Ok(Some(ShellCommand::Execute { sql, repeat: 1 }));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Lines typed in the shell, kept in a file between sessions.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    const MAX_ENTRIES: usize = 1000;
+
+    /// `.tiberius_history` in the home directory
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(".tiberius_history"))
+    }
+
+    /// Reads the history file, it's created with the first line added.
+    pub fn load(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let mut entries: Vec<String> = match std::fs::read_to_string(&path) {
+            Ok(text) => text.lines().map(str::to_owned).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        // Lines are appended as they are typed, the file is cut when it's read
+        if entries.len() > Self::MAX_ENTRIES {
+            entries.drain(..entries.len() - Self::MAX_ENTRIES);
+            let mut text = entries.join("\n");
+            text.push('\n');
+            std::fs::write(&path, text)?;
+        }
+
+        Ok(History {
+            entries,
+            path: Some(path),
+        })
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Adds a line unless it's blank or the same as the last one.
+    pub fn add(&mut self, line: &str) -> std::io::Result<()> {
+        if line.trim().is_empty() || self.entries.last().map(String::as_str) == Some(line) {
+            return Ok(());
+        }
+
+        self.entries.push(line.to_owned());
+
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Completes the names of tables, views, columns, procedures and functions.
+#[derive(Debug, Clone, Default)]
+pub struct Completer {
+    names: Vec<String>,
+}
+
+impl Completer {
+    pub fn new(names: impl IntoIterator<Item = String>) -> Self {
+        let mut names: Vec<String> = names.into_iter().collect();
+        names.sort_by_key(|name| name.to_lowercase());
+        names.dedup();
+
+        Completer { names }
+    }
+
+    /// Reads the names of the current database, objects are also completed
+    /// with their schema (`dbo.SalesOrderHeader`).
+    pub async fn from_catalog(
+        client: &mut Client<Compat<TcpStream>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let rows = client
+            .simple_query(
+                r#"
+select schema_name(o.schema_id) as SchemaName, o.name as Name
+from sys.objects o
+where o.type in ('U', 'V', 'P', 'FN', 'IF', 'TF')
+  and o.is_ms_shipped = 0
+union
+select null, c.name
+from sys.columns c
+     join sys.objects o on o.object_id = c.object_id
+where o.type in ('U', 'V')
+  and o.is_ms_shipped = 0
+            "#,
+            )
+            .await?
+            .into_first_result()
+            .await?;
+
+        let mut names = Vec::with_capacity(rows.len() * 2);
+        for row in &rows {
+            let name: &str = required(row, "Name")?;
+            if let Some(schema) = row.try_get::<&str, _>("SchemaName")? {
+                names.push(format!("{}.{}", schema, name));
+            }
+            names.push(name.to_owned());
+        }
+
+        Ok(Completer::new(names))
+    }
+
+    /// Returns where the word before `cursor` (a byte offset) starts
+    /// and the names that start with it, ignoring case.
+    pub fn complete(&self, line: &str, cursor: usize) -> (usize, Vec<&str>) {
+        let before = &line[..cursor];
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !(c.is_alphanumeric() || *c == '_' || *c == '.'))
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        let word = before[start..].to_lowercase();
+
+        if word.is_empty() {
+            return (start, Vec::new());
+        }
+
+        let candidates = self
+            .names
+            .iter()
+            .filter(|name| name.to_lowercase().starts_with(&word))
+            .map(String::as_str)
+            .collect();
+
+        (start, candidates)
+    }
+}
+
+/// A key read from the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    /// Ctrl-C
+    Interrupt,
+    /// Ctrl-D
+    EndOfFile,
+}
+
+/// Turns the bytes read from a terminal in raw mode into keys.
+///
+/// A key split between two reads is kept until the rest arrives.
+#[derive(Debug, Default)]
+pub struct KeyDecoder {
+    pending: Vec<u8>,
+}
+
+impl KeyDecoder {
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Key> {
+        self.pending.extend_from_slice(bytes);
+        let mut keys = Vec::new();
+        let mut i = 0;
+
+        while i < self.pending.len() {
+            let rest = &self.pending[i..];
+            let (key, used) = match rest[0] {
+                0x1b => match escape_sequence(rest) {
+                    Some(decoded) => decoded,
+                    None => break,
+                },
+                b'\r' | b'\n' => (Some(Key::Enter), 1),
+                0x7f | 0x08 => (Some(Key::Backspace), 1),
+                b'\t' => (Some(Key::Tab), 1),
+                0x03 => (Some(Key::Interrupt), 1),
+                0x04 => (Some(Key::EndOfFile), 1),
+                0x01 => (Some(Key::Home), 1),
+                0x05 => (Some(Key::End), 1),
+                b if b < 0x20 => (None, 1),
+                b => {
+                    let width = match b {
+                        0xf0..=0xff => 4,
+                        0xe0..=0xef => 3,
+                        0xc0..=0xdf => 2,
+                        _ => 1,
+                    };
+                    if rest.len() < width {
+                        break;
+                    }
+                    let key = std::str::from_utf8(&rest[..width])
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                        .map(Key::Char);
+                    (key, width)
+                }
+            };
+
+            keys.extend(key);
+            i += used;
+        }
+
+        self.pending.drain(..i);
+        keys
+    }
+}
+
+// Decodes `ESC [ A` and the like, `None` when the sequence is not complete yet
+fn escape_sequence(bytes: &[u8]) -> Option<(Option<Key>, usize)> {
+    match bytes.get(1)? {
+        b'[' | b'O' => {}
+        // Not a sequence, the escape key alone is ignored
+        _ => return Some((None, 1)),
+    }
+
+    let end = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b))? + 2;
+    let key = match &bytes[2..=end] {
+        b"A" => Some(Key::Up),
+        b"B" => Some(Key::Down),
+        b"C" => Some(Key::Right),
+        b"D" => Some(Key::Left),
+        b"H" | b"1~" | b"7~" => Some(Key::Home),
+        b"F" | b"4~" | b"8~" => Some(Key::End),
+        b"3~" => Some(Key::Delete),
+        _ => None,
+    };
+
+    Some((key, end + 1))
+}
+
+/// What the terminal has to do after a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditorEvent {
+    /// Draw the line again
+    Redraw,
+    /// The line was entered
+    Line(String),
+    /// Ctrl-C, the line is dropped
+    Interrupt,
+    /// Ctrl-D on an empty line
+    EndOfFile,
+    /// More than one name completes the word
+    Candidates(Vec<String>),
+}
+
+/// Edits one line: moves the cursor, goes through the history and completes names.
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    buffer: String,
+    /// Byte offset in `buffer`
+    cursor: usize,
+    /// Position in the history while going through it with the arrows
+    history_index: Option<usize>,
+    /// The line that was being typed before going through the history
+    typed: String,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor::default()
+    }
+
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    /// Characters before the cursor
+    pub fn cursor_column(&self) -> usize {
+        self.buffer[..self.cursor].chars().count()
+    }
+
+    fn previous_boundary(&self) -> Option<usize> {
+        self.buffer[..self.cursor]
+            .char_indices()
+            .last()
+            .map(|(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> Option<usize> {
+        self.buffer[self.cursor..]
+            .chars()
+            .next()
+            .map(|c| self.cursor + c.len_utf8())
+    }
+
+    fn show(&mut self, line: String) {
+        self.buffer = line;
+        self.cursor = self.buffer.len();
+    }
+
+    fn reset(&mut self) -> String {
+        let line = std::mem::take(&mut self.buffer);
+        *self = LineEditor::default();
+        line
+    }
+
+    pub fn key(&mut self, key: Key, history: &History, completer: &Completer) -> EditorEvent {
+        match key {
+            Key::Char(c) => {
+                self.buffer.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            }
+            Key::Enter => return EditorEvent::Line(self.reset()),
+            Key::Interrupt => {
+                self.reset();
+                return EditorEvent::Interrupt;
+            }
+            Key::EndOfFile if self.buffer.is_empty() => return EditorEvent::EndOfFile,
+            Key::EndOfFile => {}
+            Key::Backspace => {
+                if let Some(previous) = self.previous_boundary() {
+                    self.buffer.drain(previous..self.cursor);
+                    self.cursor = previous;
+                }
+            }
+            Key::Delete => {
+                if let Some(next) = self.next_boundary() {
+                    self.buffer.drain(self.cursor..next);
+                }
+            }
+            Key::Left => self.cursor = self.previous_boundary().unwrap_or(self.cursor),
+            Key::Right => self.cursor = self.next_boundary().unwrap_or(self.cursor),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.buffer.len(),
+            Key::Up => {
+                let entries = history.entries();
+                let index = match self.history_index {
+                    Some(0) => 0,
+                    Some(index) => index - 1,
+                    None if entries.is_empty() => return EditorEvent::Redraw,
+                    None => {
+                        self.typed = self.buffer.clone();
+                        entries.len() - 1
+                    }
+                };
+                self.history_index = Some(index);
+                self.show(entries[index].clone());
+            }
+            Key::Down => match self.history_index {
+                Some(index) if index + 1 < history.entries().len() => {
+                    self.history_index = Some(index + 1);
+                    self.show(history.entries()[index + 1].clone());
+                }
+                Some(_) => {
+                    self.history_index = None;
+                    let typed = std::mem::take(&mut self.typed);
+                    self.show(typed);
+                }
+                None => {}
+            },
+            Key::Tab => return self.complete(completer),
+        }
+
+        EditorEvent::Redraw
+    }
+
+    fn complete(&mut self, completer: &Completer) -> EditorEvent {
+        let (start, candidates) = completer.complete(&self.buffer, self.cursor);
+        let Some(first) = candidates.first() else {
+            return EditorEvent::Redraw;
+        };
+
+        // The longest beginning shared by the candidates, ignoring case
+        let common = candidates.iter().skip(1).fold(first.len(), |length, name| {
+            first[..length]
+                .char_indices()
+                .zip(name.chars())
+                .find(|((_, a), b)| !a.eq_ignore_ascii_case(b))
+                .map(|((i, _), _)| i)
+                .unwrap_or(length.min(name.len()))
+        });
+
+        let word = self.cursor - start;
+        if common > word || candidates.len() == 1 {
+            self.buffer
+                .replace_range(start..self.cursor, &first[..common]);
+            self.cursor = start + common;
+            return EditorEvent::Redraw;
+        }
+
+        EditorEvent::Candidates(candidates.iter().map(|c| c.to_string()).collect())
+    }
+}
+
+/// The columns of a table as shown by `\d`
+pub fn table_description(table: &TableSchema) -> ResultSet {
+    let rows = table
+        .columns
+        .iter()
+        .map(|column| {
+            let mut data_type = match &column.computed {
+                Some(computed) => format!("as {}", computed.definition),
+                None => column.data_type.to_string(),
+            };
+            if let Some(identity) = &column.identity {
+                data_type.push_str(&format!(
+                    " identity({}, {})",
+                    identity.seed, identity.increment
+                ));
+            }
+
+            vec![
+                Value::from(column.name.as_str()),
+                Value::from(data_type),
+                Value::from(if column.is_nullable {
+                    "null"
+                } else {
+                    "not null"
+                }),
+                column
+                    .default
+                    .as_ref()
+                    .map(|d| Value::from(d.definition.as_str()))
+                    .unwrap_or(Value::Null),
+            ]
+        })
+        .collect();
+
+    ResultSet {
+        columns: ["Column", "Type", "Nullable", "Default"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect(),
+        rows,
+    }
+}
@@ -0,0 +1,428 @@
+use serde::{Deserialize, Serialize};
+use tiberius::Client;
+use tokio::net::TcpStream;
+use tokio_util::compat::Compat;
+
+use crate::connections::connect_to_local_server;
+use crate::pagination::required;
+use crate::{sales_order_header, sales_order_objects, DataType, ObjectKind, ObjectName};
+
+/// What a procedure or function takes and returns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    pub name: ObjectName,
+    pub kind: ObjectKind,
+    /// In declaration order
+    pub parameters: Vec<Parameter>,
+    /// Type of the value returned by a scalar function
+    pub returns: Option<DataType>,
+    /// First result set of a procedure or the columns of a table valued function
+    pub result_columns: Vec<ResultColumn>,
+    /// Why the first result set could not be described,
+    /// a procedure that reads a temporary table for instance
+    pub result_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterDirection {
+    Input,
+    /// Declared with `output`, it's also an input
+    Output,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Parameter {
+    /// The name with its `@`
+    pub name: String,
+    pub data_type: DataType,
+    pub direction: ParameterDirection,
+    pub is_readonly: bool,
+    /// The default as written in the definition, `null` for instance
+    pub default_value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultColumn {
+    pub name: Option<String>,
+    pub data_type: DataType,
+    pub is_nullable: bool,
+}
+
+/// Reads the signature of a procedure or function of the current database,
+/// `None` if there is no such procedure or function.
+pub async fn describe_routine(
+    client: &mut Client<Compat<TcpStream>>,
+    name: &ObjectName,
+) -> Result<Option<Signature>, Box<dyn std::error::Error>> {
+    let row = client
+        .query(
+            r#"
+select o.object_id, rtrim(o.type) as ObjectType, m.definition
+from sys.objects o
+     left join sys.sql_modules m on m.object_id = o.object_id
+where o.object_id = object_id(@P1)
+  and o.type in ('P', 'FN', 'IF', 'TF')
+            "#,
+            &[&name.to_string()],
+        )
+        .await?
+        .into_row()
+        .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let object_id: i32 = required(&row, "object_id")?;
+    let object_type: &str = required(&row, "ObjectType")?;
+    let kind = ObjectKind::from_object_type(object_type)
+        .ok_or_else(|| format!("Unknown object type {}", object_type))?;
+
+    // Defaults of T-SQL parameters are not in the catalog, only in the definition
+    // (`has_default_value` is only set for CLR procedures)
+    let defaults = row
+        .try_get::<&str, _>("definition")?
+        .map(parameter_defaults)
+        .unwrap_or_default();
+
+    let mut parameters = Vec::new();
+    let mut returns = None;
+    for (parameter_id, mut parameter) in read_parameters(client, object_id).await? {
+        // Parameter 0 is the value returned by a scalar function
+        if parameter_id == 0 {
+            returns = Some(parameter.data_type);
+            continue;
+        }
+
+        parameter.default_value = defaults
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&parameter.name))
+            .map(|(_, value)| value.clone());
+        parameters.push(parameter);
+    }
+
+    let statement = match kind {
+        ObjectKind::ScalarFunction | ObjectKind::View => None,
+        ObjectKind::Procedure => Some(format!("exec {}", name)),
+        ObjectKind::TableValuedFunction => {
+            let arguments = vec!["null"; parameters.len()].join(", ");
+            Some(format!("select * from {}({})", name, arguments))
+        }
+    };
+
+    let (result_columns, result_error) = match statement {
+        Some(statement) => match describe_first_result_set(client, &statement).await? {
+            Ok(columns) => (columns, None),
+            Err(message) => (Vec::new(), Some(message)),
+        },
+        None => (Vec::new(), None),
+    };
+
+    Ok(Some(Signature {
+        name: name.clone(),
+        kind,
+        parameters,
+        returns,
+        result_columns,
+        result_error,
+    }))
+}
+
+async fn read_parameters(
+    client: &mut Client<Compat<TcpStream>>,
+    object_id: i32,
+) -> Result<Vec<(i32, Parameter)>, Box<dyn std::error::Error>> {
+    let rows = client
+        .query(
+            r#"
+select p.parameter_id,
+       p.name,
+       t.name as type_name,
+       p.max_length,
+       p.precision,
+       p.scale,
+       p.is_output,
+       p.is_readonly
+from sys.parameters p
+     join sys.types t on t.user_type_id = p.user_type_id
+where p.object_id = @P1
+order by p.parameter_id
+            "#,
+            &[&object_id],
+        )
+        .await?
+        .into_first_result()
+        .await?;
+
+    let mut parameters = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let is_output: bool = required(row, "is_output")?;
+
+        parameters.push((
+            required(row, "parameter_id")?,
+            Parameter {
+                name: required::<&str>(row, "name")?.to_owned(),
+                data_type: DataType {
+                    name: required::<&str>(row, "type_name")?.to_owned(),
+                    max_length: required(row, "max_length")?,
+                    precision: required(row, "precision")?,
+                    scale: required(row, "scale")?,
+                },
+                direction: if is_output {
+                    ParameterDirection::Output
+                } else {
+                    ParameterDirection::Input
+                },
+                is_readonly: required(row, "is_readonly")?,
+                default_value: None,
+            },
+        ));
+    }
+
+    Ok(parameters)
+}
+
+// The statement is only analyzed, it does not run.
+// The inner error is the reason the server gives when it can't describe it.
+async fn describe_first_result_set(
+    client: &mut Client<Compat<TcpStream>>,
+    statement: &str,
+) -> Result<Result<Vec<ResultColumn>, String>, Box<dyn std::error::Error>> {
+    let rows = client
+        .query(
+            r#"
+select r.name,
+       type_name(r.system_type_id) as type_name,
+       r.max_length,
+       r.precision,
+       r.scale,
+       r.is_nullable,
+       r.error_message
+from sys.dm_exec_describe_first_result_set(@P1, null, 0) r
+order by r.column_ordinal
+            "#,
+            &[&statement],
+        )
+        .await?
+        .into_first_result()
+        .await?;
+
+    let mut columns = Vec::with_capacity(rows.len());
+    for row in &rows {
+        // Errors come back as a row instead of being raised
+        if let Some(message) = row.try_get::<&str, _>("error_message")? {
+            return Ok(Err(message.to_owned()));
+        }
+
+        columns.push(ResultColumn {
+            name: row.try_get::<&str, _>("name")?.map(str::to_owned),
+            data_type: DataType {
+                name: required::<&str>(row, "type_name")?.to_owned(),
+                max_length: required(row, "max_length")?,
+                precision: required(row, "precision")?,
+                scale: required(row, "scale")?,
+            },
+            is_nullable: row.try_get("is_nullable")?.unwrap_or(true),
+        });
+    }
+
+    Ok(Ok(columns))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Word,
+    Quoted,
+    Punctuation,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+// Splits T-SQL into words, strings or quoted identifiers and punctuation,
+// comments are skipped
+fn tokenize(sql: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let next = chars.peek().map(|(_, c)| *c);
+
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '-' if next == Some('-') => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+                continue;
+            }
+            '/' if next == Some('*') => {
+                chars.next();
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some((_, '/')) if chars.next_if(|(_, c)| *c == '*').is_some() => depth += 1,
+                        Some((_, '*')) if chars.next_if(|(_, c)| *c == '/').is_some() => depth -= 1,
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+                continue;
+            }
+            '\'' | '[' | '"' => {
+                skip_quoted(&mut chars, c);
+                TokenKind::Quoted
+            }
+            'N' | 'n' if next == Some('\'') => {
+                chars.next();
+                skip_quoted(&mut chars, '\'');
+                TokenKind::Quoted
+            }
+            c if is_word_char(c) => {
+                while chars.next_if(|(_, c)| is_word_char(*c)).is_some() {}
+                TokenKind::Word
+            }
+            _ => TokenKind::Punctuation,
+        };
+
+        let end = chars.peek().map(|(i, _)| *i).unwrap_or(sql.len());
+        tokens.push(Token { kind, start, end });
+    }
+
+    tokens
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '$')
+}
+
+// Moves past the closing quote, a doubled one is an escaped quote
+fn skip_quoted(chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>, open: char) {
+    let close = if open == '[' { ']' } else { open };
+
+    while let Some((_, c)) = chars.next() {
+        if c == close && chars.next_if(|(_, c)| *c == close).is_none() {
+            break;
+        }
+    }
+}
+
+/// Reads the defaults of the parameters from a procedure or function definition,
+/// `[("@Comment", "null")]` for `create procedure p @Comment nvarchar(128) = null as ...`
+pub(crate) fn parameter_defaults(definition: &str) -> Vec<(String, String)> {
+    let tokens = tokenize(definition);
+    let text = |token: &Token| &definition[token.start..token.end];
+    let is_word = |token: &Token, words: &[&str]| {
+        token.kind == TokenKind::Word && words.iter().any(|w| text(token).eq_ignore_ascii_case(w))
+    };
+
+    let first = match tokens
+        .iter()
+        .position(|t| t.kind == TokenKind::Word && text(t).starts_with('@'))
+    {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+
+    let mut defaults = Vec::new();
+    let mut name: Option<&str> = None;
+    let mut default: Option<(usize, usize)> = None;
+    let mut in_default = false;
+    let mut depth = 0;
+
+    let mut finish = |name: &mut Option<&str>, default: &mut Option<(usize, usize)>| {
+        if let (Some(name), Some((start, end))) = (name.take(), default.take()) {
+            defaults.push((name.to_owned(), definition[start..end].to_owned()));
+        }
+    };
+
+    for token in &tokens[first..] {
+        let punctuation = |p: &str| token.kind == TokenKind::Punctuation && text(token) == p;
+
+        if punctuation("(") {
+            depth += 1;
+        } else if punctuation(")") {
+            depth -= 1;
+            // The parenthesis around the parameters of a function closes
+            if depth < 0 {
+                break;
+            }
+        }
+
+        if depth == 0 {
+            if is_word(token, &["as", "returns", "with"]) {
+                break;
+            }
+            if punctuation(",") {
+                finish(&mut name, &mut default);
+                in_default = false;
+                continue;
+            }
+            if punctuation("=") && !in_default {
+                in_default = true;
+                continue;
+            }
+            if is_word(token, &["output", "out", "readonly"]) {
+                in_default = false;
+                continue;
+            }
+        }
+
+        if name.is_none() && token.kind == TokenKind::Word && text(token).starts_with('@') {
+            name = Some(text(token));
+        } else if in_default {
+            let start = default.map(|(start, _)| start).unwrap_or(token.start);
+            default = Some((start, token.end));
+        }
+    }
+
+    finish(&mut name, &mut default);
+    defaults
+}
+
+pub async fn describe_sales_order_routines() -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    for object in sales_order_objects(&sales_order_header())? {
+        let signature = match describe_routine(&mut client, &object.name).await? {
+            Some(signature) => signature,
+            None => {
+                println!("{} does not exist", object.name);
+                continue;
+            }
+        };
+
+        println!("{:?} {}", signature.kind, signature.name);
+        for parameter in &signature.parameters {
+            print!("  {} {}", parameter.name, parameter.data_type);
+            if let Some(default) = &parameter.default_value {
+                print!(" = {}", default);
+            }
+            if parameter.direction == ParameterDirection::Output {
+                print!(" output");
+            }
+            println!();
+        }
+        if let Some(returns) = &signature.returns {
+            println!("  returns {}", returns);
+        }
+        if let Some(error) = &signature.result_error {
+            println!("  result set unknown: {}", error);
+        }
+        for column in &signature.result_columns {
+            println!(
+                "  column {} {}",
+                column.name.as_deref().unwrap_or("(no name)"),
+                column.data_type
+            );
+        }
+    }
+
+    client.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::field::{Field, Visit};
+use tracing::instrument::WithSubscriber;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Metadata};
+use tracing_core::span::Current;
+
+/// How the slow query log gets the I/O and the time of a statement from the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatisticsCapture {
+    /// Only the duration measured by the client
+    Off,
+    /// The call site runs a slow `select` again with `SET STATISTICS IO, TIME ON`, with
+    /// `TracedClient::rerun_slow_query`, outside of its own timeout. A statement that
+    /// changes data is not run again.
+    ///
+    /// The second run costs as much as the first one: the server reads the same pages
+    /// and sends all the rows again, they are read and dropped.
+    Rerun,
+    /// Every statement of the connection runs with `SET STATISTICS IO, TIME ON`, the
+    /// messages are kept for the slow ones
+    Always,
+}
+
+pub type SlowQueryHandler = Arc<dyn Fn(&SlowQuery) + Send + Sync>;
+
+/// The statements that take longer than a threshold, logged as `sql.slow_query` events.
+///
+/// Off unless it's given to `TracedClient::slow_queries`.
+#[derive(Clone)]
+pub struct SlowQueryLog {
+    pub(crate) threshold: Duration,
+    pub(crate) statistics: StatisticsCapture,
+    pub(crate) on_slow_query: Option<SlowQueryHandler>,
+}
+
+impl SlowQueryLog {
+    pub fn new(threshold: Duration) -> Self {
+        SlowQueryLog {
+            threshold,
+            statistics: StatisticsCapture::Off,
+            on_slow_query: None,
+        }
+    }
+
+    pub fn statistics(mut self, statistics: StatisticsCapture) -> Self {
+        self.statistics = statistics;
+        self
+    }
+
+    /// Called with each slow query, after its event, to keep it elsewhere than in the logs.
+    /// It's called again with the statistics of a query run again.
+    pub fn on_slow_query(mut self, handler: impl Fn(&SlowQuery) + Send + Sync + 'static) -> Self {
+        self.on_slow_query = Some(Arc::new(handler));
+        self
+    }
+}
+
+impl fmt::Debug for SlowQueryLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlowQueryLog")
+            .field("threshold", &self.threshold)
+            .field("statistics", &self.statistics)
+            .field("on_slow_query", &self.on_slow_query.is_some())
+            .finish()
+    }
+}
+
+/// A statement that took longer than the threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowQuery {
+    pub server: String,
+    /// The normalized text, without literals or comments
+    pub statement: String,
+    /// `@P1 (DueDate) = '2024-08-20'`, sensitive values are masked
+    pub parameters: String,
+    /// Until the statement finished, or until it was given up
+    pub duration: Duration,
+    pub threshold: Duration,
+    /// `false` when the statement was given up before it finished,
+    /// by `QueryTimeout::run` for instance
+    pub finished: bool,
+    /// The `SET STATISTICS IO, TIME` messages,
+    /// `Table 'SalesOrderHeader'. Scan count 1, logical reads 689, ...` for instance
+    pub statistics: Vec<String>,
+}
+
+impl SlowQuery {
+    pub(crate) fn log(&self, database: Option<&str>) {
+        tracing::event!(
+            target: "sql.slow_query",
+            tracing::Level::WARN,
+            server.address = %self.server,
+            db.name = database.unwrap_or(""),
+            db.statement = %self.statement,
+            db.parameter_values = %self.parameters,
+            duration_ms = self.duration.as_secs_f64() * 1000.0,
+            threshold_ms = self.threshold.as_secs_f64() * 1000.0,
+            finished = self.finished,
+            statistics = %self.statistics.join("\n"),
+            "slow query"
+        );
+    }
+}
+
+// Tiberius does not return the info messages of the server, it logs them as `tracing`
+// events: they are collected while the statement runs. Spans and the other events go
+// to the subscriber that was the default before
+struct MessageCollector {
+    messages: Mutex<Vec<String>>,
+    previous: Dispatch,
+}
+
+struct Message(Option<String>);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+impl tracing::Subscriber for MessageCollector {
+    // The previous subscriber is asked again for each call site, its answer may change
+    fn register_callsite(&self, _: &'static Metadata<'static>) -> Interest {
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        is_server_message(metadata) || self.previous.enabled(metadata)
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        self.previous.new_span(span)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        self.previous.record(span, values)
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        self.previous.record_follows_from(span, follows)
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        if is_server_message(event.metadata()) {
+            let mut message = Message(None);
+            event.record(&mut message);
+            if let Some(message) = message.0.filter(|m| is_statistics(m)) {
+                self.messages
+                    .lock()
+                    .unwrap()
+                    .push(message.trim().to_owned());
+                return;
+            }
+        }
+
+        if self.previous.enabled(event.metadata()) {
+            self.previous.event(event);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        self.previous.enter(span)
+    }
+
+    fn exit(&self, span: &Id) {
+        self.previous.exit(span)
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        self.previous.clone_span(span)
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        self.previous.try_close(span)
+    }
+
+    fn current_span(&self) -> Current {
+        self.previous.current_span()
+    }
+}
+
+fn is_server_message(metadata: &Metadata<'_>) -> bool {
+    metadata.is_event() && metadata.target().starts_with("tiberius")
+}
+
+// `Table '...'. Scan count ...` from STATISTICS IO, `CPU time = ...` from STATISTICS TIME
+fn is_statistics(message: &str) -> bool {
+    message.starts_with("Table '") || message.contains("CPU time =")
+}
+
+/// Runs `work` and returns the `SET STATISTICS IO, TIME` messages the server sent meanwhile.
+///
+/// The statistics messages are not logged, the spans and other `tracing` events of the work
+/// go to the subscriber of the application.
+pub async fn capture_statistics<T>(work: impl Future<Output = T>) -> (T, Vec<String>) {
+    let collector = Arc::new(MessageCollector {
+        messages: Mutex::new(Vec::new()),
+        previous: tracing::dispatcher::get_default(Dispatch::clone),
+    });
+    let output = work.with_subscriber(collector.clone()).await;
+    let messages = std::mem::take(&mut *collector.messages.lock().unwrap());
+
+    (output, messages)
+}
@@ -0,0 +1,715 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use tiberius::{Client, ColumnData, ExecuteResult, Row, ToSql};
+use tokio::net::TcpStream;
+use tokio_stream::StreamExt;
+use tokio_util::compat::Compat;
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
+
+use crate::{capture_statistics, ConnectionSpec, SlowQuery, SlowQueryLog, StatisticsCapture};
+
+// Longer statements are cut in the spans
+const MAX_STATEMENT_LENGTH: usize = 2048;
+const MASK: &str = "********";
+
+/// What the spans of the statements show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceSettings {
+    /// Adds the values of the parameters to the spans, off by default
+    pub log_parameters: bool,
+    /// Parameters and columns whose values are masked, compared without case
+    pub sensitive_fields: Vec<String>,
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        TraceSettings {
+            log_parameters: false,
+            sensitive_fields: vec!["Password".to_owned(), "CreditCardApprovalCode".to_owned()],
+        }
+    }
+}
+
+impl TraceSettings {
+    fn is_sensitive(&self, name: &str) -> bool {
+        self.sensitive_fields
+            .iter()
+            .any(|field| field.eq_ignore_ascii_case(name))
+    }
+}
+
+/// The statement with its literals replaced by `?`, its comments removed and its spaces
+/// collapsed, so the same statement has the same text whatever its values.
+pub fn normalize_statement(sql: &str) -> String {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut normalized = String::new();
+    let mut i = 0;
+
+    let is_word = |c: char| c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '$');
+    let push_space = |normalized: &mut String| {
+        if !normalized.is_empty() && !normalized.ends_with(' ') {
+            normalized.push(' ');
+        }
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            push_space(&mut normalized);
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+            push_space(&mut normalized);
+        } else if c.is_whitespace() {
+            push_space(&mut normalized);
+            i += 1;
+        } else if c == '\'' {
+            // N'...' is a literal too
+            if normalized.ends_with(['N', 'n'])
+                && !normalized[..normalized.len() - 1].ends_with(is_word)
+            {
+                normalized.pop();
+            }
+            i += 1;
+            while i < chars.len() {
+                if chars[i] == '\'' && chars.get(i + 1) == Some(&'\'') {
+                    i += 2;
+                } else if chars[i] == '\'' {
+                    break;
+                } else {
+                    i += 1;
+                }
+            }
+            i += 1;
+            normalized.push('?');
+        } else if c == '[' || c == '"' {
+            // Quoted names are kept as they are
+            let close = if c == '[' { ']' } else { '"' };
+            normalized.push(c);
+            i += 1;
+            while i < chars.len() {
+                normalized.push(chars[i]);
+                if chars[i] == close && chars.get(i + 1) == Some(&close) {
+                    normalized.push(close);
+                    i += 2;
+                } else if chars[i] == close {
+                    i += 1;
+                    break;
+                } else {
+                    i += 1;
+                }
+            }
+        } else if c.is_ascii_digit() && !normalized.ends_with(is_word) {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            normalized.push('?');
+        } else {
+            normalized.push(c);
+            i += 1;
+        }
+    }
+
+    let normalized = normalized.trim();
+    match normalized.char_indices().nth(MAX_STATEMENT_LENGTH) {
+        Some((end, _)) => format!("{}...", &normalized[..end]),
+        None => normalized.to_owned(),
+    }
+}
+
+/// The kind of a statement, from its first keyword: `select`, `insert`, `exec`, ...
+pub fn statement_kind(sql: &str) -> &'static str {
+    let normalized = normalize_statement(sql).to_ascii_lowercase();
+    let mut words = normalized
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty());
+
+    match words.next() {
+        Some("select") => "select",
+        Some("insert") => "insert",
+        Some("update") => "update",
+        Some("delete") => "delete",
+        Some("merge") => "merge",
+        Some("with") => "with",
+        Some("exec" | "execute") => "exec",
+        Some("begin") => match words.next() {
+            Some("tran" | "transaction") => "begin transaction",
+            _ => "begin",
+        },
+        Some("commit") => "commit",
+        Some("rollback") => "rollback",
+        Some("create") => "create",
+        Some("alter") => "alter",
+        Some("drop") => "drop",
+        Some("truncate") => "truncate",
+        Some("use") => "use",
+        Some("set") => "set",
+        Some("declare") => "declare",
+        _ => "other",
+    }
+}
+
+// The procedure an `exec` statement calls
+fn procedure_name(normalized: &str) -> Option<&str> {
+    let mut words = normalized.split_whitespace();
+    let first = words.next()?;
+    if !first.eq_ignore_ascii_case("exec") && !first.eq_ignore_ascii_case("execute") {
+        return None;
+    }
+    let mut name = words.next()?;
+    // exec @Status = dbo.uspName ...
+    if name.starts_with('@') {
+        words.next();
+        name = words.next()?;
+    }
+
+    Some(name.trim_end_matches(';'))
+}
+
+// Strips the brackets and the schema, `[o].[CreditCardApprovalCode]` is `CreditCardApprovalCode`
+fn bare_name(name: &str) -> &str {
+    let name = name.rsplit('.').next().unwrap_or(name);
+    name.trim_start_matches('@')
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+}
+
+/// The names the `@P1`, `@P2`, ... parameters stand for in the statement:
+/// `@CreditCardApprovalCode = @P3` in a procedure call or a `where`, and the columns
+/// of `insert (...) values (...)`.
+pub(crate) fn parameter_names(sql: &str) -> HashMap<usize, String> {
+    let normalized = normalize_statement(sql);
+    let tokens: Vec<&str> = tokenize(&normalized);
+    let mut names = HashMap::new();
+    let parameter = |token: &str| {
+        token
+            .strip_prefix("@P")
+            .or_else(|| token.strip_prefix("@p"))
+            .and_then(|n| n.parse::<usize>().ok())
+    };
+
+    // name = @Pn, name <> @Pn, ...
+    for (index, token) in tokens.iter().enumerate() {
+        if let Some(n) = parameter(token) {
+            let operator = index.checked_sub(1).map(|i| tokens[i]);
+            let operand = index.checked_sub(2).map(|i| tokens[i]);
+            if let (Some("=" | "<>" | "!=" | "<" | ">" | "<=" | ">="), Some(name)) =
+                (operator, operand)
+            {
+                names.entry(n).or_insert_with(|| bare_name(name).to_owned());
+            }
+        }
+    }
+
+    // insert into t (a, b) values (@P1, @P2)
+    let lower: Vec<String> = tokens.iter().map(|t| t.to_ascii_lowercase()).collect();
+    if let (Some(insert), Some(values)) = (
+        lower.iter().position(|t| t == "insert"),
+        lower.iter().position(|t| t == "values"),
+    ) {
+        let list = |from: usize, to: usize| -> Vec<&str> {
+            let Some(open) = (from..to).find(|&i| tokens[i] == "(") else {
+                return Vec::new();
+            };
+            tokens[open + 1..to]
+                .iter()
+                .take_while(|t| **t != ")")
+                .filter(|t| **t != ",")
+                .copied()
+                .collect()
+        };
+        let columns = list(insert, values);
+        let values = list(values, tokens.len());
+        for (column, value) in columns.iter().zip(values) {
+            if let Some(n) = parameter(value) {
+                names
+                    .entry(n)
+                    .or_insert_with(|| bare_name(column).to_owned());
+            }
+        }
+    }
+
+    names
+}
+
+// Words, quoted names and operators of a normalized statement
+fn tokenize(normalized: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let bytes = normalized.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b' ' => {
+                i += 1;
+                continue;
+            }
+            b'[' => {
+                while i < bytes.len() && bytes[i] != b']' {
+                    i += 1;
+                }
+                i += 1;
+                // [schema].[name] is one token
+                while i < bytes.len() && bytes[i] == b'.' {
+                    i += 1;
+                    while i < bytes.len() && bytes[i] != b']' && bytes[i] != b' ' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            b'<' | b'>' | b'!' | b'=' => {
+                while i < bytes.len() && matches!(bytes[i], b'<' | b'>' | b'!' | b'=') {
+                    i += 1;
+                }
+            }
+            c if c.is_ascii_alphanumeric()
+                || matches!(c, b'_' | b'@' | b'#' | b'.')
+                || c >= 0x80 =>
+            {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric()
+                        || matches!(bytes[i], b'_' | b'@' | b'#' | b'.' | b'[' | b']' | b'$')
+                        || bytes[i] >= 0x80)
+                {
+                    i += 1;
+                }
+            }
+            _ => i += 1,
+        }
+        tokens.push(&normalized[start..i.min(bytes.len())]);
+    }
+
+    tokens
+}
+
+/// `@P1 = 5, @P3 (CreditCardApprovalCode) = ********`, the values of the sensitive
+/// parameters masked.
+pub fn parameter_values(sql: &str, params: &[&dyn ToSql], settings: &TraceSettings) -> String {
+    let names = parameter_names(sql);
+
+    params
+        .iter()
+        .enumerate()
+        .map(|(index, param)| {
+            let n = index + 1;
+            let value = match names.get(&n) {
+                Some(name) if settings.is_sensitive(name) => MASK.to_owned(),
+                _ => column_data_text(&param.to_sql()),
+            };
+            match names.get(&n) {
+                Some(name) => format!("@P{} ({}) = {}", n, name, value),
+                None => format!("@P{} = {}", n, value),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn column_data_text(data: &ColumnData<'_>) -> String {
+    fn text<T: ToString>(value: &Option<T>) -> String {
+        value
+            .as_ref()
+            .map_or_else(|| "NULL".to_owned(), T::to_string)
+    }
+
+    match data {
+        ColumnData::U8(v) => text(v),
+        ColumnData::I16(v) => text(v),
+        ColumnData::I32(v) => text(v),
+        ColumnData::I64(v) => text(v),
+        ColumnData::F32(v) => text(v),
+        ColumnData::F64(v) => text(v),
+        ColumnData::Bit(v) => text(v),
+        ColumnData::Guid(v) => text(v),
+        ColumnData::Numeric(v) => text(&v.map(f64::from)),
+        ColumnData::String(Some(s)) => {
+            let shown: String = s.chars().take(100).collect();
+            let cut = if shown.len() < s.len() { "..." } else { "" };
+            format!("'{}'{}", shown.replace('\'', "''"), cut)
+        }
+        ColumnData::Binary(Some(bytes)) => format!("<{} bytes>", bytes.len()),
+        ColumnData::String(None) | ColumnData::Binary(None) | ColumnData::Xml(None) => {
+            "NULL".to_owned()
+        }
+        other => format!("{:?}", other),
+    }
+}
+
+/// A connection whose queries, procedure calls and transactions are `tracing` spans.
+///
+/// Each statement is a `sql.statement` span with the server, the database, the kind and
+/// the normalized text of the statement, the number of parameters, the rows and the time
+/// it took. Statements of a transaction are in its `sql.transaction` span.
+pub struct TracedClient {
+    client: Client<Compat<TcpStream>>,
+    server: String,
+    database: Option<String>,
+    settings: TraceSettings,
+    transaction: Option<(Span, Instant)>,
+    slow_queries: Option<SlowQueryLog>,
+    // `SET STATISTICS IO, TIME ON` was run for `StatisticsCapture::Always`
+    statistics_on: bool,
+    // The last statement when it was a slow `select` to run again
+    last_slow_query: Option<SlowQuery>,
+}
+
+impl TracedClient {
+    pub fn new(client: Client<Compat<TcpStream>>, server: &str, database: Option<&str>) -> Self {
+        TracedClient {
+            client,
+            server: server.to_owned(),
+            database: database.map(str::to_owned),
+            settings: TraceSettings::default(),
+            transaction: None,
+            slow_queries: None,
+            statistics_on: false,
+            last_slow_query: None,
+        }
+    }
+
+    /// Connects as `connect` does, in a `sql.connect` span
+    pub async fn connect(
+        spec: impl Into<ConnectionSpec>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let server = spec.into().server_config()?;
+        let client = crate::connect(server.clone()).await?;
+
+        Ok(TracedClient::new(
+            client,
+            &server.host,
+            server.database.as_deref(),
+        ))
+    }
+
+    pub fn settings(mut self, settings: TraceSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Logs the statements slower than the threshold of `log` as `sql.slow_query` events
+    pub fn slow_queries(mut self, log: SlowQueryLog) -> Self {
+        self.slow_queries = Some(log);
+        self
+    }
+
+    /// The connection itself, its statements are not traced
+    pub fn client(&mut self) -> &mut Client<Compat<TcpStream>> {
+        &mut self.client
+    }
+
+    pub fn into_client(self) -> Client<Compat<TcpStream>> {
+        self.client
+    }
+
+    /// Runs a statement and returns the rows of its first result
+    pub async fn query(&mut self, sql: &str, params: &[&dyn ToSql]) -> tiberius::Result<Vec<Row>> {
+        let capture = self.start_statistics().await?;
+        let (span, statement) = self.statement_span(sql, params);
+        let started = Instant::now();
+        let (client, slow_query) = self.watch(sql, params, started);
+
+        let work = async { client.query(sql, params).await?.into_first_result().await }
+            .instrument(span.clone());
+        let (result, statistics) = run(work, capture).await;
+
+        let rows = result.as_ref().map(|rows| rows.len() as u64);
+        let duration = finish(&span, &statement, started, rows.map(|rows| (rows, 0)));
+        self.last_slow_query = slow_query.finish(duration, statistics);
+        result
+    }
+
+    /// Runs a statement without results, the rows are the ones it changed
+    pub async fn execute(
+        &mut self,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> tiberius::Result<ExecuteResult> {
+        let capture = self.start_statistics().await?;
+        let (span, statement) = self.statement_span(sql, params);
+        let started = Instant::now();
+        let (client, slow_query) = self.watch(sql, params, started);
+
+        let work = client.execute(sql, params).instrument(span.clone());
+        let (result, statistics) = run(work, capture).await;
+
+        let rows = result.as_ref().map(|r| r.rows_affected().iter().sum());
+        let duration = finish(&span, &statement, started, rows.map(|rows| (0, rows)));
+        self.last_slow_query = slow_query.finish(duration, statistics);
+        result
+    }
+
+    /// Starts a transaction, the statements until `commit` or `rollback` are in its span
+    pub async fn begin_transaction(&mut self) -> tiberius::Result<()> {
+        let span = tracing::info_span!(
+            "sql.transaction",
+            server.address = %self.server,
+            db.name = self.database.as_deref().unwrap_or(""),
+            outcome = Empty,
+            duration_ms = Empty,
+        );
+        self.transaction = Some((span, Instant::now()));
+
+        match self.execute("BEGIN TRANSACTION", &[]).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.end_transaction("failed to begin");
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn commit(&mut self) -> tiberius::Result<()> {
+        let result = self.execute("COMMIT", &[]).await;
+        self.end_transaction(if result.is_ok() {
+            "committed"
+        } else {
+            "failed to commit"
+        });
+        result.map(|_| ())
+    }
+
+    pub async fn rollback(&mut self) -> tiberius::Result<()> {
+        let result = self.execute("ROLLBACK", &[]).await;
+        self.end_transaction("rolled back");
+        result.map(|_| ())
+    }
+
+    fn end_transaction(&mut self, outcome: &str) {
+        if let Some((span, started)) = self.transaction.take() {
+            span.record("outcome", outcome);
+            span.record("duration_ms", started.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    // Turns the statistics on for the session once, whether the messages are captured
+    async fn start_statistics(&mut self) -> tiberius::Result<bool> {
+        let always = self
+            .slow_queries
+            .as_ref()
+            .is_some_and(|log| log.statistics == StatisticsCapture::Always);
+        if always && !self.statistics_on {
+            self.client
+                .simple_query("SET STATISTICS IO, TIME ON")
+                .await?
+                .into_results()
+                .await?;
+            self.statistics_on = true;
+        }
+
+        Ok(always)
+    }
+
+    // The connection to run a statement on, and the guard that logs it if it's slow
+    fn watch<'a>(
+        &'a mut self,
+        sql: &'a str,
+        params: &'a [&'a dyn ToSql],
+        started: Instant,
+    ) -> (&'a mut Client<Compat<TcpStream>>, SlowQueryGuard<'a>) {
+        self.last_slow_query = None;
+        let guard = SlowQueryGuard {
+            log: self.slow_queries.as_ref(),
+            server: &self.server,
+            database: self.database.as_deref(),
+            settings: &self.settings,
+            sql,
+            params,
+            started,
+            finished: false,
+        };
+
+        (&mut self.client, guard)
+    }
+
+    /// Runs the last statement again with `SET STATISTICS IO, TIME ON` when it was a slow
+    /// `select` and the log captures its statistics with `StatisticsCapture::Rerun`.
+    /// The slow query, with its statistics, is logged again and returned.
+    ///
+    /// It's up to the call site: the statement runs a second time, which should not count
+    /// in its own timeout or change anything. It costs as much as the first run, its rows
+    /// are read from the server and dropped.
+    pub async fn rerun_slow_query(
+        &mut self,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> tiberius::Result<Option<SlowQuery>> {
+        let Some(mut slow_query) = self.last_slow_query.take() else {
+            return Ok(None);
+        };
+        if slow_query.statement != normalize_statement(sql) {
+            return Ok(None);
+        }
+
+        let batch = format!(
+            "SET STATISTICS IO, TIME ON;\n{};\nSET STATISTICS IO, TIME OFF;",
+            sql
+        );
+        let client = &mut self.client;
+        let (result, statistics) = capture_statistics(async {
+            let mut stream = client.query(batch, params).await?;
+            while stream.try_next().await?.is_some() {}
+            Ok::<_, tiberius::error::Error>(())
+        })
+        .await;
+        result?;
+
+        slow_query.statistics = statistics;
+        slow_query.log(self.database.as_deref());
+        if let Some(handler) = self
+            .slow_queries
+            .as_ref()
+            .and_then(|log| log.on_slow_query.as_ref())
+        {
+            handler(&slow_query);
+        }
+
+        Ok(Some(slow_query))
+    }
+
+    // The span of the statement, and its name in the metrics: the procedure or the operation
+    fn statement_span(&self, sql: &str, params: &[&dyn ToSql]) -> (Span, String) {
+        let parent = match &self.transaction {
+            Some((span, _)) => span.clone(),
+            None => Span::current(),
+        };
+        let normalized = normalize_statement(sql);
+        let span = tracing::info_span!(
+            parent: &parent,
+            "sql.statement",
+            server.address = %self.server,
+            db.name = self.database.as_deref().unwrap_or(""),
+            db.operation = statement_kind(sql),
+            db.procedure = Empty,
+            db.statement = %normalized,
+            db.parameters = params.len(),
+            db.parameter_values = Empty,
+            db.rows = Empty,
+            duration_ms = Empty,
+            error = Empty,
+        );
+
+        let procedure = procedure_name(&normalized);
+        if let Some(procedure) = &procedure {
+            span.record("db.procedure", *procedure);
+        }
+        if self.settings.log_parameters && !params.is_empty() {
+            span.record(
+                "db.parameter_values",
+                parameter_values(sql, params, &self.settings),
+            );
+        }
+
+        let statement = procedure.unwrap_or_else(|| statement_kind(sql));
+        (span, statement.to_owned())
+    }
+}
+
+// Logs a statement slower than the threshold of the log as a slow query. A statement that
+// doesn't finish, because `QueryTimeout::run` dropped its future for instance, is logged
+// when the guard is dropped, with the time until then.
+pub(crate) struct SlowQueryGuard<'a> {
+    pub(crate) log: Option<&'a SlowQueryLog>,
+    pub(crate) server: &'a str,
+    pub(crate) database: Option<&'a str>,
+    pub(crate) settings: &'a TraceSettings,
+    pub(crate) sql: &'a str,
+    pub(crate) params: &'a [&'a dyn ToSql],
+    pub(crate) started: Instant,
+    pub(crate) finished: bool,
+}
+
+impl SlowQueryGuard<'_> {
+    // Logs the finished statement if it's slow, and returns it when it's a `select` to run
+    // again with `StatisticsCapture::Rerun`
+    pub(crate) fn finish(
+        mut self,
+        duration: Duration,
+        statistics: Vec<String>,
+    ) -> Option<SlowQuery> {
+        self.finished = true;
+        let slow_query = self.log(duration, statistics)?;
+        let rerun = self
+            .log
+            .is_some_and(|log| log.statistics == StatisticsCapture::Rerun);
+
+        Some(slow_query).filter(|_| rerun && statement_kind(self.sql) == "select")
+    }
+
+    fn log(&self, duration: Duration, statistics: Vec<String>) -> Option<SlowQuery> {
+        let log = self.log?;
+        if duration < log.threshold {
+            return None;
+        }
+
+        let slow_query = SlowQuery {
+            server: self.server.to_owned(),
+            statement: normalize_statement(self.sql),
+            parameters: parameter_values(self.sql, self.params, self.settings),
+            duration,
+            threshold: log.threshold,
+            finished: self.finished,
+            statistics,
+        };
+        slow_query.log(self.database);
+        if let Some(handler) = &log.on_slow_query {
+            handler(&slow_query);
+        }
+
+        Some(slow_query)
+    }
+}
+
+impl Drop for SlowQueryGuard<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.log(self.started.elapsed(), Vec::new());
+        }
+    }
+}
+
+// Runs a statement, with the statistics messages the server sent when they're captured
+async fn run<T>(work: impl Future<Output = T>, capture: bool) -> (T, Vec<String>) {
+    if capture {
+        capture_statistics(work).await
+    } else {
+        (work.await, Vec::new())
+    }
+}
+
+// The rows, returned and affected, and the time of a statement, or its error
+fn finish(
+    span: &Span,
+    statement: &str,
+    started: Instant,
+    rows: Result<(u64, u64), &tiberius::error::Error>,
+) -> Duration {
+    let duration = started.elapsed();
+    span.record("duration_ms", duration.as_secs_f64() * 1000.0);
+    match rows {
+        Ok((returned, affected)) => {
+            span.record("db.rows", returned + affected);
+            crate::record_query(statement, duration, returned, affected);
+        }
+        Err(e) => {
+            crate::record_query(statement, duration, 0, 0);
+            crate::record_error(e);
+            span.record("error", e.to_string());
+            tracing::warn!(parent: span, error = %e, "statement failed");
+        }
+    }
+
+    duration
+}
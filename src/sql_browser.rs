@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+// SQL Server Resolution Protocol (MS-SQLR) messages
+const CLNT_UCAST_EX: u8 = 0x03;
+const CLNT_UCAST_INST: u8 = 0x04;
+const SVR_RESP: u8 = 0x05;
+
+/// An instance of SQL Server as SQL Server Browser describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceInfo {
+    pub server_name: String,
+    pub instance_name: String,
+    pub is_clustered: bool,
+    /// `16.0.1000.6` for instance
+    pub version: String,
+    /// `None` when TCP is turned off for the instance
+    pub tcp_port: Option<u16>,
+    /// `\\HOST\pipe\MSSQL$SQL2022D\sql\query` for instance
+    pub named_pipe: Option<String>,
+}
+
+impl InstanceInfo {
+    // `ServerName;HOST;InstanceName;SQL2022D;IsClustered;No;Version;16.0.1000.6;tcp;49670;np;\\HOST\pipe\sql\query`
+    fn parse(description: &str) -> Result<Self, BrowserError> {
+        let invalid = |message: &str| BrowserError::InvalidResponse(message.to_owned());
+        let mut fields = HashMap::new();
+        let mut parts = description.split(';');
+        while let Some(key) = parts.next() {
+            let value = parts
+                .next()
+                .ok_or_else(|| invalid(&format!("{} has no value", key)))?;
+            fields.insert(key.to_ascii_lowercase(), value);
+        }
+        let field = |name: &str| fields.get(name).map(|value| value.to_string());
+
+        Ok(InstanceInfo {
+            server_name: field("servername").ok_or_else(|| invalid("no ServerName"))?,
+            instance_name: field("instancename").ok_or_else(|| invalid("no InstanceName"))?,
+            is_clustered: field("isclustered").is_some_and(|v| v.eq_ignore_ascii_case("yes")),
+            version: field("version").unwrap_or_default(),
+            tcp_port: match field("tcp") {
+                Some(port) => Some(
+                    port.parse()
+                        .map_err(|_| invalid(&format!("{} is not a port", port)))?,
+                ),
+                None => None,
+            },
+            named_pipe: field("np"),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum BrowserError {
+    Io(std::io::Error),
+    /// SQL Server Browser did not answer, it's not running or UDP 1434 is blocked
+    Timeout {
+        address: SocketAddr,
+        after: Duration,
+    },
+    InvalidResponse(String),
+    UnknownInstance {
+        host: String,
+        instance_name: String,
+    },
+}
+
+impl fmt::Display for BrowserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrowserError::Io(e) => write!(f, "Cannot ask SQL Server Browser: {}", e),
+            BrowserError::Timeout { address, after } => write!(
+                f,
+                "SQL Server Browser did not answer on {} in {:?}, is it running and is UDP open?",
+                address, after
+            ),
+            BrowserError::InvalidResponse(message) => {
+                write!(
+                    f,
+                    "SQL Server Browser sent an invalid response: {}",
+                    message
+                )
+            }
+            BrowserError::UnknownInstance {
+                host,
+                instance_name,
+            } => write!(f, "{} has no instance {}", host, instance_name),
+        }
+    }
+}
+
+impl std::error::Error for BrowserError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BrowserError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BrowserError {
+    fn from(e: std::io::Error) -> Self {
+        BrowserError::Io(e)
+    }
+}
+
+/// Asks SQL Server Browser, on UDP port 1434 by default, for the instances of a host.
+///
+/// The answers are kept for a while, 30 seconds by default, so connecting to a named
+/// instance again does not wait for UDP each time.
+pub struct SqlServerBrowser {
+    port: u16,
+    timeout: Duration,
+    time_to_live: Duration,
+    cached: Mutex<HashMap<String, (Vec<InstanceInfo>, Instant)>>,
+}
+
+impl SqlServerBrowser {
+    pub fn new() -> Self {
+        SqlServerBrowser {
+            port: 1434,
+            timeout: Duration::from_secs(1),
+            time_to_live: Duration::from_secs(30),
+            cached: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The UDP port SQL Server Browser listens on
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// How long to wait for an answer
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long the instances of a host are kept, zero asks each time
+    pub fn time_to_live(mut self, time_to_live: Duration) -> Self {
+        self.time_to_live = time_to_live;
+        self
+    }
+
+    /// All the instances of the host (`CLNT_UCAST_EX`)
+    pub async fn instances(&self, host: &str) -> Result<Vec<InstanceInfo>, BrowserError> {
+        let key = host.to_ascii_lowercase();
+        if let Some(instances) = self.cached_instances(&key) {
+            return Ok(instances);
+        }
+
+        let instances = self.ask(host, &[CLNT_UCAST_EX]).await?;
+        self.cache(key, &instances);
+
+        Ok(instances)
+    }
+
+    /// One instance of the host (`CLNT_UCAST_INST`)
+    pub async fn instance(
+        &self,
+        host: &str,
+        instance_name: &str,
+    ) -> Result<InstanceInfo, BrowserError> {
+        let find = |instances: &[InstanceInfo]| {
+            instances
+                .iter()
+                .find(|instance| instance.instance_name.eq_ignore_ascii_case(instance_name))
+                .cloned()
+        };
+        // Kept with all the instances of the host, or on its own
+        let key = format!("{}\\{}", host, instance_name).to_ascii_lowercase();
+        let cached = [host.to_ascii_lowercase(), key.clone()]
+            .iter()
+            .find_map(|key| self.cached_instances(key).and_then(|i| find(&i)));
+        if let Some(instance) = cached {
+            return Ok(instance);
+        }
+
+        // The instance name ends with a null byte
+        let mut request = vec![CLNT_UCAST_INST];
+        request.extend_from_slice(instance_name.as_bytes());
+        request.push(0);
+        let instances = self.ask(host, &request).await?;
+        self.cache(key, &instances);
+
+        find(&instances).ok_or_else(|| BrowserError::UnknownInstance {
+            host: host.to_owned(),
+            instance_name: instance_name.to_owned(),
+        })
+    }
+
+    /// The TCP port of a named instance
+    pub async fn tcp_port(&self, host: &str, instance_name: &str) -> Result<u16, BrowserError> {
+        let instance = self.instance(host, instance_name).await?;
+
+        instance.tcp_port.ok_or_else(|| {
+            BrowserError::InvalidResponse(format!("{} has no TCP port", instance_name))
+        })
+    }
+
+    /// Forgets the instances of the host, after the port of one of them stopped answering
+    pub fn invalidate(&self, host: &str) {
+        let host = host.to_ascii_lowercase();
+        self.cached
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.split('\\').next() != Some(host.as_str()));
+    }
+
+    fn cached_instances(&self, key: &str) -> Option<Vec<InstanceInfo>> {
+        let cached = self.cached.lock().unwrap();
+        match cached.get(key) {
+            Some((instances, fetched)) if fetched.elapsed() < self.time_to_live => {
+                Some(instances.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn cache(&self, key: String, instances: &[InstanceInfo]) {
+        self.cached
+            .lock()
+            .unwrap()
+            .insert(key, (instances.to_vec(), Instant::now()));
+    }
+
+    // Tries the addresses of the host one after the other, the first answer wins,
+    // the error of the last address is returned when none of them answers
+    async fn ask(&self, host: &str, request: &[u8]) -> Result<Vec<InstanceInfo>, BrowserError> {
+        let mut error = None;
+
+        for address in tokio::net::lookup_host((host, self.port)).await? {
+            let local: SocketAddr = if address.is_ipv4() {
+                "0.0.0.0:0".parse().unwrap()
+            } else {
+                "[::]:0".parse().unwrap()
+            };
+            // An address that can't be reached is skipped like one that doesn't answer
+            let socket = match UdpSocket::bind(local).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    error = Some(BrowserError::Io(e));
+                    continue;
+                }
+            };
+            if let Err(e) = socket.send_to(request, address).await {
+                error = Some(BrowserError::Io(e));
+                continue;
+            }
+
+            let mut response = vec![0u8; 65535 + 3];
+            match tokio::time::timeout(self.timeout, socket.recv(&mut response)).await {
+                Ok(Ok(received)) => return parse_response(&response[..received]),
+                Ok(Err(e)) => error = Some(BrowserError::Io(e)),
+                Err(_) => {
+                    error = Some(BrowserError::Timeout {
+                        address,
+                        after: self.timeout,
+                    })
+                }
+            }
+        }
+
+        Err(error.unwrap_or_else(|| {
+            BrowserError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} has no address", host),
+            ))
+        }))
+    }
+}
+
+impl Default for SqlServerBrowser {
+    fn default() -> Self {
+        SqlServerBrowser::new()
+    }
+}
+
+// SVR_RESP: 0x05, the size of the data on 2 bytes (little endian), then the instances
+// separated with `;;`
+fn parse_response(response: &[u8]) -> Result<Vec<InstanceInfo>, BrowserError> {
+    let invalid = |message: &str| BrowserError::InvalidResponse(message.to_owned());
+
+    if response.len() < 3 || response[0] != SVR_RESP {
+        return Err(invalid("not a SVR_RESP message"));
+    }
+    let size = u16::from_le_bytes([response[1], response[2]]) as usize;
+    let data = response
+        .get(3..3 + size)
+        .ok_or_else(|| invalid("shorter than its size"))?;
+    // The data is in the code page of the server, instance names are ASCII
+    let data = String::from_utf8_lossy(data);
+
+    data.split(";;")
+        .map(|description| description.trim_end_matches(';'))
+        .filter(|description| !description.is_empty())
+        .map(InstanceInfo::parse)
+        .collect()
+}
+
+pub async fn list_instances_with_sql_browser() -> Result<(), Box<dyn std::error::Error>> {
+    let browser = SqlServerBrowser::new();
+
+    for instance in browser.instances("127.0.0.1").await? {
+        println!(
+            "{}\\{} (version {}), TCP port {}",
+            instance.server_name,
+            instance.instance_name,
+            instance.version,
+            instance
+                .tcp_port
+                .map_or_else(|| "off".to_owned(), |port| port.to_string())
+        );
+    }
+
+    Ok(())
+}
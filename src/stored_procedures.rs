@@ -1,60 +1,89 @@
-use tiberius::{AuthMethod, Client, Config, QueryItem};
-use tokio::net::TcpStream;
-use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+use tiberius::QueryItem;
 use tokio_stream::StreamExt;
 
-async fn connect_with_host_port() -> Result<Client<Compat<TcpStream>>, Box<dyn std::error::Error>> {
-    let mut config = Config::new();
-    config.authentication(AuthMethod::Integrated);
-    config.host("127.0.0.1");
-    config.port(22828);
-    config.database("FakeAdventureWorks");
-    config.trust_cert();
-
-    let tcp = TcpStream::connect(config.get_addr()).await?;
-    let client = Client::connect(config, tcp.compat_write()).await?;
-
-    Ok(client)
-}
-
-pub async fn create_stored_procedure() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
-
-    let _ = client
-        .simple_query(
-            r#"
-create procedure dbo.uspSaveOrderHeader @DueDate datetime,
+use crate::connections::connect_to_local_server;
+use crate::{
+    deploy_object, json_array, sales_order_header, ObjectKind, ObjectName, ProgrammableObject,
+    TraceSettings, TracedClient,
+};
+
+/// Inserts a sales order
+pub fn save_order_header_procedure(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> ProgrammableObject {
+    ProgrammableObject::new(
+        ObjectKind::Procedure,
+        procedure,
+        format!(
+            r#"@DueDate datetime,
                                         @ShipDate datetime,
                                         @CreditCardApprovalCode varchar(15),
                                         @Comment nvarchar(128) = null,
                                         @ModifiedDate datetime
 as
 begin
-    insert dbo.SalesOrderHeader(DueDate, ShipDate, CreditCardApprovalCode, Comment, ModifiedDate)
+    insert {table}(DueDate, ShipDate, CreditCardApprovalCode, Comment, ModifiedDate)
     values (@DueDate, @ShipDate, @CreditCardApprovalCode, @Comment, @ModifiedDate)
 end
+"#
+        ),
+    )
+}
 
-    "#,
-        )
-        .await?;
+pub async fn create_stored_procedure() -> Result<(), Box<dyn std::error::Error>> {
+    create_stored_procedure_named(
+        &ObjectName::new("dbo", "uspSaveOrderHeader")?,
+        &sales_order_header(),
+    )
+    .await
+}
 
-    println!("Created stored procedure.");
+pub async fn create_stored_procedure_named(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let object = save_order_header_procedure(procedure, table);
+    let outcome = deploy_object(&mut client, &object).await?;
+
+    println!("{} stored procedure.", outcome);
 
     Ok(())
 }
 
 pub async fn call_stored_procedure() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    call_stored_procedure_named(&ObjectName::new("dbo", "uspSaveOrderHeader")?).await
+}
+
+pub async fn call_stored_procedure_named(
+    procedure: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The call is a `sql.statement` span with the values of its parameters,
+    // but the value of @CreditCardApprovalCode is masked
+    let mut client = TracedClient::new(
+        connect_to_local_server().await?,
+        "127.0.0.1",
+        Some("FakeAdventureWorks"),
+    )
+    .settings(TraceSettings {
+        log_parameters: true,
+        ..TraceSettings::default()
+    });
 
     // The @Comment parameter is not set because it defaults to NULL
     // However it's still possible to set this parameter if desired
     let _ = client
         .execute(
-            r#"exec dbo.uspSaveOrderHeader
+            &format!(
+                r#"exec {}
         @DueDate = @P1,
         @ShipDate = @P2,
         @CreditCardApprovalCode = @P3,
         @ModifiedDate = @P4"#,
+                procedure
+            ),
             &[
                 &"2024-08-20", // DueDate
                 &"2024-08-22", // ShipDate
@@ -69,14 +98,17 @@ pub async fn call_stored_procedure() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-pub async fn create_stored_procedure_output_parameter() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
-
+/// Inserts a sales order and returns its ID in an output parameter
+pub fn save_order_header_get_id_procedure(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> ProgrammableObject {
     // @SalesOrderID is the output parameter of the stored procedure
-    let _ = client
-        .simple_query(
-            r#"
-create procedure dbo.uspSaveOrderHeaderGetID @DueDate datetime,
+    ProgrammableObject::new(
+        ObjectKind::Procedure,
+        procedure,
+        format!(
+            r#"@DueDate datetime,
                                              @ShipDate datetime,
                                              @CreditCardApprovalCode varchar(15),
                                              @Comment nvarchar(128) = null,
@@ -84,28 +116,57 @@ create procedure dbo.uspSaveOrderHeaderGetID @DueDate datetime,
                                              @SalesOrderID int output
 as
 begin
-    insert dbo.SalesOrderHeader(DueDate, ShipDate, CreditCardApprovalCode, Comment, ModifiedDate)
+    insert {table}(DueDate, ShipDate, CreditCardApprovalCode, Comment, ModifiedDate)
     values (@DueDate, @ShipDate, @CreditCardApprovalCode, @Comment, @ModifiedDate)
 
     set @SalesOrderID = @@identity
 end
-    "#,
-        )
-        .await?;
+"#
+        ),
+    )
+}
+
+pub async fn create_stored_procedure_output_parameter() -> Result<(), Box<dyn std::error::Error>> {
+    create_stored_procedure_output_parameter_named(
+        &ObjectName::new("dbo", "uspSaveOrderHeaderGetID")?,
+        &sales_order_header(),
+    )
+    .await
+}
+
+pub async fn create_stored_procedure_output_parameter_named(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
 
-    println!("Created stored procedure with output parameter.");
+    let object = save_order_header_get_id_procedure(procedure, table);
+    let outcome = deploy_object(&mut client, &object).await?;
+
+    println!("{} stored procedure with output parameter.", outcome);
 
     Ok(())
 }
 
 pub async fn call_stored_procedure_output_parameter() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    call_stored_procedure_output_parameter_named(&ObjectName::new(
+        "dbo",
+        "uspSaveOrderHeaderGetID",
+    )?)
+    .await
+}
+
+pub async fn call_stored_procedure_output_parameter_named(
+    procedure: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
 
     let mut result = client
         .query(
-            r#"
+            format!(
+                r#"
         declare @NewSalesOrderID int
-        exec dbo.uspSaveOrderHeaderGetID
+        exec {}
         @DueDate = @P1,
         @ShipDate = @P2,
         @CreditCardApprovalCode = @P3,
@@ -114,6 +175,8 @@ pub async fn call_stored_procedure_output_parameter() -> Result<(), Box<dyn std:
 
         select @NewSalesOrderID as SalesOrderID
         "#,
+                procedure
+            ),
             &[
                 &"2024-09-12", // DueDate
                 &"2024-09-22", // ShipDate
@@ -135,20 +198,23 @@ pub async fn call_stored_procedure_output_parameter() -> Result<(), Box<dyn std:
     Ok(())
 }
 
-pub async fn create_procedure_returns_status_code() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
-
+/// Changes the status of a sales order, returns -2 when it fails
+pub fn update_order_status_procedure(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> ProgrammableObject {
     // The `return` keyword is optional in SQL Server stored procedures,
     // if not specified, the database engine returns 0
-    let _ = client
-        .simple_query(
-            r#"
-create procedure dbo.uspUpdateOrderStatus @SalesOrderID int,
+    ProgrammableObject::new(
+        ObjectKind::Procedure,
+        procedure,
+        format!(
+            r#"@SalesOrderID int,
                                       @Status int
 as
 begin
     begin try
-        update SalesOrderHeader
+        update {table}
         set Status=@Status
         where SalesOrderID = @SalesOrderID
         return 0
@@ -157,17 +223,45 @@ begin
         return -2
     end catch
 end
-    "#,
-        )
-        .await?;
+"#
+        ),
+    )
+}
 
-    println!("Created stored procedure that returns a status code.");
+pub async fn create_procedure_returns_status_code() -> Result<(), Box<dyn std::error::Error>> {
+    create_procedure_returns_status_code_named(
+        &ObjectName::new("dbo", "uspUpdateOrderStatus")?,
+        &sales_order_header(),
+    )
+    .await
+}
+
+pub async fn create_procedure_returns_status_code_named(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let object = update_order_status_procedure(procedure, table);
+    let outcome = deploy_object(&mut client, &object).await?;
+
+    println!("{} stored procedure that returns a status code.", outcome);
 
     Ok(())
 }
 
 pub async fn call_stored_procedure_returns_status_code() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    call_stored_procedure_returns_status_code_named(&ObjectName::new(
+        "dbo",
+        "uspUpdateOrderStatus",
+    )?)
+    .await
+}
+
+pub async fn call_stored_procedure_returns_status_code_named(
+    procedure: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
 
     let sales_order_id: i32 = 2;
     let status: i32 = 16;
@@ -176,11 +270,14 @@ pub async fn call_stored_procedure_returns_status_code() -> Result<(), Box<dyn s
     // valid values must be between 0 and 8
     let mut result = client
         .query(
-            r#"
+            format!(
+                r#"
         declare @ReturnCode int
-        exec @ReturnCode = dbo.uspUpdateOrderStatus @SalesOrderID = @P1, @Status = @P2
+        exec @ReturnCode = {} @SalesOrderID = @P1, @Status = @P2
         select @ReturnCode as ReturnCode
         "#,
+                procedure
+            ),
             &[
                 &sales_order_id, // SalesOrderID
                 &status,         // Status
@@ -209,13 +306,16 @@ pub async fn call_stored_procedure_returns_status_code() -> Result<(), Box<dyn s
     Ok(())
 }
 
-pub async fn create_stored_procedure_returns_table() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
-
-    let _ = client
-        .simple_query(
-            r#"
-create procedure dbo.uspGetSaleOrderByID @SalesOrderID int
+/// Returns the details and the summary of a sales order
+pub fn get_sale_order_by_id_procedure(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> ProgrammableObject {
+    ProgrammableObject::new(
+        ObjectKind::Procedure,
+        procedure,
+        format!(
+            r#"@SalesOrderID int
 as
 begin
 
@@ -235,7 +335,7 @@ begin
            rowguid,
            ModifiedDate
     into #saleorder
-    from dbo.SalesOrderHeader
+    from {table}
     where
         SalesOrderID=@SalesOrderID
 
@@ -266,23 +366,50 @@ begin
            TotalDue
     from #saleorder
 end
-    "#,
-        )
-        .await?;
+"#
+        ),
+    )
+}
+
+pub async fn create_stored_procedure_returns_table() -> Result<(), Box<dyn std::error::Error>> {
+    create_stored_procedure_returns_table_named(
+        &ObjectName::new("dbo", "uspGetSaleOrderByID")?,
+        &sales_order_header(),
+    )
+    .await
+}
+
+pub async fn create_stored_procedure_returns_table_named(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
 
-    println!("Created stored procedure that returns a table.");
+    let object = get_sale_order_by_id_procedure(procedure, table);
+    let outcome = deploy_object(&mut client, &object).await?;
+
+    println!("{} stored procedure that returns a table.", outcome);
 
     Ok(())
 }
 
 pub async fn call_stored_procedure_returns_table() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    call_stored_procedure_returns_table_named(&ObjectName::new("dbo", "uspGetSaleOrderByID")?).await
+}
+
+pub async fn call_stored_procedure_returns_table_named(
+    procedure: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
 
     let mut result = client
         .query(
-            r#"
-        exec dbo.uspGetSaleOrderByID @SalesOrderID = @P1
+            format!(
+                r#"
+        exec {} @SalesOrderID = @P1
         "#,
+                procedure
+            ),
             &[&2i32], // SalesOrderID
         )
         .await?;
@@ -357,4 +484,90 @@ pub async fn call_stored_procedure_returns_table() -> Result<(), Box<dyn std::er
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Returns the sales orders whose IDs are in a JSON array
+pub fn get_sale_orders_by_ids_procedure(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> ProgrammableObject {
+    // @SalesOrderIDs is a JSON array like [1,2,3],
+    // OPENJSON turns it into a table of IDs
+    ProgrammableObject::new(
+        ObjectKind::Procedure,
+        procedure,
+        format!(
+            r#"@SalesOrderIDs nvarchar(max)
+as
+begin
+    select SalesOrderID,
+           OrderDate,
+           Status,
+           TotalDue
+    from {table}
+    where
+        SalesOrderID in (select [value] from openjson(@SalesOrderIDs) with ([value] int '$'))
+end
+"#
+        ),
+    )
+}
+
+pub async fn create_stored_procedure_set_parameter() -> Result<(), Box<dyn std::error::Error>> {
+    create_stored_procedure_set_parameter_named(
+        &ObjectName::new("dbo", "uspGetSaleOrdersByIDs")?,
+        &sales_order_header(),
+    )
+    .await
+}
+
+pub async fn create_stored_procedure_set_parameter_named(
+    procedure: &ObjectName,
+    table: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let object = get_sale_orders_by_ids_procedure(procedure, table);
+    let outcome = deploy_object(&mut client, &object).await?;
+
+    println!("{} stored procedure with a set parameter.", outcome);
+
+    Ok(())
+}
+
+pub async fn call_stored_procedure_set_parameter() -> Result<(), Box<dyn std::error::Error>> {
+    call_stored_procedure_set_parameter_named(&ObjectName::new("dbo", "uspGetSaleOrdersByIDs")?)
+        .await
+}
+
+pub async fn call_stored_procedure_set_parameter_named(
+    procedure: &ObjectName,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let sales_order_ids = [1, 2, 3, 5, 8];
+
+    let mut result = client
+        .query(
+            format!("exec {} @SalesOrderIDs = @P1", procedure),
+            &[&json_array(&sales_order_ids)], // SalesOrderIDs
+        )
+        .await?;
+
+    while let Some(row) = result.try_next().await? {
+        if let QueryItem::Row(r) = row {
+            let sales_order_id: i32 = r.get("SalesOrderID").unwrap();
+            let order_date: chrono::NaiveDateTime = r.get("OrderDate").unwrap();
+            let status: u8 = r.get("Status").unwrap();
+            let total_due: f64 = r.get("TotalDue").unwrap();
+
+            println!("Sale order ID: {}", sales_order_id);
+            println!("Order date: {}", order_date);
+            println!("Status: {}", status);
+            println!("Total due: {}", total_due);
+            println!();
+        }
+    }
+
+    Ok(())
+}
@@ -1,65 +1,81 @@
-use tiberius::{AuthMethod, Client, Config, Query, QueryItem};
-use tokio::net::TcpStream;
-use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+use tiberius::{Query, QueryItem};
 use tokio_stream::StreamExt;
 
-async fn connect_with_host_port() -> Result<Client<Compat<TcpStream>>, Box<dyn std::error::Error>> {
-    let mut config = Config::new();
-    config.authentication(AuthMethod::Integrated);
-    config.host("127.0.0.1");
-    config.port(22828);
-    config.database("FakeAdventureWorks");
-    config.trust_cert();
+use crate::connections::{connect_to_local_server, local_server};
+use crate::{
+    create_table_statements, ColumnDefinition, DataType, FailoverClient, ObjectName,
+    TableDefinition,
+};
 
-    let tcp = TcpStream::connect(config.get_addr()).await?;
-    let client = Client::connect(config, tcp.compat_write()).await?;
-
-    Ok(client)
+/// The table used by the examples of this crate
+pub fn sales_order_header() -> ObjectName {
+    ObjectName::new("dbo", "SalesOrderHeader").expect("valid table name")
 }
 
 pub async fn create_table() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
-
-    let statement = Query::new(
-        r#"
-create table dbo.SalesOrderHeader
-(
-    SalesOrderID           int identity
-        constraint PK_SalesOrderHeader_SalesOrderID
-            primary key,
-    RevisionNumber         tinyint
-        constraint DF_SalesOrderHeader_RevisionNumber default 0       not null,
-    OrderDate              datetime
-        constraint DF_SalesOrderHeader_OrderDate default getdate()    not null,
-    DueDate                datetime                                   not null,
-    ShipDate               datetime,
-    Status                 tinyint
-        constraint DF_SalesOrderHeader_Status default 1               not null
-        constraint CK_SalesOrderHeader_Status
-            check ([Status] >= 0 AND [Status] <= 8),
-    SalesOrderNumber       as isnull(N'SO' + CONVERT([nvarchar](23), [SalesOrderID]), N'*** ERROR ***'),
-    CreditCardApprovalCode varchar(15),
-    SubTotal               money
-        constraint DF_SalesOrderHeader_SubTotal default 0.00          not null
-        constraint CK_SalesOrderHeader_SubTotal
-            check ([SubTotal] >= 0.00),
-    TaxAmt                 money
-        constraint DF_SalesOrderHeader_TaxAmt default 0.00            not null
-        constraint CK_SalesOrderHeader_TaxAmt
-            check ([TaxAmt] >= 0.00),
-    Freight                money
-        constraint DF_SalesOrderHeader_Freight default 0.00           not null
-        constraint CK_SalesOrderHeader_Freight
-            check ([Freight] >= 0.00),
-    TotalDue               as isnull([SubTotal] + [TaxAmt] + [Freight], 0),
-    Comment                nvarchar(128),
-    rowguid                uniqueidentifier default newid()        not null,
-    ModifiedDate           datetime
-)
-    "#,
-    );
+    create_table_named(&sales_order_header()).await
+}
+
+/// The columns and constraints of `dbo.SalesOrderHeader`, for a table with the given name.
+///
+/// The columns are the ones read into `SalesOrder`.
+pub fn sales_order_header_definition(table: &ObjectName) -> TableDefinition {
+    TableDefinition::new(table)
+        .column(ColumnDefinition::new("SalesOrderID", DataType::int()).identity(1, 1))
+        .column(ColumnDefinition::new("RevisionNumber", DataType::tinyint()).default("0"))
+        .column(ColumnDefinition::new("OrderDate", DataType::datetime()).default("getdate()"))
+        .column(ColumnDefinition::new("DueDate", DataType::datetime()))
+        .column(ColumnDefinition::new("ShipDate", DataType::datetime()).nullable())
+        .column(
+            ColumnDefinition::new("Status", DataType::tinyint())
+                .default("1")
+                .check("[Status] >= 0 AND [Status] <= 8"),
+        )
+        .column(ColumnDefinition::computed(
+            "SalesOrderNumber",
+            DataType::nvarchar(25),
+            "isnull(N'SO' + CONVERT([nvarchar](23), [SalesOrderID]), N'*** ERROR ***')",
+        ))
+        .column(ColumnDefinition::new("CreditCardApprovalCode", DataType::varchar(15)).nullable())
+        .column(
+            ColumnDefinition::new("SubTotal", DataType::money())
+                .default("0.00")
+                .check("[SubTotal] >= 0.00"),
+        )
+        .column(
+            ColumnDefinition::new("TaxAmt", DataType::money())
+                .default("0.00")
+                .check("[TaxAmt] >= 0.00"),
+        )
+        .column(
+            ColumnDefinition::new("Freight", DataType::money())
+                .default("0.00")
+                .check("[Freight] >= 0.00"),
+        )
+        .column(ColumnDefinition::computed(
+            "TotalDue",
+            DataType::money(),
+            "isnull([SubTotal] + [TaxAmt] + [Freight], 0)",
+        ))
+        .column(ColumnDefinition::new("Comment", DataType::nvarchar(128)).nullable())
+        .column(
+            ColumnDefinition::new("rowguid", DataType::uniqueidentifier())
+                .unnamed_default("newid()"),
+        )
+        .column(ColumnDefinition::new("ModifiedDate", DataType::datetime()).nullable())
+        .primary_key(&["SalesOrderID"])
+}
+
+pub async fn create_table_named(table: &ObjectName) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    // Constraint names must be unique in the schema,
+    // so they are named after the table (PK_SalesOrderHeader_SalesOrderID)
+    let schema = sales_order_header_definition(table).build()?;
 
-    let _ = statement.execute(&mut client).await?;
+    for statement in create_table_statements(&schema) {
+        client.simple_query(statement).await?.into_results().await?;
+    }
     println!("Created table");
 
     let _ = client.close().await?;
@@ -68,11 +84,14 @@ create table dbo.SalesOrderHeader
 }
 
 pub async fn insert_row() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    insert_row_named(&sales_order_header()).await
+}
 
-    let result = client
-        .execute(
-            r#"INSERT INTO dbo.SalesOrderHeader (
+pub async fn insert_row_named(table: &ObjectName) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let statement = format!(
+        r#"INSERT INTO {} (
         RevisionNumber, OrderDate, DueDate, ShipDate, Status, CreditCardApprovalCode,
         SubTotal, TaxAmt, Freight, Comment, rowguid, ModifiedDate
         )
@@ -80,6 +99,12 @@ pub async fn insert_row() -> Result<(), Box<dyn std::error::Error>> {
         (
         @P1, @P2, @P3, @P4, @P5, @P6, @P7, @P8, @P9, @P10, @P11, @P12
         );"#,
+        table
+    );
+
+    let result = client
+        .execute(
+            statement,
             // These are the values for the parameters in the
             // INSERT statement
             &[
@@ -107,10 +132,18 @@ pub async fn insert_row() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 pub async fn select_row() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    select_row_named(&sales_order_header()).await
+}
+
+pub async fn select_row_named(table: &ObjectName) -> Result<(), Box<dyn std::error::Error>> {
+    // A read: it goes to a readable secondary replica through the listener,
+    // and runs again on a new connection after a failover
+    let mut server = local_server()?;
+    server.readonly = true;
+    let mut connection = FailoverClient::connect(server).await?;
 
     // Get the sale with an order ID equals to 1
-    let mut result = Query::new(
+    let sql = format!(
         r#"select SalesOrderID,
        RevisionNumber,
        OrderDate,
@@ -126,93 +159,106 @@ pub async fn select_row() -> Result<(), Box<dyn std::error::Error>> {
        Comment,
        rowguid,
        ModifiedDate
-from dbo.SalesOrderHeader
+from {}
 WHERE
     SalesOrderID = @P1;"#,
+        table
     );
 
-    // this will be the value of the parameter @P1
-    result.bind(1i32);
-
-    // get the result set from SQL Server
-    let mut rows = result.query(&mut client).await?;
-
-    // This will read all the returned rows
-    while let Some(row) = rows.try_next().await? {
-        match row {
-            // This section contains the rows returned by the query
-            QueryItem::Row(r) => {
-                let salesorderid: i32 = r.get(0).unwrap();
-                let revisionnumber: u8 = r.get(1).unwrap();
-                let orderdate: chrono::NaiveDateTime = r.get(2).unwrap();
-                let duedate: chrono::NaiveDateTime = r.get(3).unwrap();
-                let shipdate: chrono::NaiveDateTime = r.get(4).unwrap();
-                let status: u8 = r.get(5).unwrap();
-                let salesordernumber: &str = r.get(6).unwrap();
-                let creditcardapprovalcode: &str = r.get(7).unwrap();
-                let subtotal: f64 = r.get(8).unwrap();
-                let taxamt: f64 = r.get(9).unwrap();
-                let freight: f64 = r.get(10).unwrap();
-                let totaldue: f64 = r.get(11).unwrap();
-                let comment: &str = r.get(12).unwrap_or_else(|| "");
-                let rowguid: uuid::Uuid = r.get(13).unwrap();
-                let modifieddate: chrono::NaiveDateTime = r.get(14).unwrap();
-
-                println!("SalesOrderID: {}", salesorderid);
-                println!("RevisionNumber: {}", revisionnumber);
-                println!("OrderDate: {}", orderdate);
-                println!("DueDate: {}", duedate);
-                println!("ShipDate: {}", shipdate);
-                println!("Status: {}", status);
-                println!("SalesOrderNumber: {}", salesordernumber);
-                println!(
-                    "CreditCardApprovalCode: {}",
-                    creditcardapprovalcode.to_owned()
-                );
-                println!("SubTotal: {}", subtotal);
-                println!("TaxAmt: {}", taxamt);
-                println!("Freight: {}", freight);
-                println!("TotalDue: {}", totaldue);
-                println!("Comment: {}", comment.to_owned());
-                println!("rowguid: {}", rowguid);
-                println!("ModifiedDate: {}", modifieddate);
-                println!("--------------------------------------");
-                println!();
-            }
-
-            // This section contains the metadata of the result set
-            QueryItem::Metadata(meta) => {
-                println!("Metadata: {:?}", meta);
-                // The above line comes out with this:
-                // ResultMetadata { columns: [
-                // Column { name: "SalesOrderID", column_type: Int4 },
-                // Column { name: "RevisionNumber", column_type: Int1 },
-                // Column { name: "OrderDate", column_type: Datetime },
-                // Column { name: "DueDate", column_type: Datetime },
-                // Column { name: "ShipDate", column_type: Datetimen },
-                // Column { name: "Status", column_type: Int1 },
-                // Column { name: "SalesOrderNumber", column_type: NVarchar },
-                // Column { name: "CreditCardApprovalCode", column_type: BigVarChar },
-                // Column { name: "SubTotal", column_type: Money },
-                // Column { name: "TaxAmt", column_type: Money },
-                // Column { name: "Freight", column_type: Money },
-                // Column { name: "TotalDue", column_type: Money },
-                // Column { name: "Comment", column_type: NVarchar },
-                // Column { name: "rowguid", column_type: Guid },
-                // Column { name: "ModifiedDate", column_type: Datetimen }], result_index: 0 }
-            }
-        }
-    }
+    connection
+        .run(|client| {
+            let sql = sql.clone();
+            Box::pin(async move {
+                let mut result = Query::new(sql);
 
-    Ok(())
+                // this will be the value of the parameter @P1
+                result.bind(1i32);
+
+                // get the result set from SQL Server
+                let mut rows = result.query(client).await?;
+
+                // This will read all the returned rows
+                while let Some(row) = rows.try_next().await? {
+                    match row {
+                        // This section contains the rows returned by the query
+                        QueryItem::Row(r) => {
+                            let salesorderid: i32 = r.get(0).unwrap();
+                            let revisionnumber: u8 = r.get(1).unwrap();
+                            let orderdate: chrono::NaiveDateTime = r.get(2).unwrap();
+                            let duedate: chrono::NaiveDateTime = r.get(3).unwrap();
+                            let shipdate: chrono::NaiveDateTime = r.get(4).unwrap();
+                            let status: u8 = r.get(5).unwrap();
+                            let salesordernumber: &str = r.get(6).unwrap();
+                            let creditcardapprovalcode: &str = r.get(7).unwrap();
+                            let subtotal: f64 = r.get(8).unwrap();
+                            let taxamt: f64 = r.get(9).unwrap();
+                            let freight: f64 = r.get(10).unwrap();
+                            let totaldue: f64 = r.get(11).unwrap();
+                            let comment: &str = r.get(12).unwrap_or_else(|| "");
+                            let rowguid: uuid::Uuid = r.get(13).unwrap();
+                            let modifieddate: chrono::NaiveDateTime = r.get(14).unwrap();
+
+                            println!("SalesOrderID: {}", salesorderid);
+                            println!("RevisionNumber: {}", revisionnumber);
+                            println!("OrderDate: {}", orderdate);
+                            println!("DueDate: {}", duedate);
+                            println!("ShipDate: {}", shipdate);
+                            println!("Status: {}", status);
+                            println!("SalesOrderNumber: {}", salesordernumber);
+                            println!(
+                                "CreditCardApprovalCode: {}",
+                                creditcardapprovalcode.to_owned()
+                            );
+                            println!("SubTotal: {}", subtotal);
+                            println!("TaxAmt: {}", taxamt);
+                            println!("Freight: {}", freight);
+                            println!("TotalDue: {}", totaldue);
+                            println!("Comment: {}", comment.to_owned());
+                            println!("rowguid: {}", rowguid);
+                            println!("ModifiedDate: {}", modifieddate);
+                            println!("--------------------------------------");
+                            println!();
+                        }
+
+                        // This section contains the metadata of the result set
+                        QueryItem::Metadata(meta) => {
+                            println!("Metadata: {:?}", meta);
+                            // The above line comes out with this:
+                            // ResultMetadata { columns: [
+                            // Column { name: "SalesOrderID", column_type: Int4 },
+                            // Column { name: "RevisionNumber", column_type: Int1 },
+                            // Column { name: "OrderDate", column_type: Datetime },
+                            // Column { name: "DueDate", column_type: Datetime },
+                            // Column { name: "ShipDate", column_type: Datetimen },
+                            // Column { name: "Status", column_type: Int1 },
+                            // Column { name: "SalesOrderNumber", column_type: NVarchar },
+                            // Column { name: "CreditCardApprovalCode", column_type: BigVarChar },
+                            // Column { name: "SubTotal", column_type: Money },
+                            // Column { name: "TaxAmt", column_type: Money },
+                            // Column { name: "Freight", column_type: Money },
+                            // Column { name: "TotalDue", column_type: Money },
+                            // Column { name: "Comment", column_type: NVarchar },
+                            // Column { name: "rowguid", column_type: Guid },
+                            // Column { name: "ModifiedDate", column_type: Datetimen }], result_index: 0 }
+                        }
+                    }
+                }
+
+                Ok(())
+            })
+        })
+        .await
 }
 
 pub async fn update_row() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    update_row_named(&sales_order_header()).await
+}
 
-    let result = client
-        .execute(
-            r#"UPDATE dbo.SalesOrderHeader
+pub async fn update_row_named(table: &ObjectName) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
+
+    let statement = format!(
+        r#"UPDATE {}
 SET
     RevisionNumber = @P1,
     OrderDate = @P2,
@@ -228,6 +274,12 @@ SET
     ModifiedDate = @P12
 WHERE
     SalesOrderID = @P13;"#,
+        table
+    );
+
+    let result = client
+        .execute(
+            statement,
             // These will be the values for the parameters in the
             // UPDATE statement
             &[
@@ -256,14 +308,23 @@ WHERE
 }
 
 pub async fn delete_row() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = connect_with_host_port().await?;
+    delete_row_named(&sales_order_header()).await
+}
+
+pub async fn delete_row_named(table: &ObjectName) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_to_local_server().await?;
 
     // Delete the sale with order ID equals to 1
-    let result = client
-        .execute(
-            r#"DELETE FROM dbo.SalesOrderHeader
+    let statement = format!(
+        r#"DELETE FROM {}
 WHERE
     SalesOrderID = @P1;"#,
+        table
+    );
+
+    let result = client
+        .execute(
+            statement,
             // this will be the value of the parameter @P1
             &[&1i32],
         )
@@ -274,4 +335,4 @@ WHERE
     client.close().await?;
 
     Ok(())
-}
\ No newline at end of file
+}
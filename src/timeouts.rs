@@ -0,0 +1,106 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+/// How long connecting, logging in and running a query may take, `None` waits forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Reaching the server, with SQL Server Browser for a named instance
+    pub connect: Option<Duration>,
+    /// The TLS handshake and the login, once the server is reached
+    pub login: Option<Duration>,
+    /// A query run by `QueryTimeout::run`
+    pub query: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    /// 15 seconds to connect and to log in as ADO.NET, queries are not bounded
+    fn default() -> Self {
+        Timeouts {
+            connect: Some(Duration::from_secs(15)),
+            login: Some(Duration::from_secs(15)),
+            query: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TimeoutError {
+    Connect {
+        server: String,
+        after: Duration,
+    },
+    Login {
+        server: String,
+        after: Duration,
+    },
+    /// The connection of the query can't be used again
+    Query {
+        after: Duration,
+    },
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutError::Connect { server, after } => {
+                write!(f, "Could not reach {} in {:?}", server, after)
+            }
+            TimeoutError::Login { server, after } => {
+                write!(f, "{} did not complete the login in {:?}", server, after)
+            }
+            TimeoutError::Query { after } => write!(
+                f,
+                "The query did not finish in {:?}, close its connection: \
+                 Tiberius can't cancel a query and keep the connection",
+                after
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+// Runs `work` for at most `limit`, the error of `timed_out` when it takes longer
+pub(crate) async fn within<T>(
+    limit: Option<Duration>,
+    work: impl Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    timed_out: impl FnOnce(Duration) -> TimeoutError,
+) -> Result<T, Box<dyn std::error::Error>> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, work)
+            .await
+            .map_err(|_| timed_out(limit))?,
+        None => work.await,
+    }
+}
+
+/// Bounds the queries of a connection by `timeouts.query`.
+///
+/// A driver cancels a query that runs too long with a TDS attention and keeps its
+/// connection. Tiberius 0.12 can't send one: it has no API for it, and the packets go
+/// through its own TLS stream. When the limit is reached, the future of the query is
+/// dropped and `run` returns a `TimeoutError::Query`. The connection is left in the middle
+/// of a response, so the client must be dropped too: the server stops the query and rolls
+/// back its transaction once it sees the connection is closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryTimeout {
+    limit: Option<Duration>,
+}
+
+impl QueryTimeout {
+    pub fn new(timeouts: &Timeouts) -> Self {
+        QueryTimeout {
+            limit: timeouts.query,
+        }
+    }
+
+    /// Runs `work`, the queries of a connection, and stops waiting for it when it takes
+    /// longer than the limit.
+    pub async fn run<T>(
+        &self,
+        work: impl Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        within(self.limit, work, |after| TimeoutError::Query { after }).await
+    }
+}
@@ -0,0 +1,537 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{ready, Context, Poll};
+
+use async_native_tls::{Certificate, TlsConnector};
+use futures_util::io::{AsyncRead, AsyncWrite};
+use sha2::{Digest, Sha256};
+use tiberius::{Config, EncryptionLevel};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+/// How much of the traffic is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionMode {
+    /// No TLS at all, the login and its password are sent in clear (`DANGER_PLAINTEXT`)
+    Plaintext,
+    /// TLS for the login only
+    Off,
+    /// TLS when the server supports it
+    On,
+    /// TLS or no connection
+    #[default]
+    Required,
+    /// TDS 8.0, TLS before any TDS message
+    Strict,
+}
+
+impl FromStr for EncryptionMode {
+    type Err = TlsError;
+
+    /// The values of `Encrypt` in connection strings, and the mode names
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "danger_plaintext" | "plaintext" => Ok(EncryptionMode::Plaintext),
+            "false" | "no" | "optional" | "off" => Ok(EncryptionMode::Off),
+            "on" => Ok(EncryptionMode::On),
+            "true" | "yes" | "mandatory" | "required" => Ok(EncryptionMode::Required),
+            "strict" => Ok(EncryptionMode::Strict),
+            _ => Err(TlsError::InvalidEncryptionMode(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for EncryptionMode {
+    /// The value of `Encrypt` in connection strings
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            EncryptionMode::Plaintext => "DANGER_PLAINTEXT",
+            EncryptionMode::Off => "false",
+            EncryptionMode::On => "on",
+            EncryptionMode::Required => "true",
+            EncryptionMode::Strict => "strict",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// The SHA-256 fingerprint of a certificate, written as `A6:32:48:...`
+/// like `openssl x509 -noout -fingerprint -sha256` prints it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// The fingerprint of a DER encoded certificate
+    pub fn of(der: &[u8]) -> Self {
+        Fingerprint(Sha256::digest(der).into())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = TlsError;
+
+    /// Hexadecimal digits, with or without colons and a `sha256:` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TlsError::InvalidFingerprint(s.to_owned());
+        let trimmed = s.trim();
+        let digits: Vec<u8> = trimmed
+            .strip_prefix("sha256:")
+            .or_else(|| trimmed.strip_prefix("SHA256:"))
+            .unwrap_or(trimmed)
+            .bytes()
+            .filter(|b| *b != b':')
+            .collect();
+        if digits.len() != 64 {
+            return Err(invalid());
+        }
+
+        let mut bytes = [0; 32];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+
+        Ok(Fingerprint(bytes))
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: Vec<String> = self.0.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{}", hex.join(":"))
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+/// How the certificate of the server is checked.
+///
+/// The default validates it with the certificates of the system,
+/// against the host connected to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsSettings {
+    pub encryption: EncryptionMode,
+    /// A `.pem`, `.crt` or `.der` file with the certificate of the CA that signs
+    /// the server certificate. It's trusted on top of the certificates of the system,
+    /// which are still trusted too. Tiberius reads a single certificate from it.
+    pub ca_bundle: Option<PathBuf>,
+    /// The name the server certificate is issued to, when it is not the host connected to
+    pub server_name: Option<String>,
+    /// Fingerprints the server certificate must have. Not supported: Tiberius does
+    /// the handshake of the login itself and does not show the certificate it got,
+    /// so `validate` rejects them. `server_certificate` reads the fingerprint.
+    pub pinned_certificates: Vec<Fingerprint>,
+    /// Accepts any certificate, for a development server only
+    pub trust_server_certificate: bool,
+}
+
+impl TlsSettings {
+    /// Checks the settings go together and the CA bundle can be read, before connecting.
+    pub fn validate(&self) -> Result<(), TlsError> {
+        if self.encryption == EncryptionMode::Strict {
+            return Err(TlsError::StrictUnsupported);
+        }
+        if !self.pinned_certificates.is_empty() {
+            return Err(TlsError::PinningUnsupported);
+        }
+
+        let checks_certificate = self.ca_bundle.is_some() || self.server_name.is_some();
+        if self.encryption == EncryptionMode::Plaintext && checks_certificate {
+            return Err(TlsError::Conflict(
+                "DANGER_PLAINTEXT does not use TLS, there is no certificate to check",
+            ));
+        }
+        if self.trust_server_certificate && checks_certificate {
+            return Err(TlsError::Conflict(
+                "TrustServerCertificate accepts any certificate, \
+                 it can't be used with a CA bundle or a server name",
+            ));
+        }
+
+        if let Some(path) = &self.ca_bundle {
+            read_ca_bundle(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the encryption and the trust of a config. The server name is the host
+    /// of the config, it is set by `connect` after the TCP connection.
+    pub fn apply(&self, config: &mut Config) {
+        config.encryption(match self.encryption {
+            EncryptionMode::Plaintext => EncryptionLevel::NotSupported,
+            EncryptionMode::Off => EncryptionLevel::Off,
+            EncryptionMode::On => EncryptionLevel::On,
+            EncryptionMode::Required | EncryptionMode::Strict => EncryptionLevel::Required,
+        });
+
+        if self.trust_server_certificate {
+            config.trust_cert();
+        } else if let Some(path) = &self.ca_bundle {
+            config.trust_cert_ca(path.to_string_lossy());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    InvalidEncryptionMode(String),
+    InvalidFingerprint(String),
+    /// Tiberius 0.12 negotiates TLS inside the TDS 7.x prelogin only
+    StrictUnsupported,
+    /// Tiberius 0.12 does not show the certificate of its own handshake
+    PinningUnsupported,
+    Conflict(&'static str),
+    CaBundle {
+        path: PathBuf,
+        message: String,
+    },
+    /// The server answered the prelogin without TLS
+    NotEncrypted {
+        server_name: String,
+    },
+    /// The certificate is not signed by a trusted CA
+    Untrusted {
+        server_name: String,
+        message: String,
+    },
+    /// The certificate is issued to another name
+    HostnameMismatch {
+        server_name: String,
+        message: String,
+    },
+    Handshake {
+        server_name: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::InvalidEncryptionMode(mode) => write!(
+                f,
+                "{} is not an encryption mode, use DANGER_PLAINTEXT, off, on, required or strict",
+                mode
+            ),
+            TlsError::InvalidFingerprint(fingerprint) => write!(
+                f,
+                "{} is not a SHA-256 fingerprint, it must have 64 hexadecimal digits",
+                fingerprint
+            ),
+            TlsError::StrictUnsupported => write!(
+                f,
+                "Strict encryption (TDS 8.0) is not supported by Tiberius, use required"
+            ),
+            TlsError::PinningUnsupported => write!(
+                f,
+                "Certificate pinning is not supported by Tiberius, \
+                 give the certificate of the CA that signs the server certificate as CA bundle"
+            ),
+            TlsError::Conflict(message) => write!(f, "{}", message),
+            TlsError::CaBundle { path, message } => {
+                write!(f, "Cannot use {} as CA: {}", path.display(), message)
+            }
+            TlsError::NotEncrypted { server_name } => write!(
+                f,
+                "{} does not support TLS, its certificate can't be checked",
+                server_name
+            ),
+            TlsError::Untrusted {
+                server_name,
+                message,
+            } => write!(
+                f,
+                "The certificate of {} is not trusted ({}), \
+                 give the certificate of the CA that signs it as CA bundle",
+                server_name, message
+            ),
+            TlsError::HostnameMismatch {
+                server_name,
+                message,
+            } => write!(
+                f,
+                "The certificate of the server is not issued to {} ({}), \
+                 give the name it is issued to as server name (HostNameInCertificate)",
+                server_name, message
+            ),
+            TlsError::Handshake {
+                server_name,
+                message,
+            } => write!(f, "TLS handshake with {} failed: {}", server_name, message),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl TlsError {
+    /// Tells a validation failure from the message of the TLS library
+    pub(crate) fn from_handshake(server_name: &str, message: String) -> Self {
+        let lower = message.to_lowercase();
+        let server_name = server_name.to_owned();
+
+        if lower.contains("hostname mismatch")
+            || lower.contains("address mismatch")
+            || lower.contains("name does not match")
+        {
+            TlsError::HostnameMismatch {
+                server_name,
+                message,
+            }
+        } else if lower.contains("certificate verify failed")
+            || lower.contains("self-signed")
+            || lower.contains("self signed")
+            || lower.contains("not trusted")
+            || lower.contains("unable to get local issuer")
+        {
+            TlsError::Untrusted {
+                server_name,
+                message,
+            }
+        } else {
+            TlsError::Handshake {
+                server_name,
+                message,
+            }
+        }
+    }
+}
+
+fn read_ca_bundle(path: &Path) -> Result<Certificate, TlsError> {
+    let ca_error = |message: String| TlsError::CaBundle {
+        path: path.to_owned(),
+        message,
+    };
+
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    let content = std::fs::read(path).map_err(|e| ca_error(e.to_string()))?;
+
+    match extension.as_deref() {
+        Some("pem") | Some("crt") => {
+            let count = String::from_utf8_lossy(&content)
+                .matches("-----BEGIN CERTIFICATE-----")
+                .count();
+            if count != 1 {
+                return Err(ca_error(format!(
+                    "it holds {} certificates, keep only the CA that signs the server certificate",
+                    count
+                )));
+            }
+            Certificate::from_pem(&content).map_err(|e| ca_error(e.to_string()))
+        }
+        Some("der") => Certificate::from_der(&content).map_err(|e| ca_error(e.to_string())),
+        _ => Err(ca_error(
+            "the file must end with .pem, .crt or .der".to_owned(),
+        )),
+    }
+}
+
+const PRELOGIN: u8 = 0x12;
+const ENCRYPTION_OPTION: u8 = 0x01;
+const ENCRYPT_ON: u8 = 0x01;
+const ENCRYPT_NOT_SUP: u8 = 0x02;
+const TERMINATOR: u8 = 0xFF;
+
+fn packet(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let length = (payload.len() + 8) as u16;
+    let mut packet = vec![kind, 0x01];
+    packet.extend_from_slice(&length.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 1, 0]);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+// The VERSION and ENCRYPTION options, then their values
+fn prelogin_request() -> Vec<u8> {
+    let mut payload = vec![
+        0x00,
+        0x00,
+        0x0B,
+        0x00,
+        0x06,
+        ENCRYPTION_OPTION,
+        0x00,
+        0x11,
+        0x00,
+        0x01,
+        TERMINATOR,
+    ];
+    payload.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    payload.push(ENCRYPT_ON);
+
+    packet(PRELOGIN, &payload)
+}
+
+fn prelogin_encryption(payload: &[u8]) -> Option<u8> {
+    let mut option = 0;
+    while *payload.get(option)? != TERMINATOR {
+        let offset = u16::from_be_bytes([*payload.get(option + 1)?, *payload.get(option + 2)?]);
+        if payload[option] == ENCRYPTION_OPTION {
+            return payload.get(offset as usize).copied();
+        }
+        option += 5;
+    }
+    None
+}
+
+/// Carries TLS records in TDS prelogin packets, as SQL Server expects during the handshake.
+pub(crate) struct PreloginWrapper<S> {
+    stream: S,
+    // Bytes left in the payload of the packet being read
+    remaining: usize,
+    header: [u8; 8],
+    header_read: usize,
+    // Written by TLS, sent as one packet on flush
+    pending: Vec<u8>,
+    framed: Vec<u8>,
+    sent: usize,
+}
+
+impl<S> PreloginWrapper<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        PreloginWrapper {
+            stream,
+            remaining: 0,
+            header: [0; 8],
+            header_read: 0,
+            pending: Vec::new(),
+            framed: Vec::new(),
+            sent: 0,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PreloginWrapper<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+
+        while this.remaining == 0 {
+            while this.header_read < this.header.len() {
+                let read =
+                    ready!(Pin::new(&mut this.stream)
+                        .poll_read(cx, &mut this.header[this.header_read..]))?;
+                if read == 0 {
+                    return Poll::Ready(Ok(0));
+                }
+                this.header_read += read;
+            }
+            let length = u16::from_be_bytes([this.header[2], this.header[3]]) as usize;
+            this.remaining = length.saturating_sub(this.header.len());
+            this.header_read = 0;
+        }
+
+        let max = buf.len().min(this.remaining);
+        let read = ready!(Pin::new(&mut this.stream).poll_read(cx, &mut buf[..max]))?;
+        this.remaining -= read;
+
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PreloginWrapper<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.pending.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        if this.framed.is_empty() && !this.pending.is_empty() {
+            this.framed = packet(PRELOGIN, &this.pending);
+            this.pending.clear();
+            this.sent = 0;
+        }
+        while this.sent < this.framed.len() {
+            let sent =
+                ready!(Pin::new(&mut this.stream).poll_write(cx, &this.framed[this.sent..]))?;
+            if sent == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.sent += sent;
+        }
+        this.framed.clear();
+
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+/// Does a TLS handshake with the server, as Tiberius does before the login,
+/// and returns the fingerprint of its certificate.
+///
+/// The certificate is validated with the CA bundle, on top of the certificates of the
+/// system, and the server name of the settings. It's not validated at all without
+/// a CA bundle, so the fingerprint of a self-signed certificate can be read.
+pub async fn server_certificate(
+    address: SocketAddr,
+    server_name: &str,
+    settings: &TlsSettings,
+) -> Result<(Fingerprint, Vec<u8>), Box<dyn std::error::Error>> {
+    let mut tcp = TcpStream::connect(address).await?;
+    tcp.write_all(&prelogin_request()).await?;
+
+    let mut header = [0; 8];
+    tcp.read_exact(&mut header).await?;
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let mut payload = vec![0; length.saturating_sub(header.len())];
+    tcp.read_exact(&mut payload).await?;
+
+    if matches!(prelogin_encryption(&payload), Some(ENCRYPT_NOT_SUP) | None) {
+        return Err(TlsError::NotEncrypted {
+            server_name: server_name.to_owned(),
+        }
+        .into());
+    }
+
+    let mut connector = TlsConnector::new();
+    match &settings.ca_bundle {
+        Some(path) => connector = connector.add_root_certificate(read_ca_bundle(path)?),
+        None => {
+            connector = connector
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true)
+        }
+    }
+
+    let tls = connector
+        .connect(server_name, PreloginWrapper::new(tcp.compat()))
+        .await
+        .map_err(|e| TlsError::from_handshake(server_name, e.to_string()))?;
+
+    let der = tls
+        .peer_certificate()
+        .ok()
+        .flatten()
+        .and_then(|certificate| certificate.to_der().ok())
+        .ok_or_else(|| TlsError::Handshake {
+            server_name: server_name.to_owned(),
+            message: "the server sent no certificate".to_owned(),
+        })?;
+
+    Ok((Fingerprint::of(&der), der))
+}